@@ -0,0 +1,39 @@
+use regex::Regex;
+
+pub const DOWNLOAD_ARCHIVE_FILE: &str = "data/.archive";
+
+// yt-dlp download archives list one `<extractor> <id>` pair per line. Jobs
+// created from them use a canonical URL for the id, and URLs added to the
+// queue are canonicalized the same way so either form finds the other.
+pub fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let mut parts = line.split_whitespace();
+    let extractor = parts.next()?;
+    let id = parts.next()?;
+    if parts.next().is_some() || !extractor.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':') {
+        return None;
+    }
+    Some((extractor, id))
+}
+
+pub fn canonical_url(extractor: &str, id: &str) -> Option<String> {
+    match extractor.to_ascii_lowercase().as_str() {
+        "tiktok" if id.chars().all(|c| c.is_ascii_digit()) => Some(format!("https://www.tiktok.com/@/video/{}", id)),
+        "youtube" if id.len() == 11 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') => {
+            Some(format!("https://www.youtube.com/watch?v={}", id))
+        }
+        _ => None,
+    }
+}
+
+pub fn canonical_url_for(url: &str) -> Option<String> {
+    let tiktok = Regex::new(r"^https?://(?:www\.|m\.)?tiktok\.com/(?:@[\w.-]*/video|embed(?:/v2)?)/(\d+)").unwrap();
+    if let Some(caps) = tiktok.captures(url) {
+        return canonical_url("tiktok", &caps[1]);
+    }
+
+    let youtube = Regex::new(r"^https?://(?:(?:www\.|m\.)?youtube\.com/(?:watch\?(?:[^#]*&)?v=|shorts/)|youtu\.be/)([\w-]{11})").unwrap();
+    if let Some(caps) = youtube.captures(url) {
+        return canonical_url("youtube", &caps[1]);
+    }
+    None
+}
@@ -0,0 +1,144 @@
+use crate::error::ApiError;
+use crate::routes::AppState;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// Each scope includes the ones below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(Scope::Read),
+            "write" => Some(Scope::Write),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+}
+
+pub fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+pub fn generate_key() -> String {
+    format!("tiak_{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+// Reading covers everything that changes nothing, including zipping a
+// selection (and deleting the archive again) and WebDAV. Writing covers adding jobs, acting on single jobs
+// and creating share links; deleting, settings, sync, imports and key
+// management are admin. A job's own webhook URL needs admin too, which the
+// add handlers check since it is in the body.
+fn required_scope(method: &Method, path: &str) -> Scope {
+    if path.starts_with("/api/keys") || (path.starts_with("/api/queue/") && path.ends_with("/headers")) {
+        return Scope::Admin;
+    }
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) || path == "/dav" || path.starts_with("/dav/") {
+        return Scope::Read;
+    }
+    if *method == Method::POST && (path == "/api/files/zip" || path == "/api/files/zip/jobs") {
+        return Scope::Read;
+    }
+    if *method == Method::DELETE && path.starts_with("/api/files/zip/jobs/") {
+        return Scope::Read;
+    }
+    let job_action = path.strip_prefix("/api/queue/").is_some_and(|rest| {
+        rest == "add"
+            || rest == "validate"
+            || rest.starts_with("retry/")
+            || rest.starts_with("redownload/")
+            || ["/pause", "/resume", "/star"].iter().any(|action| rest.ends_with(action))
+    });
+    if *method == Method::POST && (job_action || ["/api/files/resolve", "/api/files/formats", "/api/files/share"].contains(&path)) {
+        return Scope::Write;
+    }
+    if *method == Method::PATCH && path.starts_with("/api/queue/") && path.ends_with("/url") {
+        return Scope::Write;
+    }
+    Scope::Admin
+}
+
+// `Authorization: Bearer <key>`, or Basic auth with the key as password for
+// WebDAV clients. GET requests may pass `?apiKey=` instead, for media
+// elements and links that can't set headers.
+fn presented_key(req: &Request) -> Option<String> {
+    if let Some(auth) = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) {
+        if let Some(token) = auth.strip_prefix("Bearer ") {
+            return Some(token.trim().to_string());
+        }
+        if let Some(encoded) = auth.strip_prefix("Basic ") {
+            let decoded = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            return decoded.split_once(':').map(|(_, password)| password.to_string());
+        }
+    }
+    if matches!(*req.method(), Method::GET | Method::HEAD) {
+        return req.uri().query()?.split('&').find_map(|pair| {
+            let value = pair.strip_prefix("apiKey=")?;
+            Some(percent_encoding::percent_decode_str(value).decode_utf8_lossy().into_owned())
+        });
+    }
+    None
+}
+
+// The caller's scope is left in the request extensions for handlers whose
+// output depends on it; without `ADMIN_API_KEY` there is none and everyone
+// counts as admin.
+pub async fn require_key(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let Some(admin_key_hash) = &state.config.admin_api_key_hash else {
+        return next.run(req).await;
+    };
+    let path = req.uri().path();
+    // Shared links are signed instead; see `crate::share`.
+    if path.starts_with("/api/files/shared/") {
+        return next.run(req).await;
+    }
+    let required = required_scope(req.method(), path);
+    let unauthorized = |message: &str| {
+        let mut response = ApiError::unauthorized(message).into_response();
+        if path == "/dav" || path.starts_with("/dav/") {
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Basic realm=\"tiak\""));
+        }
+        response
+    };
+    let Some(key) = presented_key(&req).filter(|k| !k.is_empty()) else {
+        return unauthorized("API key required");
+    };
+    let hash = hash_key(&key);
+    let scope = if &hash == admin_key_hash {
+        Scope::Admin
+    } else {
+        match state.db.api_key_scope(&hash).await {
+            Ok(Some(scope)) => Scope::parse(&scope).unwrap_or(Scope::Read),
+            Ok(None) => return unauthorized("Invalid API key"),
+            Err(e) => return ApiError::database(e).into_response(),
+        }
+    };
+    if scope < required {
+        return ApiError::forbidden(format!("This API key lacks the '{}' scope", required.as_str())).into_response();
+    }
+    req.extensions_mut().insert(scope);
+    next.run(req).await
+}
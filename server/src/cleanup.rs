@@ -1,22 +1,155 @@
-use crate::db::Db;
-use crate::storage::DATA_ROOT;
+use crate::db::{Db, Job};
+use crate::queue::{remove_job_file, DownloadQueue};
+use crate::storage::{DATA_ROOT, FileIndex, available_space, cold_root, data_root_available, date_folder_name, locate_data_file, move_file, sidecar_paths, stored_to_path};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use chrono::{DateTime, Utc};
-use tracing::info;
+use serde::Serialize;
+use tracing::{error, info, warn};
 
-pub async fn run_cleanup(db: &Db) {
+// How long jobs are kept, per status; `None` keeps them forever. Starred
+// jobs are exempt from every policy, and archived ones have no files left.
+#[derive(Clone, Copy, Debug)]
+pub struct RetentionPolicies {
+    // Failed jobs other than cancelled ones, dated by when they were added.
+    pub failed: Option<chrono::Duration>,
+    // Jobs cancelled before finishing, dated by when they were added.
+    pub cancelled: Option<chrono::Duration>,
+    // Jobs whose files are all gone, dated by when they finished.
+    pub missing: Option<chrono::Duration>,
+    // Deletes the files of finished jobs and marks them `archived`, keeping
+    // the row so the URL is still known.
+    pub done_files: Option<chrono::Duration>,
+}
+
+impl Default for RetentionPolicies {
+    fn default() -> Self {
+        Self {
+            failed: Some(chrono::Duration::days(7)),
+            cancelled: Some(chrono::Duration::days(3)),
+            missing: Some(chrono::Duration::days(30)),
+            done_files: None,
+        }
+    }
+}
+
+impl RetentionPolicies {
+    // `failed:7d,missing:30d,done_files:0,cancelled:3d`, where durations are
+    // days (`30d` or `30`) or hours (`12h`) and `0` disables a policy.
+    // Policies that aren't listed keep their default.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut policies = Self::default();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, value) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid retention policy '{}', expected <status>:<duration>", entry))?;
+            let value = value.trim();
+            let (number, hours) = match value.strip_suffix('h') {
+                Some(n) => (n, true),
+                None => (value.strip_suffix('d').unwrap_or(value), false),
+            };
+            let number: i64 = number
+                .parse()
+                .ok()
+                .filter(|n| *n >= 0)
+                .ok_or_else(|| format!("Invalid retention duration '{}', expected e.g. 7d, 12h or 0", value))?;
+            let duration = (number > 0).then(|| if hours { chrono::Duration::hours(number) } else { chrono::Duration::days(number) });
+            match name.trim() {
+                "failed" => policies.failed = duration,
+                "cancelled" => policies.cancelled = duration,
+                "missing" => policies.missing = duration,
+                "done_files" => policies.done_files = duration,
+                other => return Err(format!("Unknown retention policy '{}', expected failed, cancelled, missing or done_files", other)),
+            }
+        }
+        Ok(policies)
+    }
+}
+
+pub async fn run_retention(db: &Db, file_index: &FileIndex, policies: RetentionPolicies) {
     info!("[Cleanup] Starting cleanup task...");
-    
-    let cutoff = Utc::now() - chrono::Duration::days(7);
-    let cutoff_ts = cutoff.timestamp_millis();
-    
-    match db.delete_old_failed_jobs(cutoff_ts).await {
-        Ok(count) => info!("[Cleanup] Deleted {} old failed jobs", count),
-        Err(e) => info!("[Cleanup] Error deleting failed jobs: {}", e),
+    let cutoff = |age: chrono::Duration| (Utc::now() - age).timestamp_millis();
+
+    if let Some(age) = policies.failed {
+        match db.delete_old_failed_jobs(cutoff(age)).await {
+            Ok(count) => info!("[Cleanup] Deleted {} old failed jobs", count),
+            Err(e) => info!("[Cleanup] Error deleting failed jobs: {}", e),
+        }
+    }
+
+    if let Some(age) = policies.cancelled {
+        match db.delete_old_cancelled_jobs(cutoff(age)).await {
+            Ok(count) => info!("[Cleanup] Deleted {} old cancelled jobs", count),
+            Err(e) => info!("[Cleanup] Error deleting cancelled jobs: {}", e),
+        }
+    }
+
+    if let Some(age) = policies.missing {
+        match db.delete_old_missing_jobs(cutoff(age)).await {
+            Ok(count) => info!("[Cleanup] Deleted {} old missing jobs", count),
+            Err(e) => info!("[Cleanup] Error deleting missing jobs: {}", e),
+        }
+    }
+
+    if let Some(age) = policies.done_files {
+        if data_root_available() {
+            archive_old_jobs(db, file_index, cutoff(age)).await;
+        } else {
+            warn!("[Cleanup] Data root '{}' is unavailable, skipping removal of old files", DATA_ROOT);
+        }
+    }
+
+    let uploads = crate::upload::remove_stale().await;
+    if uploads > 0 {
+        info!("[Cleanup] Removed {} abandoned import uploads", uploads);
+    }
+    let zips = crate::zip_job::remove_stale().await;
+    if zips > 0 {
+        info!("[Cleanup] Removed {} expired zip archives", zips);
+    }
+    let sheets = crate::media::remove_stale_contact_sheets().await;
+    if sheets > 0 {
+        info!("[Cleanup] Removed {} old contact sheets", sheets);
     }
 }
 
+async fn archive_old_jobs(db: &Db, file_index: &FileIndex, cutoff: i64) {
+    let jobs = match db.get_jobs_for_missing_scan().await {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            error!("[Cleanup] Failed to load finished jobs: {}", e);
+            return;
+        }
+    };
+    let mut archived = 0;
+    for job in jobs.iter().filter(|job| !job.starred && job.completed_at.unwrap_or(job.created_at) < cutoff) {
+        // Rows from an archive import have no files; archiving them would
+        // only hide them from the already-downloaded check.
+        let files = job.all_files();
+        if files.is_empty() {
+            continue;
+        }
+        let folder = job_folder(job);
+        let mut removed = true;
+        for name in files {
+            if let Err(e) = remove_job_file(file_index, &folder, &name).await {
+                error!("[Cleanup] Failed to delete {} of job {}: {}", name, job.id, e);
+                removed = false;
+                break;
+            }
+        }
+        if removed && db.mark_archived(&job.id).await.is_ok() {
+            archived += 1;
+        }
+    }
+    info!("[Cleanup] Deleted the files of {} old finished jobs", archived);
+}
+
 pub async fn scan_for_missing_files(db: &Db) {
+    if !data_root_available() {
+        warn!("[Cleanup] Data root '{}' is unavailable, skipping missing file scan", DATA_ROOT);
+        return;
+    }
     info!("[Cleanup] Scanning for missing files...");
     
     let jobs = match db.get_jobs_for_missing_scan().await {
@@ -27,19 +160,15 @@ pub async fn scan_for_missing_files(db: &Db) {
     let mut missing_count = 0;
     
     for job in jobs {
-        if let Some(filename) = &job.filename {
-            let ts = job.completed_at.or(Some(job.created_at)).unwrap();
-            
-            let date = DateTime::<Utc>::from_timestamp_millis(ts).unwrap_or(Utc::now());
-            let folder_name = date.format("%Y-%m-%d").to_string();
-            
-            let path = Path::new(DATA_ROOT).join(folder_name).join(filename);
-            
-            if !path.exists() {
-                if let Ok(_) = db.mark_missing(&job.id).await {
-                    missing_count += 1;
-                }
-            }
+        let files = job.all_files();
+        if files.is_empty() {
+            continue;
+        }
+        let folder = job_folder(&job);
+
+        // A multi-file job only counts as missing once none of its files remain.
+        if !files.iter().any(|name| locate_data_file(Path::new(&folder), name).is_some()) && db.mark_missing(&job.id).await.is_ok() {
+            missing_count += 1;
         }
     }
     
@@ -48,4 +177,188 @@ pub async fn scan_for_missing_files(db: &Db) {
     } else {
         info!("[Cleanup] No missing files found");
     }
-}
\ No newline at end of file
+}
+
+// Moves indexed files older than `after_days` from the primary data root to
+// the cold root, keeping their `data/...` path, so jobs and links are
+// unaffected. The modification time is set to the original creation time,
+// which is what the index dates cold files by.
+pub async fn tier_old_files(file_index: &FileIndex, after_days: u64) {
+    let Some(cold) = cold_root() else { return };
+    if !data_root_available() || !cold.is_dir() {
+        warn!("[Tiering] A data root is unavailable, skipping");
+        return;
+    }
+    let cutoff = Utc::now() - chrono::Duration::days(after_days as i64);
+    let mut moved = 0;
+    for file in file_index.all_files().into_iter().filter(|f| !f.cold && f.created_at < cutoff) {
+        let src = stored_to_path(&file.path);
+        let Ok(relative) = src.strip_prefix(DATA_ROOT) else { continue };
+        let dst = cold.join(relative);
+        if dst.exists() {
+            warn!("[Tiering] {} already exists on the cold root, leaving it", file.path);
+            continue;
+        }
+        if let Err(e) = move_file(&src, &dst).await {
+            error!("[Tiering] Failed to move {}: {}", file.path, e);
+            continue;
+        }
+        let mtime: std::time::SystemTime = file.created_at.into();
+        if let Err(e) = std::fs::File::options().write(true).open(&dst).and_then(|f| f.set_modified(mtime)) {
+            warn!("[Tiering] Failed to keep the date of {}: {}", file.path, e);
+        }
+        for (from, to) in sidecar_paths(&src).into_iter().zip(sidecar_paths(&dst)) {
+            if from.exists() {
+                let _ = move_file(&from, &to).await;
+            }
+        }
+        file_index.mark_cold(&file.path);
+        moved += 1;
+    }
+    if moved > 0 {
+        info!("[Tiering] Moved {} files to the cold data root", moved);
+    }
+}
+
+// Warns once when free space drops below the configured threshold and once
+// when it recovers. Returns whether the disk is currently low.
+pub async fn check_disk_space(queue: &DownloadQueue, was_low: bool) -> bool {
+    let threshold = queue.get_low_disk_threshold_bytes().await;
+    let free = match available_space() {
+        Ok(free) => free,
+        Err(_) => return was_low,
+    };
+    let low = threshold > 0 && free < threshold;
+    if low && !was_low {
+        warn!("[Disk] Low disk space: {} bytes free, threshold is {} bytes; queued downloads are held until it recovers", free, threshold);
+    } else if !low && was_low {
+        info!("[Disk] Free disk space recovered: {} bytes free", free);
+    }
+    low
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct DuplicateMapping {
+    pub path: String,
+    #[serde(rename = "jobIds")]
+    pub job_ids: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, utoipa::ToSchema)]
+pub struct ReconcileReport {
+    #[serde(rename = "orphanFiles")]
+    pub orphan_files: Vec<String>,
+    #[serde(rename = "orphanJobs")]
+    pub orphan_jobs: Vec<String>,
+    pub duplicates: Vec<DuplicateMapping>,
+    pub fixed: bool,
+    #[serde(rename = "jobsMarkedMissing")]
+    pub jobs_marked_missing: usize,
+    #[serde(rename = "jobsDeleted")]
+    pub jobs_deleted: usize,
+}
+
+impl ReconcileReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphan_files.is_empty() && self.orphan_jobs.is_empty() && self.duplicates.is_empty()
+    }
+}
+
+pub fn job_folder(job: &Job) -> String {
+    if let Some(subfolder) = &job.subfolder {
+        return subfolder.clone();
+    }
+    let ts = job.completed_at.unwrap_or(job.created_at);
+    date_folder_name(DateTime::<Utc>::from_timestamp_millis(ts).unwrap_or(Utc::now()))
+}
+
+// Jobs only store file names, and moves keep the name but not the folder, so
+// ownership is matched by name. Duplicates are keyed by folder and name since
+// the same title downloaded on two days is two distinct files.
+// With `fix`, orphan jobs are marked missing and for each duplicate only the
+// most recently completed job is kept, along with any starred ones. Orphan
+// files are only reported.
+pub async fn reconcile(db: &Db, file_index: &FileIndex, fix: bool) -> anyhow::Result<ReconcileReport> {
+    let jobs = db.get_jobs_for_missing_scan().await?;
+    let files = file_index.all_files();
+    let indexed_names: HashSet<&str> = files.iter().map(|f| f.name.as_str()).collect();
+
+    let mut report = ReconcileReport { fixed: fix, ..Default::default() };
+    let mut owned_names = HashSet::new();
+    let mut owners: HashMap<String, Vec<&Job>> = HashMap::new();
+
+    for job in &jobs {
+        let names = job.all_files();
+        if names.is_empty() {
+            continue;
+        }
+        if !names.iter().any(|name| indexed_names.contains(name.as_str())) {
+            report.orphan_jobs.push(job.id.clone());
+        }
+        let folder = job_folder(job);
+        for name in names {
+            owners.entry(format!("{}/{}/{}", DATA_ROOT, folder, name)).or_default().push(job);
+            owned_names.insert(name);
+        }
+    }
+
+    report.orphan_files = files.iter()
+        .filter(|f| !f.name.starts_with('.') && !owned_names.contains(&f.name))
+        .map(|f| f.path.clone())
+        .collect();
+    report.orphan_files.sort();
+
+    for (path, mut claimants) in owners {
+        if claimants.len() < 2 {
+            continue;
+        }
+        claimants.sort_by_key(|job| std::cmp::Reverse(job.completed_at.unwrap_or(job.created_at)));
+        report.duplicates.push(DuplicateMapping {
+            path,
+            job_ids: claimants.iter().map(|job| job.id.clone()).collect(),
+        });
+    }
+    report.duplicates.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if fix {
+        for id in &report.orphan_jobs {
+            if db.mark_missing(id).await.is_ok() {
+                report.jobs_marked_missing += 1;
+            }
+        }
+        // A multi-file job kept for one path must survive losing another,
+        // and starred jobs are never removed.
+        let mut kept: HashSet<&String> = report.duplicates.iter().filter_map(|d| d.job_ids.first()).collect();
+        kept.extend(jobs.iter().filter(|job| job.starred).map(|job| &job.id));
+        let stale: HashSet<&String> = report.duplicates.iter()
+            .flat_map(|d| d.job_ids.iter().skip(1))
+            .filter(|id| !kept.contains(id))
+            .collect();
+        for id in stale {
+            if db.delete_job(id).await.is_ok() {
+                report.jobs_deleted += 1;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn archiving_keeps_imported_jobs_without_files() {
+        let dir = std::env::temp_dir().join(format!("tiak-test-{}", uuid::Uuid::new_v4()));
+        let db = Db::new(dir.join("tiak.db").to_str().unwrap()).await.unwrap();
+        db.add_archived_job("https://a.test/video/1").await.unwrap();
+
+        archive_old_jobs(&db, &FileIndex::new(), Utc::now().timestamp_millis() + 1000).await;
+
+        let job = db.find_done_job_by_url("https://a.test/video/1").await.unwrap();
+        assert!(job.is_some_and(|job| job.status == "done"));
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
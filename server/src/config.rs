@@ -1,10 +1,65 @@
+use chrono_tz::Tz;
+use crate::cleanup::RetentionPolicies;
+use crate::domains::DomainFilter;
+use crate::maintenance::MaintenanceWindow;
+use crate::storage::LibraryMode;
 use std::env;
 
 #[derive(Clone)]
 pub struct Config {
     pub db_path: String,
     pub server_port: u16,
+    pub listen_socket: Option<String>,
     pub allowed_origins: Vec<String>,
+    pub max_page_size: i64,
+    pub timezone: Option<Tz>,
+    pub compression: bool,
+    pub read_only: bool,
+    pub enable_sync: bool,
+    pub enable_resolve: bool,
+    pub enable_import: bool,
+    pub reconcile_interval_hours: u64,
+    pub event_log_path: Option<String>,
+    pub library_mode: LibraryMode,
+    pub request_timeout_seconds: u64,
+    pub max_concurrent_requests: usize,
+    pub walk_concurrency: usize,
+    pub index_threads: usize,
+    pub cold_data_root: Option<String>,
+    pub tier_after_days: u64,
+    pub maintenance_window: Option<MaintenanceWindow>,
+    pub seed_urls_file: Option<String>,
+    pub budget_bytes: u64,
+    pub budget_period_days: u64,
+    pub domain_filter: DomainFilter,
+    pub retention: RetentionPolicies,
+    // SHA-256 of `ADMIN_API_KEY`; API keys are only required when it is set.
+    pub admin_api_key_hash: Option<String>,
+    // Only used by retries that ask for it, see `POST /api/queue/retry/:id`.
+    pub proxy_url: Option<String>,
+    // Signs `POST /api/files/share` links. Without `SHARE_SECRET` a random
+    // one is used, and links stop working on restart.
+    pub share_secret: Vec<u8>,
+}
+
+fn env_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    match env::var(name) {
+        Ok(s) => match s.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "on" => true,
+            "0" | "false" | "off" => false,
+            _ => default,
+        },
+        Err(_) => default,
+    }
 }
 
 impl Config {
@@ -16,6 +71,16 @@ impl Config {
             .parse()
             .expect("SERVER_PORT must be a number");
 
+        let listen_socket = env::var("LISTEN_SOCKET")
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty());
+
+        let event_log_path = env::var("EVENT_LOG_PATH")
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty());
+
         let allowed_origins_str = env::var("ALLOWED_ORIGINS").unwrap_or_else(|_| "".to_string());
         let allowed_origins = allowed_origins_str
             .split(',')
@@ -23,10 +88,109 @@ impl Config {
             .filter(|s| !s.is_empty())
             .collect();
 
+        let max_page_size = env::var("MAX_PAGE_SIZE")
+            .unwrap_or_else(|_| "200".to_string())
+            .parse::<i64>()
+            .expect("MAX_PAGE_SIZE must be a number")
+            .max(1);
+
+        let timezone = env::var("TIMEZONE")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.trim().parse::<Tz>().expect("TIMEZONE must be an IANA timezone name, e.g. Europe/Berlin"));
+
+        let compression = env_flag("COMPRESSION", true);
+        let read_only = env_flag("READ_ONLY", false);
+
         Config {
             db_path,
             server_port,
+            listen_socket,
+            event_log_path,
             allowed_origins,
+            max_page_size,
+            timezone,
+            compression,
+            read_only,
+            enable_sync: env_flag("ENABLE_SYNC", true),
+            enable_resolve: env_flag("ENABLE_RESOLVE", true),
+            enable_import: env_flag("ENABLE_IMPORT", true),
+            reconcile_interval_hours: env::var("RECONCILE_INTERVAL_HOURS")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| s.trim().parse().expect("RECONCILE_INTERVAL_HOURS must be a number"))
+                .unwrap_or(0),
+            library_mode: env::var("LIBRARY_MODE")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| s.trim().parse().unwrap_or_else(|e: String| panic!("LIBRARY_MODE: {}", e)))
+                .unwrap_or(LibraryMode::Date),
+            request_timeout_seconds: env::var("REQUEST_TIMEOUT_SECONDS")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| s.trim().parse().expect("REQUEST_TIMEOUT_SECONDS must be a number"))
+                .unwrap_or(60),
+            max_concurrent_requests: env::var("MAX_CONCURRENT_REQUESTS")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| s.trim().parse().expect("MAX_CONCURRENT_REQUESTS must be a number"))
+                .unwrap_or(512),
+            walk_concurrency: env::var("WALK_CONCURRENCY")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| s.trim().parse().expect("WALK_CONCURRENCY must be a number"))
+                .unwrap_or(1),
+            index_threads: env::var("INDEX_THREADS")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| s.trim().parse().expect("INDEX_THREADS must be a number"))
+                .unwrap_or(1),
+            cold_data_root: env::var("COLD_DATA_ROOT")
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            tier_after_days: env::var("TIER_AFTER_DAYS")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| s.trim().parse().expect("TIER_AFTER_DAYS must be a number"))
+                .unwrap_or(0),
+            maintenance_window: env::var("MAINTENANCE_WINDOW")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| MaintenanceWindow::parse(s.trim(), timezone).unwrap_or_else(|e| panic!("MAINTENANCE_WINDOW: {}", e))),
+            seed_urls_file: env::var("SEED_URLS_FILE")
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            budget_bytes: env::var("BUDGET_BYTES")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| s.trim().parse().expect("BUDGET_BYTES must be a number"))
+                .unwrap_or(0),
+            budget_period_days: env::var("BUDGET_PERIOD_DAYS")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| s.trim().parse().expect("BUDGET_PERIOD_DAYS must be a number"))
+                .unwrap_or(30),
+            domain_filter: DomainFilter::new(env_list("ALLOWED_DOMAINS"), env_list("BLOCKED_DOMAINS")),
+            retention: env::var("RETENTION")
+                .map(|s| RetentionPolicies::parse(&s).unwrap_or_else(|e| panic!("RETENTION: {}", e)))
+                .unwrap_or_default(),
+            admin_api_key_hash: env::var("ADMIN_API_KEY")
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .map(|key| crate::auth::hash_key(&key)),
+            proxy_url: env::var("PROXY_URL")
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            share_secret: env::var("SHARE_SECRET")
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(crate::auth::generate_key)
+                .into_bytes(),
         }
     }
 }
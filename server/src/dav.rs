@@ -0,0 +1,160 @@
+use axum::{
+    body::Body,
+    extract::{Path, Request},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use crate::routes::{content_type_for, serve_file};
+use crate::storage::DATA_ROOT;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use std::fs::Metadata;
+use std::path::{Path as StdPath, PathBuf};
+
+const DAV_PREFIX: &str = "/dav";
+const ALLOWED_METHODS: &str = "OPTIONS, GET, HEAD, PROPFIND";
+
+// Characters that must be escaped inside a single href path segment.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'<').add(b'>')
+    .add(b'?').add(b'`').add(b'{').add(b'}').add(b'&').add(b'\'');
+
+pub async fn dav_root(req: Request) -> Response {
+    handle(String::new(), req).await
+}
+
+pub async fn dav_path(Path(path): Path<String>, req: Request) -> Response {
+    handle(path, req).await
+}
+
+async fn handle(path: String, req: Request) -> Response {
+    let data_root = StdPath::new(DATA_ROOT).canonicalize().unwrap_or_else(|_| PathBuf::from(DATA_ROOT));
+    let target = match resolve(&data_root, &path) {
+        Some(p) => p,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match *req.method() {
+        Method::OPTIONS => {
+            let mut response = StatusCode::OK.into_response();
+            let headers = response.headers_mut();
+            headers.insert("DAV", HeaderValue::from_static("1"));
+            headers.insert(header::ALLOW, HeaderValue::from_static(ALLOWED_METHODS));
+            response
+        }
+        Method::GET | Method::HEAD => {
+            if target.is_dir() {
+                return method_not_allowed();
+            }
+            let response = serve_file(&target, req.headers()).await;
+            if req.method() == Method::HEAD {
+                let (parts, _) = response.into_parts();
+                return Response::from_parts(parts, Body::empty());
+            }
+            response
+        }
+        ref m if m.as_str() == "PROPFIND" => propfind(&data_root, &target, req.headers()).await,
+        _ => method_not_allowed(),
+    }
+}
+
+fn method_not_allowed() -> Response {
+    let mut response = StatusCode::METHOD_NOT_ALLOWED.into_response();
+    response.headers_mut().insert(header::ALLOW, HeaderValue::from_static(ALLOWED_METHODS));
+    response
+}
+
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.') || name.contains("jobs.sqlite")
+}
+
+fn resolve(data_root: &StdPath, path: &str) -> Option<PathBuf> {
+    let path = path.trim_matches('/');
+    if path.split('/').any(is_hidden) {
+        return None;
+    }
+    let abs = data_root.join(path).canonicalize().ok()?;
+    if !abs.starts_with(data_root) {
+        return None;
+    }
+    Some(abs)
+}
+
+async fn propfind(data_root: &StdPath, target: &StdPath, headers: &HeaderMap) -> Response {
+    let depth = headers.get("Depth").and_then(|v| v.to_str().ok()).unwrap_or("1");
+    let target = target.to_path_buf();
+    let root = data_root.to_path_buf();
+    let list_children = depth != "0";
+
+    let body = tokio::task::spawn_blocking(move || -> std::io::Result<String> {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">"#);
+        xml.push_str(&entry(&root, &target, &std::fs::metadata(&target)?));
+
+        if list_children && target.is_dir() {
+            let mut children: Vec<_> = std::fs::read_dir(&target)?.filter_map(|e| e.ok()).collect();
+            children.sort_by_key(|e| e.file_name());
+            for child in children {
+                if is_hidden(&child.file_name().to_string_lossy()) {
+                    continue;
+                }
+                if let Ok(meta) = child.metadata() {
+                    xml.push_str(&entry(&root, &child.path(), &meta));
+                }
+            }
+        }
+
+        xml.push_str("</D:multistatus>");
+        Ok(xml)
+    }).await;
+
+    match body {
+        Ok(Ok(xml)) => {
+            let mut response = Response::new(Body::from(xml));
+            *response.status_mut() = StatusCode::MULTI_STATUS;
+            response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static("application/xml; charset=utf-8"));
+            response
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+fn entry(data_root: &StdPath, path: &StdPath, meta: &Metadata) -> String {
+    let relative = path.strip_prefix(data_root).unwrap_or(path);
+    let mut href = String::from(DAV_PREFIX);
+    for segment in relative.iter() {
+        href.push('/');
+        href.push_str(&utf8_percent_encode(&segment.to_string_lossy(), SEGMENT).to_string());
+    }
+    if meta.is_dir() {
+        href.push('/');
+    }
+
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let modified = meta.modified().unwrap_or(std::time::SystemTime::UNIX_EPOCH);
+    let last_modified = chrono::DateTime::<chrono::Utc>::from(modified).format("%a, %d %b %Y %H:%M:%S GMT");
+
+    let props = if meta.is_dir() {
+        "<D:resourcetype><D:collection/></D:resourcetype>".to_string()
+    } else {
+        format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype>",
+            meta.len(),
+            content_type_for(path)
+        )
+    };
+
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>{}<D:getlastmodified>{}</D:getlastmodified></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        escape_xml(&href),
+        escape_xml(&name),
+        props,
+        last_modified
+    )
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -1,10 +1,16 @@
-use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite, Row};
+use sqlx::{sqlite::SqlitePoolOptions, types::Json, Pool, Sqlite};
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
+use crate::events::{EventLog, JobUpdate};
+use crate::media::MediaInfo;
 use anyhow::Result;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
+#[serde(remote = "Self")]
 pub struct Job {
     pub id: String,
     pub url: String,
@@ -20,13 +26,166 @@ pub struct Job {
     pub completed_at: Option<i64>,
     pub retries: i64,
     pub error: Option<String>,
+    #[sqlx(rename = "errorKind")]
+    pub error_kind: Option<String>,
+    // May carry cookies, so it is left out of job JSON; admins read it
+    // from `GET /api/queue/:id/headers` and the export.
+    #[serde(skip_serializing)]
+    #[schema(value_type = Option<HashMap<String, String>>, write_only)]
+    pub headers: Option<Json<HashMap<String, String>>>,
+    // Every file the job produced, for posts that yield more than one.
+    // `filename` stays the primary file.
+    #[schema(value_type = Option<Vec<String>>)]
+    pub files: Option<Json<Vec<String>>>,
+    #[sqlx(rename = "outputContainer")]
+    pub output_container: Option<String>,
+    #[serde(default)]
+    pub starred: bool,
+    #[sqlx(rename = "redownloadedFrom")]
+    pub redownloaded_from: Option<String>,
+    // Folder under the data root used instead of the date folder.
+    pub subfolder: Option<String>,
+    // yt-dlp `-f` selector replacing the default.
+    pub format: Option<String>,
+    #[sqlx(rename = "audioOnly")]
+    #[serde(default)]
+    pub audio_only: bool,
+    // Codec extracted audio is converted to; `original` (or `null`, on jobs
+    // added before it defaulted to mp3) keeps the source's.
+    #[sqlx(rename = "audioFormat")]
+    pub audio_format: Option<String>,
+    // Higher runs first; equal priorities keep their queue order.
+    #[serde(default)]
+    pub priority: i64,
+    // Filled in by ffprobe after the download when `probeMedia` is on.
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    #[sqlx(rename = "videoCodec")]
+    pub video_codec: Option<String>,
+    // A job added with a future `start_after` is `scheduled` until then.
+    #[sqlx(rename = "startAfter")]
+    pub start_after: Option<i64>,
+    // Replaces the `webhookUrl` setting for this job. Often carries a token,
+    // so like `headers` it is only shown to admins.
+    #[sqlx(rename = "webhookUrl")]
+    #[serde(skip_serializing)]
+    #[schema(write_only)]
+    pub webhook_url: Option<String>,
+    // Replaces the `maxHeight` setting for this job; `0` lifts the cap.
+    #[sqlx(rename = "maxHeight")]
+    pub max_height: Option<i64>,
+    // Whether ffprobe could read every file of the finished job; `null`
+    // until it is checked, see `verifyBeforeSync`.
+    pub verified: Option<bool>,
+}
+
+// Per-job overrides chosen when the job is added.
+#[derive(Debug, Clone, Default)]
+pub struct JobOptions {
+    pub headers: Option<HashMap<String, String>>,
+    pub output_container: Option<String>,
+    pub redownloaded_from: Option<String>,
+    pub subfolder: Option<String>,
+    pub format: Option<String>,
+    pub audio_only: bool,
+    pub audio_format: Option<String>,
+    pub priority: i64,
+    pub start_after: Option<i64>,
+    pub webhook_url: Option<String>,
+    pub max_height: Option<i64>,
+}
+
+impl Job {
+    pub fn all_files(&self) -> Vec<String> {
+        match (&self.files, &self.filename) {
+            (Some(files), _) if !files.is_empty() => files.0.clone(),
+            (_, Some(name)) => vec![name.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn queue_wait_ms(&self) -> Option<i64> {
+        self.started_at.map(|started| (started - self.created_at).max(0))
+    }
+
+    pub fn download_duration_ms(&self) -> Option<i64> {
+        match (self.started_at, self.completed_at) {
+            // A redownload in progress still has the last run's `completedAt`.
+            (Some(started), Some(completed)) if completed >= started => Some(completed - started),
+            _ => None,
+        }
+    }
+}
+
+// Jobs are serialized with derived timing fields appended, so every response
+// carrying a job exposes them without clients recomputing from timestamps.
+impl Serialize for Job {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Fields<'a>(&'a Job);
+
+        impl Serialize for Fields<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                Job::serialize(self.0, serializer)
+            }
+        }
+
+        #[derive(Serialize)]
+        struct WithTimings<'a> {
+            #[serde(flatten)]
+            job: Fields<'a>,
+            #[serde(rename = "queueWaitMs")]
+            queue_wait_ms: Option<i64>,
+            #[serde(rename = "downloadDurationMs")]
+            download_duration_ms: Option<i64>,
+        }
+
+        WithTimings {
+            job: Fields(self),
+            queue_wait_ms: self.queue_wait_ms(),
+            download_duration_ms: self.download_duration_ms(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Job {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Job::deserialize(deserializer)
+    }
+}
+
+// Only a hash of the key is stored; `key_prefix` helps tell keys apart.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scope: String,
+    #[serde(rename = "keyPrefix")]
+    #[sqlx(rename = "keyPrefix")]
+    pub key_prefix: String,
+    #[serde(rename = "createdAt")]
+    #[sqlx(rename = "createdAt")]
+    pub created_at: i64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct JobMetrics {
+    pub done: i64,
+    pub failed: i64,
+    pub cancelled: i64,
+    pub avg_download_ms: Option<f64>,
 }
 
 #[derive(Clone)]
 pub struct Db {
     pool: Pool<Sqlite>,
+    events: Option<Arc<EventLog>>,
+    updates: broadcast::Sender<JobUpdate>,
 }
 
+// Subscribers that fall further behind than this skip ahead.
+const JOB_UPDATES_CAPACITY: usize = 256;
+
 impl Db {
     pub async fn new(db_path: &str) -> Result<Self> {
         let path = Path::new(db_path);
@@ -65,21 +224,122 @@ impl Db {
 
             CREATE INDEX IF NOT EXISTS idx_jobs_createdAt ON jobs(createdAt);
             CREATE INDEX IF NOT EXISTS idx_jobs_status ON jobs(status);
+
+            CREATE TABLE IF NOT EXISTS download_budget (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                periodStart INTEGER NOT NULL,
+                bytes INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS api_keys (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                scope TEXT NOT NULL,
+                keyHash TEXT NOT NULL UNIQUE,
+                keyPrefix TEXT NOT NULL,
+                createdAt INTEGER NOT NULL
+            );
             "#
         )
         .execute(&pool)
         .await?;
 
-        Ok(Self { pool })
+        Self::add_column_if_missing(&pool, "errorKind", "TEXT").await?;
+        Self::add_column_if_missing(&pool, "headers", "TEXT").await?;
+        Self::add_column_if_missing(&pool, "files", "TEXT").await?;
+        Self::add_column_if_missing(&pool, "outputContainer", "TEXT").await?;
+        Self::add_column_if_missing(&pool, "starred", "INTEGER NOT NULL DEFAULT 0").await?;
+        Self::add_column_if_missing(&pool, "redownloadedFrom", "TEXT").await?;
+        Self::add_column_if_missing(&pool, "subfolder", "TEXT").await?;
+        Self::add_column_if_missing(&pool, "format", "TEXT").await?;
+        Self::add_column_if_missing(&pool, "audioOnly", "INTEGER NOT NULL DEFAULT 0").await?;
+        Self::add_column_if_missing(&pool, "priority", "INTEGER NOT NULL DEFAULT 0").await?;
+        Self::add_column_if_missing(&pool, "width", "INTEGER").await?;
+        Self::add_column_if_missing(&pool, "height", "INTEGER").await?;
+        Self::add_column_if_missing(&pool, "videoCodec", "TEXT").await?;
+        Self::add_column_if_missing(&pool, "startAfter", "INTEGER").await?;
+        Self::add_column_if_missing(&pool, "webhookUrl", "TEXT").await?;
+        Self::add_column_if_missing(&pool, "maxHeight", "INTEGER").await?;
+        // When the job last entered the queue; restores the queue order
+        // (retries and released schedules go to the back) after a restart.
+        Self::add_column_if_missing(&pool, "queuedAt", "INTEGER").await?;
+        Self::add_column_if_missing(&pool, "verified", "INTEGER").await?;
+        Self::add_column_if_missing(&pool, "audioFormat", "TEXT").await?;
+        // Insertion order, the tiebreak wherever jobs with equal timestamps
+        // are ordered. Older rows are numbered in rowid order.
+        Self::add_column_if_missing(&pool, "seq", "INTEGER").await?;
+        sqlx::query("UPDATE jobs SET seq = rowid WHERE seq IS NULL").execute(&pool).await?;
+        sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_jobs_seq ON jobs(seq)").execute(&pool).await?;
+
+        Ok(Self { pool, events: None, updates: broadcast::channel(JOB_UPDATES_CAPACITY).0 })
+    }
+
+    // Every status change is then appended to the log.
+    pub fn with_event_log(mut self, events: EventLog) -> Self {
+        self.events = Some(Arc::new(events));
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JobUpdate> {
+        self.updates.subscribe()
+    }
+
+    async fn publish(&self, kind: &'static str, job: &Job) {
+        // The event log only records status changes.
+        if let Some(events) = &self.events {
+            if kind == "status" {
+                events.record(job).await;
+            }
+        }
+        if self.updates.receiver_count() > 0 {
+            let _ = self.updates.send(JobUpdate { kind, job: job.clone() });
+        }
+    }
+
+    async fn record_event(&self, id: &str) {
+        if self.events.is_none() && self.updates.receiver_count() == 0 {
+            return;
+        }
+        if let Ok(Some(job)) = self.get_job(id).await {
+            self.publish("status", &job).await;
+        }
     }
 
-    pub async fn add_job(&self, url: String) -> Result<Job> {
+    // CREATE TABLE IF NOT EXISTS leaves databases from older versions alone,
+    // so columns added later are migrated in here.
+    async fn add_column_if_missing(pool: &Pool<Sqlite>, column: &str, definition: &str) -> Result<()> {
+        let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info('jobs') WHERE name = ?")
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+        if exists == 0 {
+            sqlx::query(&format!("ALTER TABLE jobs ADD COLUMN {} {}", column, definition))
+                .execute(pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub fn check_writable(db_path: &str) -> Result<()> {
+        for path in [db_path.to_string(), format!("{}-wal", db_path), format!("{}-shm", db_path)] {
+            if !Path::new(&path).exists() {
+                continue;
+            }
+            std::fs::OpenOptions::new().write(true).open(&path).map_err(|e| {
+                anyhow::anyhow!("Database file '{}' is not writable: {}. It is probably owned by another user (e.g. root from an earlier run); fix its ownership or start the server as the owning user.", path, e)
+            })?;
+        }
+        Ok(())
+    }
+
+    pub async fn add_job(&self, url: String, options: JobOptions) -> Result<Job> {
         let id = Uuid::new_v4().to_string();
         let created_at = chrono::Utc::now().timestamp_millis();
+        let scheduled = options.start_after.is_some_and(|at| at > created_at);
         let job = Job {
             id: id.clone(),
             url: url.clone(),
-            status: "queued".to_string(),
+            status: if scheduled { "scheduled" } else { "queued" }.to_string(),
             progress: 0,
             eta: None,
             filename: None,
@@ -88,17 +348,49 @@ impl Db {
             completed_at: None,
             retries: 0,
             error: None,
+            error_kind: None,
+            headers: options.headers.map(Json),
+            files: None,
+            output_container: options.output_container,
+            starred: false,
+            redownloaded_from: options.redownloaded_from,
+            subfolder: options.subfolder,
+            format: options.format,
+            audio_only: options.audio_only,
+            audio_format: options.audio_format,
+            priority: options.priority,
+            width: None,
+            height: None,
+            video_codec: None,
+            start_after: options.start_after,
+            webhook_url: options.webhook_url,
+            max_height: options.max_height,
+            verified: None,
         };
 
         sqlx::query(
-            "INSERT INTO jobs (id, url, status, createdAt) VALUES (?, ?, 'queued', ?)"
+            "INSERT INTO jobs (id, url, status, createdAt, headers, outputContainer, redownloadedFrom, subfolder, format, audioOnly, audioFormat, priority, startAfter, webhookUrl, maxHeight, queuedAt, seq) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, (SELECT COALESCE(MAX(seq), 0) + 1 FROM jobs))"
         )
         .bind(&job.id)
         .bind(&job.url)
+        .bind(&job.status)
         .bind(job.created_at)
+        .bind(&job.headers)
+        .bind(&job.output_container)
+        .bind(&job.redownloaded_from)
+        .bind(&job.subfolder)
+        .bind(&job.format)
+        .bind(job.audio_only)
+        .bind(&job.audio_format)
+        .bind(job.priority)
+        .bind(job.start_after)
+        .bind(&job.webhook_url)
+        .bind(job.max_height)
+        .bind((!scheduled).then_some(created_at))
         .execute(&self.pool)
         .await?;
 
+        self.publish("status", &job).await;
         Ok(job)
     }
 
@@ -110,30 +402,44 @@ impl Db {
         Ok(job)
     }
 
+    // In the order the jobs entered the queue; `enqueue` then sorts them
+    // by priority, which keeps that order within each priority.
     pub async fn get_queued_jobs(&self) -> Result<Vec<Job>> {
-        let jobs = sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE status = 'queued' ORDER BY createdAt ASC")
+        let jobs = sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE status = 'queued' ORDER BY COALESCE(queuedAt, createdAt) ASC, seq ASC")
             .fetch_all(&self.pool)
             .await?;
         Ok(jobs)
     }
     
-    pub async fn get_active_jobs(&self) -> Result<Vec<Job>> {
-        let jobs = sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE status = 'downloading' ORDER BY createdAt ASC")
+    pub async fn get_all_jobs(&self) -> Result<Vec<Job>> {
+         let jobs = sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE status IN ('scheduled', 'queued', 'downloading', 'paused', 'failed') ORDER BY createdAt ASC, seq ASC")
             .fetch_all(&self.pool)
             .await?;
         Ok(jobs)
     }
 
-    pub async fn get_all_jobs(&self) -> Result<Vec<Job>> {
-         let jobs = sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE status IN ('queued', 'downloading', 'failed') ORDER BY createdAt ASC")
+    pub async fn next_scheduled_at(&self) -> Result<Option<i64>> {
+        let at = sqlx::query_scalar("SELECT MIN(startAfter) FROM jobs WHERE status = 'scheduled'")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(at)
+    }
+
+    // Moves scheduled jobs whose time has come to `queued` and returns them.
+    pub async fn release_scheduled_jobs(&self, now: i64) -> Result<Vec<Job>> {
+        let jobs = sqlx::query_as::<_, Job>("UPDATE jobs SET status = 'queued', queuedAt = ?1 WHERE status = 'scheduled' AND startAfter <= ?1 RETURNING *")
+            .bind(now)
             .fetch_all(&self.pool)
             .await?;
+        for job in &jobs {
+            self.publish("status", job).await;
+        }
         Ok(jobs)
     }
 
     pub async fn has_active_job(&self, url: &str) -> Result<bool> {
         let count: i64 = sqlx::query_scalar(
-            "SELECT count(*) FROM jobs WHERE url = ? AND status IN ('queued', 'downloading')"
+            "SELECT count(*) FROM jobs WHERE url = ? AND status IN ('scheduled', 'queued', 'downloading', 'paused')"
         )
         .bind(url)
         .fetch_one(&self.pool)
@@ -141,6 +447,85 @@ impl Db {
         Ok(count > 0)
     }
 
+    // Bytes downloaded in the current budget period and when it started.
+    // Periods follow each other back to back from the first one, so they
+    // keep their boundaries across restarts.
+    pub async fn budget_usage(&self, now: i64, period_ms: i64) -> Result<(i64, i64)> {
+        sqlx::query("INSERT OR IGNORE INTO download_budget (id, periodStart, bytes) VALUES (1, ?, 0)")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+        let usage = sqlx::query_as(
+            r#"
+            UPDATE download_budget SET
+                periodStart = CASE WHEN periodStart + ?2 <= ?1 THEN periodStart + (?1 - periodStart) / ?2 * ?2 ELSE periodStart END,
+                bytes = CASE WHEN periodStart + ?2 <= ?1 THEN 0 ELSE bytes END
+            WHERE id = 1
+            RETURNING periodStart, bytes
+            "#
+        )
+            .bind(now)
+            .bind(period_ms)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(usage)
+    }
+
+    pub async fn add_budget_bytes(&self, bytes: i64, now: i64, period_ms: i64) -> Result<()> {
+        self.budget_usage(now, period_ms).await?;
+        sqlx::query("UPDATE download_budget SET bytes = bytes + ? WHERE id = 1")
+            .bind(bytes)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn create_api_key(&self, name: &str, scope: &str, key_hash: &str, key_prefix: &str) -> Result<ApiKey> {
+        let key = sqlx::query_as::<_, ApiKey>(
+            "INSERT INTO api_keys (id, name, scope, keyHash, keyPrefix, createdAt) VALUES (?, ?, ?, ?, ?, ?) RETURNING *"
+        )
+            .bind(Uuid::new_v4().to_string())
+            .bind(name)
+            .bind(scope)
+            .bind(key_hash)
+            .bind(key_prefix)
+            .bind(chrono::Utc::now().timestamp_millis())
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(key)
+    }
+
+    pub async fn list_api_keys(&self) -> Result<Vec<ApiKey>> {
+        let keys = sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys ORDER BY createdAt ASC")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(keys)
+    }
+
+    pub async fn delete_api_key(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM api_keys WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn api_key_scope(&self, key_hash: &str) -> Result<Option<String>> {
+        let scope = sqlx::query_scalar("SELECT scope FROM api_keys WHERE keyHash = ?")
+            .bind(key_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(scope)
+    }
+
+    pub async fn has_any_job(&self, url: &str) -> Result<bool> {
+        let count: i64 = sqlx::query_scalar("SELECT count(*) FROM jobs WHERE url = ?")
+            .bind(url)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count > 0)
+    }
+
     pub async fn find_done_job_by_url(&self, url: &str) -> Result<Option<Job>> {
         let job = sqlx::query_as::<_, Job>(
             "SELECT * FROM jobs WHERE url = ? AND status = 'done' ORDER BY completedAt DESC LIMIT 1"
@@ -158,6 +543,11 @@ impl Db {
             .bind(id)
             .execute(&self.pool)
             .await?;
+        if self.updates.receiver_count() > 0 {
+            if let Some(job) = self.get_job(id).await? {
+                self.publish("progress", &job).await;
+            }
+        }
         Ok(())
     }
 
@@ -168,66 +558,228 @@ impl Db {
             .bind(id)
             .execute(&self.pool)
             .await?;
+        self.record_event(id).await;
+        Ok(())
+    }
+
+    pub async fn mark_paused(&self, id: &str) -> Result<()> {
+        let result = sqlx::query("UPDATE jobs SET status = 'paused' WHERE id = ? AND status = 'downloading'")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() > 0 {
+            self.record_event(id).await;
+        }
         Ok(())
     }
 
-    pub async fn mark_done(&self, id: &str, filename: &str) -> Result<()> {
+    pub async fn mark_resumed(&self, id: &str) -> Result<()> {
+        let result = sqlx::query("UPDATE jobs SET status = 'downloading' WHERE id = ? AND status = 'paused'")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() > 0 {
+            self.record_event(id).await;
+        }
+        Ok(())
+    }
+
+    pub async fn set_media_info(&self, id: &str, info: &MediaInfo) -> Result<()> {
+        sqlx::query("UPDATE jobs SET width = ?, height = ?, videoCodec = ? WHERE id = ?")
+            .bind(info.width)
+            .bind(info.height)
+            .bind(&info.video_codec)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_verified(&self, id: &str, verified: bool) -> Result<()> {
+        sqlx::query("UPDATE jobs SET verified = ? WHERE id = ?")
+            .bind(verified)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn mark_done(&self, id: &str, filename: &str, files: &[String]) -> Result<()> {
         let now = chrono::Utc::now().timestamp_millis();
-        sqlx::query("UPDATE jobs SET status = 'done', progress = 100, eta = NULL, filename = ?, completedAt = ? WHERE id = ?")
+        sqlx::query("UPDATE jobs SET status = 'done', progress = 100, eta = NULL, filename = ?, files = ?, completedAt = ?, verified = NULL WHERE id = ?")
             .bind(filename)
+            .bind(Json(files))
             .bind(now)
             .bind(id)
             .execute(&self.pool)
             .await?;
+        self.record_event(id).await;
+        Ok(())
+    }
+
+    // Points one job's file at its new name, and the job at `subfolder`
+    // when the file moved to another folder.
+    pub async fn rename_job_file(&self, id: &str, old_filename: &str, new_filename: &str, subfolder: Option<&str>) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE jobs SET
+                filename = CASE WHEN filename = ?2 THEN ?1 ELSE filename END,
+                files = CASE WHEN files IS NULL THEN NULL ELSE (
+                    SELECT json_group_array(CASE WHEN value = ?2 THEN ?1 ELSE value END) FROM json_each(jobs.files)
+                ) END,
+                subfolder = COALESCE(?3, subfolder)
+            WHERE id = ?4
+            "#
+        )
+            .bind(new_filename)
+            .bind(old_filename)
+            .bind(subfolder)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        self.record_event(id).await;
         Ok(())
     }
 
     pub async fn mark_failed(&self, id: &str, error: &str) -> Result<()> {
+        self.mark_failed_with_kind(id, error, None).await
+    }
+
+    pub async fn mark_failed_with_kind(&self, id: &str, error: &str, kind: Option<&str>) -> Result<()> {
         let now = chrono::Utc::now().timestamp_millis();
-        sqlx::query("UPDATE jobs SET status = 'failed', error = ?, completedAt = ? WHERE id = ?")
+        sqlx::query("UPDATE jobs SET status = 'failed', error = ?, errorKind = ?, completedAt = ? WHERE id = ?")
             .bind(error)
+            .bind(kind)
             .bind(now)
             .bind(id)
             .execute(&self.pool)
             .await?;
+        self.record_event(id).await;
         Ok(())
     }
 
     pub async fn increment_retry(&self, id: &str) -> Result<()> {
         sqlx::query(
-            "UPDATE jobs SET retries = retries + 1, status = 'queued', error = NULL, progress = 0, eta = NULL, startedAt = NULL, completedAt = NULL WHERE id = ?"
+            "UPDATE jobs SET retries = retries + 1, status = 'queued', error = NULL, errorKind = NULL, progress = 0, eta = NULL, startedAt = NULL, completedAt = NULL, queuedAt = ? WHERE id = ?"
         )
+        .bind(chrono::Utc::now().timestamp_millis())
         .bind(id)
         .execute(&self.pool)
         .await?;
+        self.record_event(id).await;
         Ok(())
     }
 
+    // Puts a download stopped by a shutdown back in the queue. Its retry
+    // count and queue position are left alone since nothing went wrong
+    // with it.
+    pub async fn requeue_interrupted(&self, id: &str) -> Result<()> {
+        let result = sqlx::query("UPDATE jobs SET status = 'queued', eta = NULL WHERE id = ? AND status IN ('downloading', 'paused')")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() > 0 {
+            self.record_event(id).await;
+        }
+        Ok(())
+    }
+
+    // `completedAt` is kept until the new run ends since the job's date
+    // folder, where its current files are, is derived from it.
     pub async fn redownload_job(&self, id: &str) -> Result<()> {
          sqlx::query(
-            "UPDATE jobs SET status = 'queued', progress = 0, eta = NULL, error = NULL, retries = retries + 1, startedAt = NULL, completedAt = NULL WHERE id = ?"
+            "UPDATE jobs SET status = 'queued', progress = 0, eta = NULL, error = NULL, errorKind = NULL, retries = retries + 1, startedAt = NULL, queuedAt = ? WHERE id = ?"
         )
+        .bind(chrono::Utc::now().timestamp_millis())
         .bind(id)
         .execute(&self.pool)
         .await?;
+        self.record_event(id).await;
         Ok(())
     }
 
     pub async fn reset_crashed_jobs(&self) -> Result<()> {
-        sqlx::query("UPDATE jobs SET status = 'failed', error = 'crashed' WHERE status = 'downloading'")
-            .execute(&self.pool)
+        let jobs = sqlx::query_as::<_, Job>("UPDATE jobs SET status = 'failed', error = 'crashed' WHERE status IN ('downloading', 'paused') RETURNING *")
+            .fetch_all(&self.pool)
             .await?;
+        for job in &jobs {
+            self.publish("status", job).await;
+        }
         Ok(())
     }
 
-    pub async fn delete_job(&self, id: &str) -> Result<()> {
-        sqlx::query("DELETE FROM jobs WHERE id = ?")
+    pub async fn get_running_job_ids(&self) -> Result<Vec<String>> {
+        let ids = sqlx::query_scalar("SELECT id FROM jobs WHERE status IN ('downloading', 'paused')")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(ids)
+    }
+
+    // Returns whether the job was still running.
+    pub async fn mark_crashed(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE jobs SET status = 'failed', error = 'crashed' WHERE id = ? AND status IN ('downloading', 'paused')")
             .bind(id)
             .execute(&self.pool)
             .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.record_event(id).await;
+        Ok(true)
+    }
+
+    // A job that started downloading in the meantime is left alone; `false`
+    // then, and when there is no such job.
+    pub async fn update_job_url(&self, id: &str, url: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE jobs SET url = ?, redownloadedFrom = NULL WHERE id = ? AND status NOT IN ('downloading', 'paused')")
+            .bind(url)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.record_event(id).await;
+        Ok(true)
+    }
+
+    // Returns the new state, or `None` if there is no such job.
+    pub async fn toggle_starred(&self, id: &str) -> Result<Option<bool>> {
+        let starred = sqlx::query_scalar("UPDATE jobs SET starred = NOT starred WHERE id = ? RETURNING starred")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(starred)
+    }
+
+    pub async fn get_starred_jobs(&self) -> Result<Vec<Job>> {
+        let jobs = sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE starred = 1")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(jobs)
+    }
+
+    pub async fn delete_job(&self, id: &str) -> Result<()> {
+        let job = sqlx::query_as::<_, Job>("DELETE FROM jobs WHERE id = ? RETURNING *")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        if let Some(job) = job {
+            self.publish("deleted", &job).await;
+        }
         Ok(())
     }
     
+    // Jobs with a file of this name in any folder; callers pick the ones
+    // whose `job_folder` matches.
+    pub async fn get_jobs_by_filename(&self, filename: &str) -> Result<Vec<Job>> {
+        let jobs = sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE filename = ?1 OR EXISTS (SELECT 1 FROM json_each(jobs.files) WHERE value = ?1)")
+            .bind(filename)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(jobs)
+    }
+
     pub async fn check_job_exists(&self, id: &str) -> Result<bool> {
         let count: i64 = sqlx::query_scalar("SELECT count(*) FROM jobs WHERE id = ?")
             .bind(id)
@@ -236,24 +788,67 @@ impl Db {
         Ok(count > 0)
     }
 
-    pub async fn get_job_history(&self, limit: i64, offset: i64) -> Result<(Vec<Job>, i64)> {
-        let items = sqlx::query_as::<_, Job>(
-            "SELECT * FROM jobs ORDER BY createdAt DESC LIMIT ? OFFSET ?"
-        )
+    pub async fn get_job_history_filtered(
+        &self,
+        limit: i64,
+        offset: i64,
+        from: Option<i64>,
+        to: Option<i64>,
+        q: Option<&str>,
+    ) -> Result<(Vec<Job>, i64)> {
+        let pattern = q.map(|q| {
+            let escaped = q.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            format!("%{}%", escaped)
+        });
+        let filter = r#"
+            WHERE (?1 IS NULL OR createdAt >= ?1)
+              AND (?2 IS NULL OR createdAt <= ?2)
+              AND (?3 IS NULL OR url LIKE ?3 ESCAPE '\' OR filename LIKE ?3 ESCAPE '\')
+        "#;
+
+        let items = sqlx::query_as::<_, Job>(&format!(
+            "SELECT * FROM jobs {} ORDER BY createdAt DESC, seq DESC LIMIT ?4 OFFSET ?5", filter
+        ))
+        .bind(from)
+        .bind(to)
+        .bind(&pattern)
         .bind(limit)
         .bind(offset)
         .fetch_all(&self.pool)
         .await?;
 
-        let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM jobs")
+        let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM jobs {}", filter))
+            .bind(from)
+            .bind(to)
+            .bind(&pattern)
             .fetch_one(&self.pool)
             .await?;
             
         Ok((items, total))
     }
     
+    // Only jobs this server actually downloaded count: archive imports are
+    // 'done' without a startedAt, and 'missing' jobs were done at the time.
+    pub async fn get_job_metrics(&self, since: i64) -> Result<JobMetrics> {
+        let metrics = sqlx::query_as::<_, JobMetrics>(
+            r#"
+            SELECT
+                COALESCE(SUM(status IN ('done', 'missing')), 0) AS done,
+                COALESCE(SUM(status = 'failed' AND COALESCE(error, '') != 'Cancelled'), 0) AS failed,
+                COALESCE(SUM(status = 'failed' AND error = 'Cancelled'), 0) AS cancelled,
+                AVG(CASE WHEN status IN ('done', 'missing') THEN completedAt - startedAt END) AS avg_download_ms
+            FROM jobs
+            WHERE completedAt >= ? AND startedAt IS NOT NULL
+            "#,
+        )
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(metrics)
+    }
+
     pub async fn export_all_jobs(&self) -> Result<Vec<Job>> {
-        let jobs = sqlx::query_as::<_, Job>("SELECT * FROM jobs ORDER BY createdAt DESC")
+        let jobs = sqlx::query_as::<_, Job>("SELECT * FROM jobs ORDER BY createdAt DESC, seq DESC")
             .fetch_all(&self.pool)
             .await?;
         Ok(jobs)
@@ -262,8 +857,8 @@ impl Db {
     pub async fn import_job(&self, job: Job) -> Result<()> {
          sqlx::query(
             r#"
-            INSERT INTO jobs (id, url, status, progress, eta, filename, createdAt, startedAt, completedAt, retries, error)
-            VALUES (?, ?, 'imported', ?, ?, ?, ?, ?, ?, 0, ?)
+            INSERT INTO jobs (id, url, status, progress, eta, filename, createdAt, startedAt, completedAt, retries, error, headers, files, outputContainer, starred, redownloadedFrom, subfolder, format, audioOnly, audioFormat, priority, width, height, videoCodec, startAfter, webhookUrl, maxHeight, seq)
+            VALUES (?, ?, 'imported', ?, ?, ?, ?, ?, ?, 0, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, (SELECT COALESCE(MAX(seq), 0) + 1 FROM jobs))
             "#
         )
         .bind(job.id)
@@ -275,20 +870,56 @@ impl Db {
         .bind(job.started_at)
         .bind(job.completed_at)
         .bind(job.error)
+        .bind(job.headers)
+        .bind(job.files)
+        .bind(job.output_container)
+        .bind(job.starred)
+        .bind(job.redownloaded_from)
+        .bind(job.subfolder)
+        .bind(job.format)
+        .bind(job.audio_only)
+        .bind(job.audio_format)
+        .bind(job.priority)
+        .bind(job.width)
+        .bind(job.height)
+        .bind(job.video_codec)
+        .bind(job.start_after)
+        .bind(job.webhook_url)
+        .bind(job.max_height)
         .execute(&self.pool)
         .await?;
         Ok(())
     }
 
-    pub async fn scan_for_missing_files(&self) -> Result<()> {
+    pub async fn add_archived_job(&self, url: &str) -> Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        sqlx::query(
+            "INSERT INTO jobs (id, url, status, progress, createdAt, completedAt, seq) VALUES (?, ?, 'done', 100, ?, ?, (SELECT COALESCE(MAX(seq), 0) + 1 FROM jobs))"
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(url)
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
         Ok(())
     }
-    
+
+    pub async fn mark_archived(&self, id: &str) -> Result<()> {
+        sqlx::query("UPDATE jobs SET status = 'archived' WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        self.record_event(id).await;
+        Ok(())
+    }
+
      pub async fn mark_missing(&self, id: &str) -> Result<()> {
         sqlx::query("UPDATE jobs SET status = 'missing' WHERE id = ?")
             .bind(id)
             .execute(&self.pool)
             .await?;
+        self.record_event(id).await;
         Ok(())
     }
     
@@ -300,10 +931,95 @@ impl Db {
     }
 
     pub async fn delete_old_failed_jobs(&self, cutoff: i64) -> Result<u64> {
-        let result = sqlx::query("DELETE FROM jobs WHERE status = 'failed' AND createdAt < ?")
+        let result = sqlx::query("DELETE FROM jobs WHERE status = 'failed' AND COALESCE(error, '') != 'Cancelled' AND starred = 0 AND createdAt < ?")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete_old_cancelled_jobs(&self, cutoff: i64) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM jobs WHERE status = 'failed' AND error = 'Cancelled' AND starred = 0 AND createdAt < ?")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    // Missing jobs are dated by when they finished downloading.
+    pub async fn delete_old_missing_jobs(&self, cutoff: i64) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM jobs WHERE status = 'missing' AND starred = 0 AND COALESCE(completedAt, createdAt) < ?")
             .bind(cutoff)
             .execute(&self.pool)
             .await?;
         Ok(result.rows_affected())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn temp_db() -> (Db, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("tiak-test-{}", Uuid::new_v4()));
+        let db = Db::new(dir.join("tiak.db").to_str().unwrap()).await.unwrap();
+        (db, dir)
+    }
+
+    #[tokio::test]
+    async fn queue_order_survives_a_restart() {
+        let (db, dir) = temp_db().await;
+        let db_path = dir.join("tiak.db");
+        let db_path = db_path.to_str().unwrap();
+
+        let mut ids = Vec::new();
+        for url in ["https://a.test", "https://b.test", "https://c.test"] {
+            ids.push(db.add_job(url.to_string(), JobOptions::default()).await.unwrap().id);
+        }
+        // A retry sends the first job to the back of the queue.
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        db.increment_retry(&ids[0]).await.unwrap();
+        db.pool.close().await;
+
+        let db = Db::new(db_path).await.unwrap();
+        let order: Vec<String> = db.get_queued_jobs().await.unwrap().into_iter().map(|j| j.id).collect();
+        assert_eq!(order, [ids[1].clone(), ids[2].clone(), ids[0].clone()]);
+        db.pool.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn job_json_leaves_out_request_headers_and_webhook_url() {
+        let json = serde_json::json!({
+            "id": "a", "url": "https://a.test", "status": "queued", "progress": 0, "eta": null,
+            "filename": null, "created_at": 1, "started_at": null, "completed_at": null,
+            "retries": 0, "error": null, "error_kind": null, "headers": { "Cookie": "secret" },
+            "files": null, "output_container": null, "redownloaded_from": null, "subfolder": null,
+            "format": null, "audio_format": null, "width": null, "height": null, "video_codec": null,
+            "start_after": null, "webhook_url": "https://hooks.test/token", "max_height": null, "verified": null
+        });
+        // Imports still read them.
+        let job: Job = serde_json::from_value(json).unwrap();
+        assert_eq!(job.headers.as_ref().unwrap().0["Cookie"], "secret");
+        assert_eq!(job.webhook_url.as_deref(), Some("https://hooks.test/token"));
+        let serialized = serde_json::to_string(&job).unwrap();
+        assert!(!serialized.contains("secret") && !serialized.contains("token"));
+    }
+
+    #[tokio::test]
+    async fn a_redownload_keeps_the_folder_of_the_current_files() {
+        let (db, dir) = temp_db().await;
+        let job = db.add_job("https://a.test".to_string(), JobOptions::default()).await.unwrap();
+        db.mark_done(&job.id, "clip.mp4", &["clip.mp4".to_string()]).await.unwrap();
+        // Finished on an earlier day than today's redownload.
+        let finished = job.created_at - 3 * 86_400_000;
+        sqlx::query("UPDATE jobs SET completedAt = ? WHERE id = ?").bind(finished).bind(&job.id).execute(&db.pool).await.unwrap();
+        let folder = crate::cleanup::job_folder(&db.get_job(&job.id).await.unwrap().unwrap());
+
+        db.redownload_job(&job.id).await.unwrap();
+        let queued = db.get_job(&job.id).await.unwrap().unwrap();
+        assert_eq!(queued.status, "queued");
+        assert_eq!(crate::cleanup::job_folder(&queued), folder);
+        db.pool.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
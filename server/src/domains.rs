@@ -0,0 +1,58 @@
+// Host allow/block lists for URLs added to the queue. An entry matches its
+// host exactly; `*.example.com` matches any subdomain of example.com but
+// not example.com itself.
+#[derive(Clone, Debug, Default)]
+pub struct DomainFilter {
+    allowed: Vec<String>,
+    blocked: Vec<String>,
+}
+
+impl DomainFilter {
+    pub fn new(allowed: Vec<String>, blocked: Vec<String>) -> Self {
+        let normalize = |list: Vec<String>| {
+            list.into_iter()
+                .map(|d| d.trim().trim_end_matches('.').to_ascii_lowercase())
+                .filter(|d| !d.is_empty())
+                .collect()
+        };
+        Self { allowed: normalize(allowed), blocked: normalize(blocked) }
+    }
+
+    // The error is the reason reported for a skipped URL.
+    pub fn check(&self, url: &str) -> Result<(), String> {
+        if self.allowed.is_empty() && self.blocked.is_empty() {
+            return Ok(());
+        }
+        let Some(host) = url_host(url) else {
+            return Err("URL has no host".to_string());
+        };
+        if self.blocked.iter().any(|d| matches(d, &host)) {
+            return Err(format!("Domain '{}' is blocked", host));
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|d| matches(d, &host)) {
+            return Err(format!("Domain '{}' is not allowed", host));
+        }
+        Ok(())
+    }
+}
+
+fn matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.len() > domain.len() + 1 && host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'),
+        None => host == pattern,
+    }
+}
+
+// Lowercased host of an http(s) URL, without user info, port or trailing dot.
+fn url_host(url: &str) -> Option<String> {
+    let rest = url.split_once("://")?.1;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    let host = if let Some(v6) = host_port.strip_prefix('[') {
+        v6.split_once(']')?.0
+    } else {
+        host_port.split(':').next()?
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    (!host.is_empty()).then_some(host)
+}
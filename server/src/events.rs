@@ -0,0 +1,62 @@
+use crate::db::Job;
+use serde::Serialize;
+use std::path::Path;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+#[derive(Serialize)]
+struct JobEvent<'a> {
+    timestamp: i64,
+    id: &'a str,
+    url: &'a str,
+    status: &'a str,
+    filename: Option<&'a str>,
+    error: Option<&'a str>,
+}
+
+// Published to `GET /api/queue/events` subscribers. `type` is `progress`
+// for a progress update, `deleted` when the job is removed and `status` for
+// any other change of the job.
+#[derive(Clone, Serialize)]
+pub struct JobUpdate {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub job: Job,
+}
+
+// Append-only JSONL log of job status changes for tools that `tail -f` it.
+// Each event is written as one line in a single write, so readers never
+// see a partial record.
+pub struct EventLog {
+    file: Mutex<File>,
+}
+
+impl EventLog {
+    pub async fn open(path: &str) -> anyhow::Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path).await?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+
+    pub async fn record(&self, job: &Job) {
+        let event = JobEvent {
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            id: &job.id,
+            url: &job.url,
+            status: &job.status,
+            filename: job.filename.as_deref(),
+            error: job.error.as_deref(),
+        };
+        let Ok(mut line) = serde_json::to_vec(&event) else { return };
+        line.push(b'\n');
+
+        let mut file = self.file.lock().await;
+        if let Err(e) = file.write_all(&line).await {
+            warn!("Failed to write job event for {}: {}", job.id, e);
+        }
+    }
+}
@@ -1,16 +1,20 @@
 use dotenv::dotenv;
 use std::sync::Arc;
-use tracing::{info, Level};
+use tracing::{error, info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 use crate::db::Db;
-use crate::queue::DownloadQueue;
-use crate::storage::FileIndex;
-use crate::routes::{create_router, AppState};
-use crate::cleanup::{run_cleanup, scan_for_missing_files};
+use crate::events::EventLog;
+use crate::queue::{DownloadBudget, DownloadQueue};
+use crate::storage::{FileIndex, DATA_ROOT, check_data_root_writable, data_root_available, set_cold_root, set_folder_timezone, set_index_threads, set_library_mode, set_walk_concurrency};
+use std::path::Path;
+use crate::routes::{create_router, AppState, SKIPPED_COUNT_HEADER};
+use crate::cleanup::{check_disk_space, reconcile, run_retention, scan_for_missing_files, tier_old_files};
 use crate::config::Config;
+use crate::maintenance::wait_for_window;
+use crate::system::Capabilities;
 use tokio::net::TcpListener;
-use tower_http::cors::{CorsLayer, Any};
-use axum::http::HeaderValue;
+use tower_http::cors::{AllowHeaders, CorsLayer, Any};
+use axum::http::{HeaderName, HeaderValue};
 
 mod db;
 mod queue;
@@ -18,6 +22,19 @@ mod storage;
 mod routes;
 mod cleanup;
 mod config;
+mod system;
+mod dav;
+mod error;
+mod archive;
+mod upload;
+mod events;
+mod media;
+mod webhook;
+mod maintenance;
+mod domains;
+mod auth;
+mod zip_job;
+mod share;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -30,15 +47,64 @@ async fn main() -> anyhow::Result<()> {
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let db = Db::new(&config.db_path).await?;
+    // With the database kept outside the data root, a missing data root is
+    // an unmounted volume rather than a first run, so it is not recreated.
+    let data_root_missing = !data_root_available() && !Path::new(&config.db_path).starts_with(DATA_ROOT) && Path::new(&config.db_path).exists();
+    if data_root_missing {
+        warn!("Data root '{}' is missing; file scans and sync are paused until it is available", DATA_ROOT);
+    }
+
+    let startup_check = if data_root_missing {
+        Db::check_writable(&config.db_path)
+    } else {
+        check_data_root_writable().and_then(|_| Db::check_writable(&config.db_path))
+    };
+    if let Err(e) = startup_check {
+        error!("Startup check failed: {}", e);
+        std::process::exit(1);
+    }
+
+    set_folder_timezone(config.timezone);
+    set_library_mode(config.library_mode);
+    set_walk_concurrency(config.walk_concurrency);
+    set_index_threads(config.index_threads);
+    if let Some(root) = &config.cold_data_root {
+        if !Path::new(root).is_dir() {
+            warn!("Cold data root '{}' is not a directory; its files are not listed until it is available", root);
+        }
+    }
+    set_cold_root(config.cold_data_root.as_ref().map(std::path::PathBuf::from));
+
+    let mut db = Db::new(&config.db_path).await?;
     info!("Database initialized at {}", config.db_path);
 
+    if let Some(path) = &config.event_log_path {
+        match EventLog::open(path).await {
+            Ok(events) => {
+                db = db.with_event_log(events);
+                info!("Writing job events to {}", path);
+            }
+            Err(e) => {
+                error!("Failed to open event log {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let window = config.maintenance_window;
+    if let Some(window) = &window {
+        info!("Background maintenance restricted to {}", window);
+    }
+
     let file_index = Arc::new(FileIndex::new());
-    file_index.build_index().await?;
-    info!("File index built");
     
     let index_clone = file_index.clone();
     tokio::spawn(async move {
+        match index_clone.build_index().await {
+            Ok(status) => log_index_built("File index built", &status),
+            Err(e) => error!("Error building file index: {}", e),
+        }
+
         tokio::time::sleep(std::time::Duration::from_secs(5 * 60)).await;
         
         let mut interval = tokio::time::interval(std::time::Duration::from_secs(30 * 60));
@@ -46,36 +112,103 @@ async fn main() -> anyhow::Result<()> {
         
         loop {
             interval.tick().await;
+            wait_for_window(window).await;
             info!("Starting scheduled file index rebuild...");
-            if let Err(e) = index_clone.build_index().await {
-                 info!("Error rebuilding index: {}", e);
-            } else {
-                info!("File index rebuild completed");
+            match index_clone.build_index().await {
+                Ok(status) => log_index_built("File index rebuild completed", &status),
+                Err(e) => error!("Error rebuilding index: {}", e),
             }
         }
     });
 
-    let queue = DownloadQueue::new(db.clone(), file_index.clone());
+    let capabilities = Capabilities::probe();
+    for (name, enabled) in [("sync", config.enable_sync), ("resolve", config.enable_resolve), ("import", config.enable_import)] {
+        if !enabled {
+            info!("Feature '{}' is disabled by configuration", name);
+        }
+    }
+
+    let queue = DownloadQueue::new(db.clone(), file_index.clone(), capabilities);
+    if config.budget_bytes > 0 && config.budget_period_days > 0 {
+        queue.set_download_budget(Some(DownloadBudget {
+            limit_bytes: config.budget_bytes,
+            period_ms: config.budget_period_days as i64 * 24 * 60 * 60 * 1000,
+        })).await;
+    }
     queue.load_initial_state().await;
+    if let Some(path) = &config.seed_urls_file {
+        queue.seed_from_file(path).await;
+    }
     info!("Queue initialized");
 
     let db_clone = db.clone();
+    let retention_index = file_index.clone();
+    let retention = config.retention;
     tokio::spawn(async move {
-         run_cleanup(&db_clone).await;
-         scan_for_missing_files(&db_clone).await;
-
          let mut interval = tokio::time::interval(std::time::Duration::from_secs(24 * 60 * 60));
          loop {
              interval.tick().await;
-             run_cleanup(&db_clone).await;
+             wait_for_window(window).await;
+             run_retention(&db_clone, &retention_index, retention).await;
              scan_for_missing_files(&db_clone).await;
          }
     });
 
+    let queue_clone = queue.clone();
+    tokio::spawn(async move {
+        let mut low = false;
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(5 * 60));
+        loop {
+            interval.tick().await;
+            low = check_disk_space(&queue_clone, low).await;
+        }
+    });
+
+    if config.cold_data_root.is_some() && config.tier_after_days > 0 {
+        let index_clone = file_index.clone();
+        let after_days = config.tier_after_days;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                wait_for_window(window).await;
+                if index_clone.is_ready() {
+                    tier_old_files(&index_clone, after_days).await;
+                }
+            }
+        });
+    }
+
+    if config.reconcile_interval_hours > 0 {
+        let db_clone = db.clone();
+        let index_clone = file_index.clone();
+        let period = std::time::Duration::from_secs(config.reconcile_interval_hours * 60 * 60);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                wait_for_window(window).await;
+                if !index_clone.is_ready() || !data_root_available() {
+                    continue;
+                }
+                match reconcile(&db_clone, &index_clone, false).await {
+                    Ok(report) if report.is_clean() => info!("[Reconcile] Database and files are consistent"),
+                    Ok(report) => warn!(
+                        "[Reconcile] {} orphan files, {} orphan jobs, {} duplicate mappings; run POST /api/system/reconcile?fix=true to repair",
+                        report.orphan_files.len(), report.orphan_jobs.len(), report.duplicates.len()
+                    ),
+                    Err(e) => error!("[Reconcile] Failed: {}", e),
+                }
+            }
+        });
+    }
+
     let app_state = AppState {
         db: db.clone(),
         queue: queue.clone(),
         file_index: file_index.clone(),
+        config: config.clone(),
+        capabilities,
     };
 
     let cors_origins: Vec<HeaderValue> = config.allowed_origins
@@ -86,15 +219,105 @@ async fn main() -> anyhow::Result<()> {
     let cors = CorsLayer::new()
         .allow_origin(cors_origins)
         .allow_methods(Any)
-        .allow_headers(Any);
+        // A `*` wildcard doesn't cover `Authorization`, which API keys need.
+        .allow_headers(AllowHeaders::mirror_request())
+        .expose_headers([HeaderName::from_static(SKIPPED_COUNT_HEADER)]);
         
     let app = create_router(app_state).layer(cors);
 
+    if let Some(path) = &config.listen_socket {
+        return serve_unix(path, app, queue).await;
+    }
+
     let addr = format!("0.0.0.0:{}", config.server_port);
     info!("Server listening on {}", addr);
     
     let listener = TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            info!("Shutting down, requeueing running downloads");
+            queue.shutdown().await;
+        })
+        .await?;
 
     Ok(())
+}
+
+fn log_index_built(message: &str, status: &storage::IndexStatus) {
+    info!("{} in {} ms: {} files", message, status.duration_ms, status.file_count);
+    if status.entry_error_count > 0 {
+        warn!(
+            "{} entries could not be indexed, first: {}",
+            status.entry_error_count,
+            status.entry_errors.first().map(String::as_str).unwrap_or("")
+        );
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+// axum 0.7's `serve` only takes a TcpListener, so connections on the socket
+// are handed to hyper directly.
+#[cfg(unix)]
+async fn serve_unix(path: &str, app: axum::Router, queue: Arc<DownloadQueue>) -> anyhow::Result<()> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::service::TowerToHyperService;
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::net::UnixListener;
+
+    // A socket left behind by a previous run would make bind fail; anything
+    // that isn't a socket is left alone.
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("Server listening on unix:{}", path);
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            _ = &mut shutdown => {
+                info!("Shutting down, requeueing running downloads");
+                queue.shutdown().await;
+                return Ok(());
+            }
+        };
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                warn!("Connection error on unix socket: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_path: &str, _app: axum::Router, _queue: Arc<DownloadQueue>) -> anyhow::Result<()> {
+    anyhow::bail!("LISTEN_SOCKET is only supported on Unix")
 }
\ No newline at end of file
@@ -0,0 +1,75 @@
+use chrono::{Local, NaiveTime, Utc};
+use chrono_tz::Tz;
+use std::time::Duration;
+
+// Daily time range in which the expensive background tasks (index
+// rebuilds, cleanup, missing-file scans, tiering, reconcile) may run.
+// Read in `TIMEZONE`, or the system local time, like the date folders.
+#[derive(Debug, Clone, Copy)]
+pub struct MaintenanceWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+    tz: Option<Tz>,
+}
+
+impl MaintenanceWindow {
+    // Parses `HH:MM-HH:MM`. A window may wrap past midnight, e.g. `22:00-04:00`.
+    pub fn parse(s: &str, tz: Option<Tz>) -> Result<Self, String> {
+        let invalid = || format!("Invalid maintenance window '{}', expected HH:MM-HH:MM", s);
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| invalid())?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| invalid())?;
+        if start == end {
+            return Err(format!("Maintenance window '{}' is empty", s));
+        }
+        Ok(Self { start, end, tz })
+    }
+
+    fn now(&self) -> NaiveTime {
+        match self.tz {
+            Some(tz) => Utc::now().with_timezone(&tz).time(),
+            None => Local::now().time(),
+        }
+    }
+
+    fn contains(&self, t: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= t && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+
+    fn time_until_open(&self) -> Duration {
+        let now = self.now();
+        if self.contains(now) {
+            return Duration::ZERO;
+        }
+        let secs = (self.start - now).num_seconds().rem_euclid(24 * 60 * 60);
+        Duration::from_secs(secs.max(1) as u64)
+    }
+
+    // Returns right away inside the window. The wait is re-checked every
+    // hour so clock and DST changes only delay a task a little.
+    pub async fn wait_until_open(&self) {
+        loop {
+            let wait = self.time_until_open();
+            if wait.is_zero() {
+                return;
+            }
+            tokio::time::sleep(wait.min(Duration::from_secs(60 * 60))).await;
+        }
+    }
+}
+
+impl std::fmt::Display for MaintenanceWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
+}
+
+pub async fn wait_for_window(window: Option<MaintenanceWindow>) {
+    if let Some(window) = window {
+        window.wait_until_open().await;
+    }
+}
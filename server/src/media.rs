@@ -0,0 +1,269 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
+use tokio::process::Command;
+
+// Cached contact sheets, outside the data root so they are not indexed,
+// synced or served over WebDAV. Sheets not rebuilt for a while are removed
+// by the cleanup.
+const CONTACT_SHEET_DIR: &str = ".contact_sheets";
+const CONTACT_SHEET_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+// Properties of the first video stream of a downloaded file.
+pub struct MediaInfo {
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub video_codec: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    width: Option<i64>,
+    height: Option<i64>,
+    codec_name: Option<String>,
+}
+
+// `None` when ffprobe fails or the file has no video stream.
+pub async fn probe(path: &Path) -> Option<MediaInfo> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=width,height,codec_name")
+        .arg("-of")
+        .arg("json")
+        .arg(path)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let parsed: ProbeOutput = serde_json::from_slice(&output.stdout).ok()?;
+    let stream = parsed.streams.into_iter().next()?;
+    Some(MediaInfo {
+        width: stream.width,
+        height: stream.height,
+        video_codec: stream.codec_name,
+    })
+}
+
+#[derive(Deserialize)]
+struct StreamsOutput {
+    #[serde(default)]
+    streams: Vec<StreamEntry>,
+    format: Option<FormatEntry>,
+}
+
+#[derive(Deserialize)]
+struct StreamEntry {
+    codec_type: Option<String>,
+    #[serde(default)]
+    disposition: Disposition,
+}
+
+#[derive(Deserialize, Default)]
+struct Disposition {
+    #[serde(default)]
+    attached_pic: u8,
+}
+
+#[derive(Deserialize)]
+struct FormatEntry {
+    format_name: Option<String>,
+}
+
+// Which kinds of streams a downloaded file has.
+pub struct StreamKinds {
+    pub video: bool,
+    pub audio: bool,
+}
+
+// ffprobe reports still images (a photo post's `.jpg`) and embedded cover
+// art as video streams, so neither counts as video here. `None` when
+// ffprobe is missing, times out or fails, e.g. on a file it can't read at all.
+pub async fn stream_kinds(path: &Path) -> Option<StreamKinds> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("stream=codec_type:stream_disposition=attached_pic:format=format_name")
+        .arg("-of")
+        .arg("json")
+        .arg(path)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(Duration::from_secs(60), output).await.ok()?.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let parsed: StreamsOutput = serde_json::from_slice(&output.stdout).ok()?;
+    let image = parsed
+        .format
+        .and_then(|f| f.format_name)
+        .is_some_and(|name| name == "image2" || name.ends_with("_pipe"));
+    let has = |kind: &str| parsed.streams.iter().any(|s| s.codec_type.as_deref() == Some(kind) && s.disposition.attached_pic == 0);
+    Some(StreamKinds { video: !image && has("video"), audio: has("audio") })
+}
+
+// Whether ffprobe reads the file without errors and finds at least one
+// stream in it, which catches truncated and corrupt downloads.
+pub async fn verify(path: &Path) -> bool {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("stream=codec_type")
+        .arg("-of")
+        .arg("json")
+        .arg(path)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await;
+    let Ok(output) = output else {
+        return false;
+    };
+    if !output.status.success() || !String::from_utf8_lossy(&output.stderr).trim().is_empty() {
+        return false;
+    }
+    serde_json::from_slice::<StreamsOutput>(&output.stdout).is_ok_and(|parsed| !parsed.streams.is_empty())
+}
+
+// Frame count and tile width of a contact sheet.
+#[derive(Clone, Copy, Debug)]
+pub struct ContactSheetOptions {
+    pub frames: u32,
+    pub tile_width: u32,
+}
+
+#[derive(Debug)]
+pub struct NoVideoStream;
+
+impl std::fmt::Display for NoVideoStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "File has no video stream")
+    }
+}
+
+impl std::error::Error for NoVideoStream {}
+
+// Sheets are generated one at a time; each decodes a whole video.
+static CONTACT_SHEET_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+async fn probe_duration(path: &Path) -> Option<f64> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("format=duration")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(path)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    let duration: f64 = String::from_utf8_lossy(&output.stdout).trim().parse().ok()?;
+    (duration > 0.0).then_some(duration)
+}
+
+// Where the sheet of the video at `path` (its `data/...` path) is cached.
+pub fn contact_sheet_path(path: &str) -> PathBuf {
+    Path::new(CONTACT_SHEET_DIR).join(format!("{:x}.jpg", Sha256::digest(path.as_bytes())))
+}
+
+// Whether `sheet` is newer than the video and than `valid_after` (the last
+// change of the options).
+pub fn contact_sheet_is_fresh(video: &Path, sheet: &Path, valid_after: SystemTime) -> bool {
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    modified(sheet).is_some_and(|at| at >= valid_after && modified(video).is_none_or(|v| at >= v))
+}
+
+// Writes the sheet of `video` to `sheet` unless a fresh one is there.
+pub async fn contact_sheet(video: &Path, sheet: &Path, options: ContactSheetOptions, valid_after: SystemTime) -> anyhow::Result<()> {
+    if contact_sheet_is_fresh(video, sheet, valid_after) {
+        return Ok(());
+    }
+    let _guard = CONTACT_SHEET_LOCK.lock().await;
+    if contact_sheet_is_fresh(video, sheet, valid_after) {
+        return Ok(());
+    }
+    if probe(video).await.is_none() {
+        return Err(NoVideoStream.into());
+    }
+    let duration = probe_duration(video).await.ok_or_else(|| anyhow::anyhow!("Could not read the video duration"))?;
+
+    let columns = (options.frames as f64).sqrt().ceil() as u32;
+    let rows = options.frames.div_ceil(columns);
+    // Only keyframes are decoded, so long videos stay cheap; each tile shows
+    // the keyframe nearest to its evenly spaced position.
+    let filter = format!(
+        "fps={}/{:.3},scale={}:-2,tile={}x{}",
+        options.frames, duration, options.tile_width, columns, rows
+    );
+    if let Some(parent) = sheet.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let temp = sheet.with_extension("tmp.jpg");
+    let output = Command::new("ffmpeg")
+        .arg("-v")
+        .arg("error")
+        .arg("-skip_frame")
+        .arg("nokey")
+        .arg("-i")
+        .arg(video)
+        .arg("-vf")
+        .arg(filter)
+        .arg("-frames:v")
+        .arg("1")
+        .arg("-q:v")
+        .arg("4")
+        .arg("-f")
+        .arg("mjpeg")
+        .arg("-y")
+        .arg(&temp)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    if !output.status.success() {
+        let _ = tokio::fs::remove_file(&temp).await;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg failed: {}", stderr.lines().map(str::trim).rfind(|l| !l.is_empty()).unwrap_or(""));
+    }
+    tokio::fs::rename(&temp, sheet).await?;
+    Ok(())
+}
+
+pub async fn remove_stale_contact_sheets() -> usize {
+    let mut removed = 0;
+    let Ok(mut entries) = tokio::fs::read_dir(CONTACT_SHEET_DIR).await else {
+        return 0;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let stale = entry.metadata().await
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > CONTACT_SHEET_TTL);
+        if stale && tokio::fs::remove_file(entry.path()).await.is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
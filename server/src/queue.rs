@@ -1,21 +1,49 @@
-use crate::db::Db;
-use crate::storage::{FileIndex, get_today_folder};
-use std::collections::VecDeque;
+use crate::archive::DOWNLOAD_ARCHIVE_FILE;
+use crate::cleanup::job_folder;
+use crate::db::{Db, Job, JobOptions};
+use crate::events::JobUpdate;
+use crate::media::ContactSheetOptions;
+use crate::storage::{DATA_ROOT, LIBRARY_FOLDER, FileIndex, LibraryMode, available_space, data_root_available, get_today_folder, is_sidecar, library_mode, locate_data_file, sidecar_paths};
+use crate::system::Capabilities;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
-use tokio::sync::{RwLock, Notify};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{broadcast, RwLock, Notify};
 use dashmap::DashMap;
 use tokio_util::sync::CancellationToken;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tokio::process::Command;
 use std::process::Stdio;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use regex::Regex;
-use tracing::{info, error};
+use tracing::{info, error, warn};
 use serde::Serialize;
 use chrono::{DateTime, Utc};
 use std::fs::File;
 
-#[derive(Clone, Serialize, Debug)]
+// Bytes that may be downloaded per period before new jobs wait.
+#[derive(Clone, Copy, Debug)]
+pub struct DownloadBudget {
+    pub limit_bytes: u64,
+    pub period_ms: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BudgetStatus {
+    #[serde(rename = "limitBytes")]
+    pub limit_bytes: u64,
+    #[serde(rename = "usedBytes")]
+    pub used_bytes: u64,
+    #[serde(rename = "remainingBytes")]
+    pub remaining_bytes: u64,
+    #[serde(rename = "periodStart")]
+    pub period_start: i64,
+    #[serde(rename = "resetsAt")]
+    pub resets_at: i64,
+    pub exhausted: bool,
+}
+
+#[derive(Clone, Serialize, utoipa::ToSchema, Debug)]
 pub struct SyncState {
     pub status: String,
     #[serde(rename = "lastRun")]
@@ -24,6 +52,24 @@ pub struct SyncState {
     pub error: Option<String>,
     #[serde(rename = "unsyncedCount")]
     pub unsynced_count: usize,
+    #[serde(rename = "filesTransferred")]
+    pub files_transferred: Option<u64>,
+    #[serde(rename = "filesSkipped")]
+    pub files_skipped: Option<u64>,
+    // Paths relative to the data root, as rclone reports them.
+    #[serde(rename = "uploadedFiles")]
+    pub uploaded_files: Vec<String>,
+    #[serde(rename = "uploadedFilesTruncated")]
+    pub uploaded_files_truncated: bool,
+    // With `verifyBeforeSync`, indexed files left out of the last sync
+    // because no verified job owns them.
+    #[serde(rename = "heldBackCount")]
+    pub held_back_count: Option<usize>,
+    #[serde(rename = "heldBackFiles")]
+    pub held_back_files: Vec<String>,
+    // Set when the check run after `syncDestination` changes fails.
+    #[serde(rename = "destinationError")]
+    pub destination_error: Option<String>,
 }
 
 impl Default for SyncState {
@@ -34,139 +80,1459 @@ impl Default for SyncState {
             logs: Vec::new(),
             error: None,
             unsynced_count: 0,
+            files_transferred: None,
+            files_skipped: None,
+            uploaded_files: Vec::new(),
+            uploaded_files_truncated: false,
+            held_back_count: None,
+            held_back_files: Vec::new(),
+            destination_error: None,
+        }
+    }
+}
+
+// Reads the file counts from the final stats block rclone prints with -v.
+// The byte totals line also starts with "Transferred:" but carries units,
+// so only the plain "N / M," counts match.
+fn parse_rclone_stats(logs: &[String]) -> (Option<u64>, Option<u64>) {
+    let re_transferred = Regex::new(r"Transferred:\s+(\d+)\s*/\s*\d+(?:,|$)").unwrap();
+    let re_checks = Regex::new(r"Checks:\s+(\d+)\s*/\s*\d+").unwrap();
+
+    let last_count = |re: &Regex| {
+        logs.iter()
+            .rev()
+            .find_map(|line| re.captures(line).and_then(|c| c[1].parse::<u64>().ok()))
+    };
+
+    (last_count(&re_transferred), last_count(&re_checks))
+}
+
+const MAX_UPLOADED_FILES: usize = 1000;
+
+fn reset_sync_state(s: &mut SyncState, dest: &str) {
+    s.status = "running".to_string();
+    s.logs.clear();
+    s.logs.push(format!("Starting sync to {}...", dest));
+    s.error = None;
+    s.files_transferred = None;
+    s.files_skipped = None;
+    s.uploaded_files.clear();
+    s.uploaded_files_truncated = false;
+    s.held_back_count = None;
+    s.held_back_files.clear();
+}
+
+// A job is verified when ffprobe reads all of its files.
+async fn verify_files(paths: &[PathBuf]) -> bool {
+    for path in paths {
+        if !crate::media::verify(path).await {
+            return false;
+        }
+    }
+    true
+}
+
+// Keeps the last 100 log lines and collects the files rclone -v reports as
+// copied, e.g. `INFO  : 2024-05-01/video.mp4: Copied (new)`.
+fn record_sync_line(s: &mut SyncState, line: String, re_copied: &Regex) {
+    if let Some(caps) = re_copied.captures(&line) {
+        if s.uploaded_files.len() < MAX_UPLOADED_FILES {
+            s.uploaded_files.push(caps[1].to_string());
+        } else {
+            s.uploaded_files_truncated = true;
+        }
+    }
+    if s.logs.len() > 100 { s.logs.remove(0); }
+    s.logs.push(line);
+}
+
+// Looks up a file named after the video id in the URL, so a video that is
+// already in the flat library is not downloaded again. URLs without an id
+// (short links) are left to yt-dlp, which skips a finished `<id>.<ext>`.
+async fn find_library_file(folder: &Path, url: &str) -> Option<String> {
+    let re = Regex::new(r"/(?:video|photo)/(\d+)").unwrap();
+    let video_id = re.captures(url)?.get(1)?.as_str().to_string();
+    let mut entries = tokio::fs::read_dir(folder).await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = Path::new(&name);
+        let partial = matches!(path.extension().and_then(|e| e.to_str()), Some("part" | "ytdl"));
+        if path.file_stem().and_then(|s| s.to_str()) == Some(video_id.as_str()) && !partial && !is_sidecar(&name) {
+            return Some(name);
+        }
+    }
+    None
+}
+
+#[derive(Clone, Default)]
+struct DownloadOptions {
+    resume: bool,
+    keep_original: bool,
+    write_description: bool,
+    write_comments: bool,
+    probe_media: bool,
+    // `verifyBeforeSync` with ffprobe installed.
+    verify: bool,
+    // `any` when ffprobe is not installed.
+    require_stream: String,
+    stall_timeout_seconds: u64,
+    progress_write_interval_ms: u64,
+    use_archive: bool,
+    ffmpeg_threads: usize,
+    output_container: String,
+    subfolder: Option<String>,
+    format: Option<String>,
+    audio_only: bool,
+    audio_format: Option<String>,
+    // `0` for no cap.
+    max_height: u32,
+    extra_args: Vec<String>,
+    proxy: Option<String>,
+    headers: HashMap<String, String>,
+    webhook_url: Option<String>,
+    // Download into `REDOWNLOAD_DIR` and only move the result into place
+    // once yt-dlp succeeds, so a failed redownload keeps the old file.
+    staged: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CancelReason {
+    User,
+    // The server is stopping; the job is queued again for the next start.
+    Shutdown,
+}
+
+// A running job's cancellation token and why it was cancelled, so its task
+// can tell a user's cancel from a shutdown.
+#[derive(Clone)]
+struct JobCancel {
+    token: CancellationToken,
+    reason: Arc<std::sync::OnceLock<CancelReason>>,
+}
+
+impl JobCancel {
+    fn new() -> Self {
+        Self { token: CancellationToken::new(), reason: Arc::new(std::sync::OnceLock::new()) }
+    }
+
+    // The first reason given wins.
+    fn cancel(&self, reason: CancelReason) {
+        let _ = self.reason.set(reason);
+        self.token.cancel();
+    }
+
+    fn reason(&self) -> CancelReason {
+        self.reason.get().copied().unwrap_or(CancelReason::User)
+    }
+}
+
+#[derive(Debug)]
+enum DownloadError {
+    Cancelled,
+    Stalled(u64),
+    Archived,
+    MissingStream(&'static str),
+}
+
+impl DownloadError {
+    fn kind(&self) -> &'static str {
+        match self {
+            DownloadError::Cancelled => "cancelled",
+            DownloadError::Stalled(_) => "stalled",
+            DownloadError::Archived => "archived",
+            DownloadError::MissingStream(_) => "missing_stream",
+        }
+    }
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Cancelled => write!(f, "Job cancelled"),
+            DownloadError::Stalled(secs) => write!(f, "Download stalled: no progress for {} seconds", secs),
+            DownloadError::Archived => write!(f, "Already recorded in the download archive"),
+            DownloadError::MissingStream(kind) => write!(f, "Downloaded file has no {} stream", kind),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+// Last sign of life from yt-dlp, used by the stall watchdog. Post-processing
+// (merging/remuxing) prints nothing while ffmpeg runs, so it pauses the clock.
+struct Activity {
+    last: std::time::Instant,
+    postprocessing: bool,
+}
+
+// The yt-dlp process behind an active job, kept so it can be paused.
+struct RunningChild {
+    pid: u32,
+    paused: Arc<AtomicBool>,
+}
+
+#[derive(Debug)]
+pub enum PauseError {
+    NotRunning,
+    AlreadyPaused,
+    NotPaused,
+    Unsupported,
+    Signal(String),
+}
+
+impl std::fmt::Display for PauseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PauseError::NotRunning => write!(f, "Job is not downloading"),
+            PauseError::AlreadyPaused => write!(f, "Job is already paused"),
+            PauseError::NotPaused => write!(f, "Job is not paused"),
+            PauseError::Unsupported => write!(f, "Pausing jobs is not supported on this platform"),
+            PauseError::Signal(e) => write!(f, "Failed to signal yt-dlp: {}", e),
+        }
+    }
+}
+
+// yt-dlp runs in its own process group (see `run_yt_dlp`), so signals go to
+// the group and reach the ffmpeg it spawns for merging and remuxing too.
+#[cfg(unix)]
+fn signal_child(pid: u32, stop: bool) -> Result<(), PauseError> {
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+    let signal = if stop { Signal::SIGSTOP } else { Signal::SIGCONT };
+    killpg(Pid::from_raw(pid as i32), signal).map_err(|e| PauseError::Signal(e.to_string()))
+}
+
+#[cfg(not(unix))]
+fn signal_child(_pid: u32, _stop: bool) -> Result<(), PauseError> {
+    Err(PauseError::Unsupported)
+}
+
+// `Child::kill` only reaches yt-dlp itself; an ffmpeg it started would keep
+// writing the file after the job is cancelled.
+#[cfg(unix)]
+async fn kill_child(child: &mut tokio::process::Child) -> std::io::Result<()> {
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+    if let Some(pid) = child.id() {
+        let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+    }
+    child.kill().await
+}
+
+#[cfg(not(unix))]
+async fn kill_child(child: &mut tokio::process::Child) -> std::io::Result<()> {
+    child.kill().await
+}
+
+// yt-dlp explains failures (geo-blocks, private videos, unsupported URLs)
+// on stderr; its last lines are kept in the job's error.
+const STDERR_TAIL_LINES: usize = 20;
+const MAX_ERROR_BYTES: usize = 4096;
+
+// When the lines don't fit, the oldest part is cut since the reason is
+// usually printed last.
+fn exit_error(code: i32, stderr_tail: &[String]) -> String {
+    let heading = format!("Process exited with code {}", code);
+    if stderr_tail.is_empty() {
+        return heading;
+    }
+    let output = stderr_tail.join("\n");
+    let room = MAX_ERROR_BYTES.saturating_sub(heading.len() + 5);
+    if output.len() <= room {
+        return format!("{}:\n{}", heading, output);
+    }
+    let mut start = output.len() - room;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("{}:\n...{}", heading, &output[start..])
+}
+
+struct DownloadOutput {
+    folder: PathBuf,
+    filename: String,
+    // Every file yt-dlp reported writing that still exists once it exits:
+    // all items of a multi-part post, plus the source streams kept by `-k`.
+    files: Vec<String>,
+}
+
+pub const REDOWNLOAD_DIR: &str = "data/.redownload";
+
+// Moves a finished redownload out of its staging folder, replacing files
+// with the same name.
+async fn promote_staged(staging: &Path, folder: &Path) -> std::io::Result<()> {
+    let mut entries = tokio::fs::read_dir(staging).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_file() {
+            tokio::fs::rename(entry.path(), folder.join(entry.file_name())).await?;
+        }
+    }
+    tokio::fs::remove_dir_all(staging).await
+}
+
+// Deletes one of a job's files and its sidecars. A file already gone is fine.
+pub async fn remove_job_file(file_index: &FileIndex, folder: &str, name: &str) -> std::io::Result<()> {
+    let Some(path) = locate_data_file(Path::new(folder), name) else {
+        return Ok(());
+    };
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => file_index.remove_file(&Path::new(DATA_ROOT).join(folder).join(name).to_string_lossy()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    for sidecar in sidecar_paths(&path) {
+        let _ = tokio::fs::remove_file(sidecar).await;
+    }
+    Ok(())
+}
+
+fn record_output(files: &Mutex<Vec<String>>, path: &str) {
+    let path = path.trim().trim_matches('"').to_string();
+    let mut w = files.lock().unwrap();
+    w.retain(|p| p != &path);
+    w.push(path);
+}
+
+// What one line of yt-dlp's `--newline` stdout tells about a download.
+#[derive(Debug, Default, PartialEq)]
+struct OutputLine<'a> {
+    // Percent done and seconds left.
+    progress: Option<(f64, Option<i64>)>,
+    // A file yt-dlp wrote or kept.
+    file: Option<&'a str>,
+    already_downloaded: bool,
+    archived: bool,
+    // `Some` when the line starts or ends a post-processing step.
+    postprocessing: Option<bool>,
+}
+
+struct OutputParser {
+    progress: Regex,
+    eta: Regex,
+    dest: Regex,
+    merge: Regex,
+    already: Regex,
+    archived: Regex,
+    postprocess: Regex,
+}
+
+impl OutputParser {
+    fn new() -> Self {
+        Self {
+            progress: Regex::new(r"^\[download\]\s+(\d+(?:\.\d+)?)%").unwrap(),
+            eta: Regex::new(r"ETA\s+(\d{2}:\d{2}(?:\:\d{2})?)").unwrap(),
+            dest: Regex::new(r"\b[dD]estination:\s+(.*)").unwrap(),
+            merge: Regex::new(r#"\b[mM]erger\b.*into\s+"?([^"]*)"?"#).unwrap(),
+            already: Regex::new(r"^\[download\]\s+(.*?)\s+has already been downloaded").unwrap(),
+            archived: Regex::new(r"has already been recorded in the archive").unwrap(),
+            postprocess: Regex::new(r"^\[(Merger|VideoRemuxer|VideoConvertor|ExtractAudio|Fixup\w*)\]").unwrap(),
         }
     }
+
+    fn parse<'a>(&self, line: &'a str) -> OutputLine<'a> {
+        let postprocessing = if line.starts_with("[download]") {
+            Some(false)
+        } else if self.postprocess.is_match(line) {
+            Some(true)
+        } else {
+            None
+        };
+        let progress = self.progress.captures(line)
+            .and_then(|c| c.get(1)?.as_str().parse::<f64>().ok())
+            .map(|p| (p, self.eta.captures(line).and_then(|c| DownloadQueue::parse_eta(c.get(1)?.as_str()))));
+        let already = self.already.captures(line).and_then(|c| c.get(1)).map(|m| m.as_str());
+        let file = self.dest.captures(line)
+            .or_else(|| self.merge.captures(line))
+            .and_then(|c| c.get(1))
+            .map(|m| m.as_str())
+            .or(already);
+        OutputLine {
+            progress,
+            file,
+            already_downloaded: already.is_some(),
+            archived: self.archived.is_match(line),
+            postprocessing,
+        }
+    }
+}
+
+// Options that could run arbitrary commands or load code, read other inputs,
+// write to arbitrary paths, or move the output away from the data directory
+// the index and cleanup expect.
+const BLOCKED_YTDLP_ARGS: &[&str] = &[
+    "--exec",
+    "--exec-before-download",
+    "--netrc-cmd",
+    "--ffmpeg-location",
+    "--plugin-dirs",
+    "--alias",
+    "--postprocessor-args",
+    "--ppa",
+    "--cookies",
+    "--download-archive",
+    "--cache-dir",
+    "--downloader",
+    "--external-downloader",
+    "--downloader-args",
+    "--external-downloader-args",
+    "--print-to-file",
+    "--config-location",
+    "--config-locations",
+    "--batch-file",
+    "-a",
+    "--load-info-json",
+    "--use-postprocessor",
+    "--output",
+    "-o",
+    "--paths",
+    "-P",
+];
+
+// yt-dlp accepts any unambiguous prefix of a long option (`--exec-before`
+// runs `--exec-before-download`), so prefixes of blocked options are refused
+// too, except these complete options that happen to start one.
+const YTDLP_OPTIONS_STARTING_BLOCKED: &[&str] = &["--print", "--netrc"];
+
+pub fn validate_extra_ytdlp_args(args: &[String]) -> Result<(), String> {
+    validate_extra_args(args, |name| {
+        BLOCKED_YTDLP_ARGS.contains(&name)
+            || (name.starts_with("--")
+                && !YTDLP_OPTIONS_STARTING_BLOCKED.contains(&name)
+                && BLOCKED_YTDLP_ARGS.iter().any(|blocked| blocked.starts_with(name)))
+    })
+}
+
+// Options that run commands, read or write local files, start the remote
+// control server, or widen what is copied past the fixed excludes.
+const BLOCKED_RCLONE_ARGS: &[&str] = &[
+    "--config",
+    "--password-command",
+    "--ask-password",
+    "--log-file",
+    "--files-from",
+    "--files-from-raw",
+    "--include",
+    "--include-from",
+    "--filter",
+    "--filter-from",
+    "--exclude-from",
+    "--exclude-if-present",
+    "--copy-links",
+    "-L",
+    "--sftp-ssh",
+    "--cache-dir",
+    "--temp-dir",
+    "--ca-cert",
+    "--client-cert",
+    "--client-key",
+    "--metadata-mapper",
+];
+
+// Backends add their own variants of these, e.g. `--sftp-key-file` or
+// `--webdav-bearer-token-command`.
+const BLOCKED_RCLONE_SUFFIXES: &[&str] = &["-command", "-file", "-from"];
+
+pub fn validate_extra_rclone_args(args: &[String]) -> Result<(), String> {
+    validate_extra_args(args, |name| {
+        BLOCKED_RCLONE_ARGS.contains(&name)
+            || name == "--rc"
+            || name.starts_with("--rc-")
+            || BLOCKED_RCLONE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+    })
+}
+
+fn validate_extra_args(args: &[String], blocked: impl Fn(&str) -> bool) -> Result<(), String> {
+    let mut prev_was_flag = false;
+    for arg in args {
+        if arg.starts_with('-') {
+            let rejected = if arg.starts_with("--") {
+                let name = arg.split('=').next().unwrap_or(arg);
+                blocked(name).then(|| name.to_string())
+            } else {
+                // A short option can carry its value (`-o/tmp/x`) or follow
+                // other flags in a group (`-io x`), so every letter counts.
+                arg.chars().skip(1).map(|c| format!("-{}", c)).find(|name| blocked(name))
+            };
+            if let Some(name) = rejected {
+                return Err(format!("Argument '{}' is not allowed", name));
+            }
+            prev_was_flag = true;
+        } else if prev_was_flag {
+            prev_was_flag = false;
+        } else {
+            return Err(format!("Argument '{}' must be an option or follow one", arg));
+        }
+    }
+    Ok(())
+}
+
+pub const MAX_JOB_HEADERS: usize = 20;
+
+// Headers end up as `--add-header Name:Value` arguments, so names are limited
+// to token characters and values may not contain control characters.
+pub fn validate_job_headers(headers: &HashMap<String, String>) -> Result<(), String> {
+    if headers.len() > MAX_JOB_HEADERS {
+        return Err(format!("Too many headers (max {})", MAX_JOB_HEADERS));
+    }
+    for (name, value) in headers {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)) {
+            return Err(format!("Invalid header name '{}'", name));
+        }
+        if value.chars().any(|c| c.is_control()) {
+            return Err(format!("Invalid value for header '{}'", name));
+        }
+    }
+    Ok(())
+}
+
+const FORMATS_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+#[derive(Clone, Serialize, utoipa::ToSchema, Debug)]
+pub struct FormatInfo {
+    #[serde(rename = "formatId")]
+    pub format_id: String,
+    pub ext: Option<String>,
+    pub resolution: Option<String>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub fps: Option<f64>,
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+    pub filesize: Option<u64>,
+    pub note: Option<String>,
+}
+
+impl FormatInfo {
+    fn from_json(v: &serde_json::Value) -> Self {
+        let text = |key: &str| v[key].as_str().map(|s| s.to_string());
+        Self {
+            format_id: text("format_id").unwrap_or_default(),
+            ext: text("ext"),
+            resolution: text("resolution"),
+            width: v["width"].as_u64(),
+            height: v["height"].as_u64(),
+            fps: v["fps"].as_f64(),
+            vcodec: text("vcodec"),
+            acodec: text("acodec"),
+            filesize: v["filesize"].as_u64().or_else(|| v["filesize_approx"].as_f64().map(|f| f as u64)),
+            note: text("format_note"),
+        }
+    }
+}
+
+pub const MAX_SYNC_TRANSFERS: usize = 64;
+pub const MAX_FFMPEG_THREADS: usize = 64;
+pub const PROGRESS_WRITE_INTERVAL_RANGE_MS: std::ops::RangeInclusive<u64> = 100..=60_000;
+
+// `original` keeps whatever container yt-dlp merges into.
+pub const OUTPUT_CONTAINERS: [&str; 4] = ["mp4", "mkv", "webm", "original"];
+
+pub fn validate_output_container(container: &str) -> Result<(), String> {
+    if OUTPUT_CONTAINERS.contains(&container) {
+        Ok(())
+    } else {
+        Err(format!("Invalid output container '{}', expected one of {}", container, OUTPUT_CONTAINERS.join(", ")))
+    }
+}
+
+pub const AUDIO_FORMATS: [&str; 2] = ["original", "mp3"];
+
+pub fn validate_audio_format(audio_format: &str) -> Result<(), String> {
+    if AUDIO_FORMATS.contains(&audio_format) {
+        Ok(())
+    } else {
+        Err(format!("Invalid audio format '{}', expected one of {}", audio_format, AUDIO_FORMATS.join(", ")))
+    }
+}
+
+// A yt-dlp format selector. A leading '-' would be parsed as an option.
+pub fn validate_format(format: &str) -> Result<(), String> {
+    if format.is_empty() || format.len() > 256 {
+        return Err("format must be between 1 and 256 characters".to_string());
+    }
+    if format.starts_with('-') || format.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(format!("Invalid format '{}'", format));
+    }
+    Ok(())
+}
+
+pub const MAX_HEIGHT_RANGE: std::ops::RangeInclusive<u32> = 144..=4320;
+
+// A resolution cap in pixels, or `0` for none.
+pub fn validate_max_height(height: u32) -> Result<(), String> {
+    if height != 0 && !MAX_HEIGHT_RANGE.contains(&height) {
+        return Err(format!(
+            "maxHeight must be 0 or between {} and {}",
+            MAX_HEIGHT_RANGE.start(),
+            MAX_HEIGHT_RANGE.end()
+        ));
+    }
+    Ok(())
+}
+
+pub const REQUIRED_STREAMS: &[&str] = &["any", "video", "audio"];
+
+pub fn validate_required_stream(kind: &str) -> Result<(), String> {
+    if REQUIRED_STREAMS.contains(&kind) {
+        Ok(())
+    } else {
+        Err(format!("Invalid required stream '{}', expected one of {}", kind, REQUIRED_STREAMS.join(", ")))
+    }
+}
+
+// Checks the primary file of a finished download for the stream the
+// `requireStream` setting asks for. Audio-only jobs only need audio. Only a
+// file ffprobe read and found without that stream fails the job; when the
+// probe itself fails the file is kept unchecked.
+async fn check_required_stream(id: &str, output: &DownloadOutput, options: &DownloadOptions) -> Result<(), DownloadError> {
+    let required = match options.require_stream.as_str() {
+        "video" if !options.audio_only => "video",
+        "video" | "audio" => "audio",
+        _ => return Ok(()),
+    };
+    let Some(kinds) = crate::media::stream_kinds(&output.folder.join(&output.filename)).await else {
+        warn!("Job {}: ffprobe could not read {}, its {} stream is unchecked", id, output.filename, required);
+        return Ok(());
+    };
+    let found = if required == "video" { kinds.video } else { kinds.audio };
+    if found {
+        Ok(())
+    } else {
+        Err(DownloadError::MissingStream(required))
+    }
+}
+
+// A relative folder under the data root. Hidden names are refused since
+// dotfolders there belong to the server (e.g. `.uploads`).
+pub fn validate_subfolder(subfolder: &str) -> Result<(), String> {
+    if subfolder.is_empty() || subfolder.len() > 255 {
+        return Err("subfolder must be between 1 and 255 characters".to_string());
+    }
+    let valid = !subfolder.contains('\\')
+        && subfolder.split('/').all(|part| !part.is_empty() && !part.starts_with('.'));
+    if !valid {
+        return Err(format!("Invalid subfolder '{}', expected a relative path without empty, '.', '..' or hidden components", subfolder));
+    }
+    Ok(())
+}
+
+// Accepts an rclone `remote:path` or a local path. A leading '-' would be
+// parsed by rclone as a flag.
+pub fn validate_sync_destination(dest: &str) -> Result<(), String> {
+    if dest.is_empty() {
+        return Ok(());
+    }
+    if dest.starts_with('-') || dest.chars().any(|c| c.is_control()) {
+        return Err(format!("Invalid sync destination '{}'", dest));
+    }
+    if let Some((remote, _)) = dest.split_once(':') {
+        let is_remote = !remote.contains(['/', '\\']);
+        let remote_re = Regex::new(r"^[\w.+@ -]+$").unwrap();
+        if is_remote && !remote_re.is_match(remote) {
+            return Err(format!("Invalid rclone remote name '{}'", remote));
+        }
+    }
+    Ok(())
+}
+
+const SYNC_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Lists the destination to confirm the remote is configured and reachable.
+// A folder that doesn't exist yet passes, since a sync creates it.
+pub async fn check_sync_destination(dest: &str) -> Result<(), String> {
+    let output = Command::new("rclone")
+        .arg("lsd")
+        .arg(dest)
+        .arg("--max-depth")
+        .arg("1")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(SYNC_CHECK_TIMEOUT, output).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return Err(format!("Failed to start rclone: {}", e)),
+        Err(_) => return Err(format!("rclone did not answer within {} seconds", SYNC_CHECK_TIMEOUT.as_secs())),
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.success() || stderr.contains("directory not found") {
+        return Ok(());
+    }
+    let message = stderr.lines().map(str::trim).rfind(|line| !line.is_empty()).unwrap_or("");
+    Err(format!("rclone exited with code {}: {}", output.status.code().unwrap_or(-1), message))
+}
+
+pub fn validate_sync_bwlimit(limit: &str) -> Result<(), String> {
+    let re = Regex::new(r"^(off|\d+(\.\d+)?[bBkKMGTP]?(:\d+(\.\d+)?[bBkKMGTP]?)?)$").unwrap();
+    if limit.is_empty() || re.is_match(limit) {
+        Ok(())
+    } else {
+        Err(format!("Invalid bandwidth limit '{}', expected e.g. 512k, 10M or 10M:2M", limit))
+    }
 }
 
 #[derive(Clone)]
 pub struct DownloadQueue {
     db: Db,
     file_index: Arc<FileIndex>,
-    queue: Arc<Mutex<VecDeque<String>>>,
-    active_jobs: Arc<DashMap<String, CancellationToken>>,
+    capabilities: Capabilities,
+    // (priority, job id), highest priority first.
+    queue: Arc<Mutex<VecDeque<(i64, String)>>>,
+    active_jobs: Arc<DashMap<String, JobCancel>>,
+    children: Arc<DashMap<String, RunningChild>>,
+    // Latest (progress, eta) parsed for each running job. The database only
+    // gets a copy every `progress_write_interval_ms`.
+    live_progress: Arc<DashMap<String, (i64, Option<i64>)>>,
+    formats_cache: Arc<DashMap<String, (std::time::Instant, Vec<FormatInfo>)>>,
+    // Proxy for the next attempt of a job, taken when that attempt starts.
+    retry_proxies: Arc<DashMap<String, String>>,
     max_concurrent: Arc<RwLock<usize>>,
     sync_destination: Arc<RwLock<String>>,
+    sync_transfers: Arc<RwLock<usize>>,
+    sync_bwlimit: Arc<RwLock<String>>,
+    resume_downloads: Arc<RwLock<bool>>,
+    keep_original: Arc<RwLock<bool>>,
+    archive_after_sync: Arc<RwLock<bool>>,
+    verify_before_sync: Arc<RwLock<bool>>,
+    write_description: Arc<RwLock<bool>>,
+    write_comments: Arc<RwLock<bool>>,
+    probe_media: Arc<RwLock<bool>>,
+    require_stream: Arc<RwLock<String>>,
+    stall_timeout_seconds: Arc<RwLock<u64>>,
+    progress_write_interval_ms: Arc<RwLock<u64>>,
+    use_download_archive: Arc<RwLock<bool>>,
+    low_disk_threshold_bytes: Arc<RwLock<u64>>,
+    ffmpeg_threads: Arc<RwLock<usize>>,
+    max_height: Arc<RwLock<u32>>,
+    output_container: Arc<RwLock<String>>,
+    extra_ytdlp_args: Arc<RwLock<Vec<String>>>,
+    extra_rclone_args: Arc<RwLock<Vec<String>>>,
+    webhook_url: Arc<RwLock<String>>,
+    download_budget: Arc<RwLock<Option<DownloadBudget>>>,
+    // Cached sheets older than the last change of the options are rebuilt.
+    contact_sheet: Arc<RwLock<(ContactSheetOptions, std::time::SystemTime)>>,
     sync_state: Arc<RwLock<SyncState>>,
+    settings_lock: Arc<tokio::sync::Mutex<()>>,
     notify: Arc<Notify>,
+    // Wakes the scheduler when a job with an earlier start time is added.
+    schedule_notify: Arc<Notify>,
+    // Cancelled on shutdown, which ends open `GET /api/queue/events` streams
+    // so they don't hold the server up.
+    stopping: CancellationToken,
 }
 
 const SYNC_MARKER_FILE: &str = "data/.last_sync";
+// Written outside the data root, which rclone copies.
+const SYNC_FILES_LIST: &str = ".sync_files";
+// Scheduled jobs are also checked this often, so clock changes and failed
+// lookups only delay them a little.
+const SCHEDULE_MAX_WAIT: std::time::Duration = std::time::Duration::from_secs(60);
+const ACTIVE_JOBS_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+const SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Frees a download task's concurrency slot when the task ends, even if it
+// panics before reaching its own cleanup.
+struct ActiveJobGuard {
+    id: String,
+    active_jobs: Arc<DashMap<String, JobCancel>>,
+    children: Arc<DashMap<String, RunningChild>>,
+    live_progress: Arc<DashMap<String, (i64, Option<i64>)>>,
+    notify: Arc<Notify>,
+}
+
+impl Drop for ActiveJobGuard {
+    fn drop(&mut self) {
+        self.children.remove(&self.id);
+        self.live_progress.remove(&self.id);
+        self.active_jobs.remove(&self.id);
+        self.notify.notify_one();
+    }
+}
 
 impl DownloadQueue {
-    pub fn new(db: Db, file_index: Arc<FileIndex>) -> Arc<Self> {
+    pub fn new(db: Db, file_index: Arc<FileIndex>, capabilities: Capabilities) -> Arc<Self> {
         let queue = Arc::new(DownloadQueue {
             db,
             file_index,
+            capabilities,
             queue: Arc::new(Mutex::new(VecDeque::new())),
             active_jobs: Arc::new(DashMap::new()),
+            children: Arc::new(DashMap::new()),
+            live_progress: Arc::new(DashMap::new()),
+            formats_cache: Arc::new(DashMap::new()),
+            retry_proxies: Arc::new(DashMap::new()),
             max_concurrent: Arc::new(RwLock::new(2)),
             sync_destination: Arc::new(RwLock::new("".to_string())),
+            sync_transfers: Arc::new(RwLock::new(4)),
+            sync_bwlimit: Arc::new(RwLock::new("".to_string())),
+            resume_downloads: Arc::new(RwLock::new(true)),
+            keep_original: Arc::new(RwLock::new(false)),
+            archive_after_sync: Arc::new(RwLock::new(false)),
+            verify_before_sync: Arc::new(RwLock::new(false)),
+            write_description: Arc::new(RwLock::new(false)),
+            write_comments: Arc::new(RwLock::new(false)),
+            probe_media: Arc::new(RwLock::new(false)),
+            require_stream: Arc::new(RwLock::new("any".to_string())),
+            stall_timeout_seconds: Arc::new(RwLock::new(300)),
+            progress_write_interval_ms: Arc::new(RwLock::new(1000)),
+            use_download_archive: Arc::new(RwLock::new(false)),
+            low_disk_threshold_bytes: Arc::new(RwLock::new(1024 * 1024 * 1024)),
+            ffmpeg_threads: Arc::new(RwLock::new(0)),
+            max_height: Arc::new(RwLock::new(0)),
+            output_container: Arc::new(RwLock::new("mp4".to_string())),
+            extra_ytdlp_args: Arc::new(RwLock::new(Vec::new())),
+            extra_rclone_args: Arc::new(RwLock::new(Vec::new())),
+            webhook_url: Arc::new(RwLock::new("".to_string())),
+            download_budget: Arc::new(RwLock::new(None)),
+            contact_sheet: Arc::new(RwLock::new((ContactSheetOptions { frames: 16, tile_width: 320 }, std::time::UNIX_EPOCH))),
             sync_state: Arc::new(RwLock::new(SyncState::default())),
+            settings_lock: Arc::new(tokio::sync::Mutex::new(())),
             notify: Arc::new(Notify::new()),
+            schedule_notify: Arc::new(Notify::new()),
+            stopping: CancellationToken::new(),
         });
         
         let q = queue.clone();
         tokio::spawn(async move {
             loop {
-                q.process_next().await;
-                q.notify.notified().await;
+                match q.process_next().await {
+                    // Out of budget or disk space: look again when the
+                    // period resets or after a while.
+                    Some(wait) => tokio::select! {
+                        _ = tokio::time::sleep(wait.min(SCHEDULE_MAX_WAIT)) => {}
+                        _ = q.notify.notified() => {}
+                    },
+                    None => q.notify.notified().await,
+                }
+            }
+        });
+
+        let q = queue.clone();
+        tokio::spawn(async move {
+            loop {
+                let wait = q.release_scheduled_jobs().await.min(SCHEDULE_MAX_WAIT);
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = q.schedule_notify.notified() => {}
+                }
+            }
+        });
+
+        let q = queue.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + ACTIVE_JOBS_SWEEP_INTERVAL, ACTIVE_JOBS_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                q.sweep_active_jobs().await;
+            }
+        });
+
+        queue
+    }
+
+    // Queues scheduled jobs that are due and returns how long until the
+    // next one is.
+    async fn release_scheduled_jobs(&self) -> std::time::Duration {
+        let now = Utc::now().timestamp_millis();
+        match self.db.release_scheduled_jobs(now).await {
+            Ok(jobs) if !jobs.is_empty() => {
+                for job in &jobs {
+                    info!("Scheduled job {} is due", job.id);
+                    self.enqueue(&job.id, job.priority);
+                }
+                self.notify.notify_one();
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to release scheduled jobs: {}", e),
+        }
+        match self.db.next_scheduled_at().await {
+            Ok(Some(at)) => std::time::Duration::from_millis((at - now).max(0) as u64),
+            _ => SCHEDULE_MAX_WAIT,
+        }
+    }
+
+    // Backstop for `ActiveJobGuard`: fails jobs left running without a task
+    // and frees slots held for jobs that are no longer running.
+    async fn sweep_active_jobs(&self) {
+        let running = match self.db.get_running_job_ids().await {
+            Ok(ids) => ids,
+            Err(e) => {
+                warn!("Failed to sweep active jobs: {}", e);
+                return;
+            }
+        };
+        for id in &running {
+            if !self.active_jobs.contains_key(id) {
+                if let Ok(true) = self.db.mark_crashed(id).await {
+                    warn!("Job {} had no download task, marked as crashed", id);
+                }
+            }
+        }
+
+        let active: Vec<String> = self.active_jobs.iter().map(|e| e.key().clone()).collect();
+        let mut freed = false;
+        for id in active {
+            // A task marks its job downloading only after taking the slot.
+            let orphaned = match self.db.get_job(&id).await {
+                Ok(Some(job)) => !matches!(job.status.as_str(), "queued" | "downloading" | "paused"),
+                Ok(None) => true,
+                Err(_) => false,
+            };
+            if orphaned {
+                if let Some((_, cancel)) = self.active_jobs.remove(&id) {
+                    cancel.cancel(CancelReason::User);
+                    warn!("Freed orphaned download slot of job {}", id);
+                    freed = true;
+                }
+            }
+        }
+        if freed {
+            self.notify.notify_one();
+        }
+    }
+
+    pub async fn load_initial_state(&self) {
+        if let Err(e) = self.db.reset_crashed_jobs().await {
+            error!("Failed to reset crashed jobs: {}", e);
+        }
+
+        if let Ok(jobs) = self.db.get_queued_jobs().await {
+            for job in jobs {
+                self.enqueue(&job.id, job.priority);
+            }
+        }
+        self.notify.notify_one();
+    }
+
+    // Queues each URL of a provisioning file (one per line, `#` comments)
+    // that has no job at all yet, so restarting doesn't queue it again.
+    pub async fn seed_from_file(&self, path: &str) {
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("Failed to read seed URLs file {}: {}", path, e);
+                return;
+            }
+        };
+        let mut seen = std::collections::HashSet::new();
+        let mut added = 0;
+        for url in contents.lines().map(str::trim) {
+            if url.is_empty() || url.starts_with('#') || !seen.insert(url) {
+                continue;
+            }
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                warn!("Skipping invalid seed URL {}", url);
+                continue;
+            }
+            match self.db.has_any_job(url).await {
+                Ok(false) => {}
+                Ok(true) => continue,
+                Err(e) => {
+                    error!("Failed to check seed URL {}: {}", url, e);
+                    continue;
+                }
+            }
+            match self.add_job(url.to_string(), JobOptions::default()).await {
+                Ok(_) => added += 1,
+                Err(e) => error!("Failed to add seed URL {}: {}", url, e),
+            }
+        }
+        if added > 0 {
+            info!("Queued {} URLs from {}", added, path);
+        }
+    }
+
+    // Behind every queued job of the same or higher priority.
+    fn enqueue(&self, id: &str, priority: i64) {
+        let mut q = self.queue.lock().unwrap();
+        if q.iter().any(|(_, queued)| queued == id) {
+            return;
+        }
+        let pos = q.iter().position(|(p, _)| *p < priority).unwrap_or(q.len());
+        q.insert(pos, (priority, id.to_string()));
+    }
+
+    pub async fn add_job(&self, url: String, mut options: JobOptions) -> Result<crate::db::Job, anyhow::Error> {
+        // Stored on the job so its files are still found after the mode changes.
+        if library_mode() == LibraryMode::FlatById && options.subfolder.is_none() {
+            options.subfolder = Some(LIBRARY_FOLDER.to_string());
+        }
+        let job = self.db.add_job(url, options).await?;
+        if job.status == "scheduled" {
+            self.schedule_notify.notify_one();
+        } else {
+            self.enqueue(&job.id, job.priority);
+            self.notify.notify_one();
+        }
+        Ok(job)
+    }
+    
+    // Stops every running download so its job is queued again on the next
+    // start, waiting up to `SHUTDOWN_GRACE` for the tasks to record that.
+    pub async fn shutdown(&self) {
+        self.stopping.cancel();
+        for entry in self.active_jobs.iter() {
+            entry.value().cancel(CancelReason::Shutdown);
+        }
+        let deadline = tokio::time::Instant::now() + SHUTDOWN_GRACE;
+        while !self.active_jobs.is_empty() && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    }
+
+    pub fn cancel_job(&self, id: &str) {
+        self.retry_proxies.remove(id);
+        if let Some(cancel) = self.active_jobs.get(id) {
+            info!("Cancelling active job {}", id);
+            cancel.cancel(CancelReason::User);
+            return;
+        }
+
+        let mut q = self.queue.lock().unwrap();
+        if let Some(pos) = q.iter().position(|(_, x)| x == id) {
+            q.remove(pos);
+            info!("Removed job {} from pending queue", id);
+        }
+    }
+    
+    pub async fn pause_job(&self, id: &str) -> Result<(), PauseError> {
+        self.set_paused(id, true).await
+    }
+
+    pub async fn resume_job(&self, id: &str) -> Result<(), PauseError> {
+        self.set_paused(id, false).await
+    }
+
+    // Paused jobs keep their concurrency slot; SIGSTOP only holds yt-dlp in
+    // place so the partial download survives.
+    async fn set_paused(&self, id: &str, pause: bool) -> Result<(), PauseError> {
+        if !cfg!(unix) {
+            return Err(PauseError::Unsupported);
+        }
+        let (pid, paused) = match self.children.get(id) {
+            Some(child) => (child.pid, child.paused.clone()),
+            None => return Err(PauseError::NotRunning),
+        };
+        if paused.load(Ordering::Relaxed) == pause {
+            return Err(if pause { PauseError::AlreadyPaused } else { PauseError::NotPaused });
+        }
+
+        signal_child(pid, pause)?;
+        paused.store(pause, Ordering::Relaxed);
+        let result = if pause { self.db.mark_paused(id).await } else { self.db.mark_resumed(id).await };
+        if let Err(e) = result {
+            error!("Failed to update paused state of job {}: {}", id, e);
+        }
+        info!("Job {} {}", id, if pause { "paused" } else { "resumed" });
+        Ok(())
+    }
+
+    // `Ok(None)` means there is no such job; database errors are passed on
+    // so callers don't report them as a missing job.
+    // With a `proxy`, only this attempt goes through it.
+    pub async fn retry_job(&self, id: &str, proxy: Option<String>) -> Result<Option<crate::db::Job>, anyhow::Error> {
+        let Some(job) = self.db.get_job(id).await? else {
+            return Ok(None);
+        };
+        match proxy {
+            Some(proxy) => self.retry_proxies.insert(id.to_string(), proxy),
+            None => self.retry_proxies.remove(id).map(|(_, p)| p),
+        };
+        self.db.increment_retry(id).await?;
+        self.enqueue(id, job.priority);
+        self.notify.notify_one();
+        self.db.get_job(id).await
+    }
+
+    pub async fn redownload_job(&self, id: &str) -> Result<Option<crate::db::Job>, anyhow::Error> {
+        let Some(job) = self.db.get_job(id).await? else {
+            return Ok(None);
+        };
+        self.db.redownload_job(id).await?;
+        self.enqueue(id, job.priority);
+        self.notify.notify_one();
+        self.db.get_job(id).await
+    }
+
+    // Queues a fresh job for the same URL and options, leaving the original
+    // record and its files untouched.
+    pub async fn redownload_job_as_new(&self, id: &str) -> Result<Option<crate::db::Job>, anyhow::Error> {
+        let Some(original) = self.db.get_job(id).await? else {
+            return Ok(None);
+        };
+        let options = JobOptions {
+            headers: original.headers.map(|h| h.0),
+            output_container: original.output_container,
+            subfolder: original.subfolder,
+            format: original.format,
+            audio_only: original.audio_only,
+            audio_format: original.audio_format,
+            priority: original.priority,
+            webhook_url: original.webhook_url,
+            max_height: original.max_height,
+            redownloaded_from: Some(original.id),
+            ..Default::default()
+        };
+        self.add_job(original.url, options).await.map(Some)
+    }
+
+    // Held while a settings update is applied so concurrent updates can't
+    // interleave field by field.
+    pub async fn lock_settings(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.settings_lock.lock().await
+    }
+
+    pub async fn set_max_concurrent(&self, limit: usize) {
+        if limit > 0 {
+            let mut w = self.max_concurrent.write().await;
+            *w = limit;
+            self.notify.notify_one();
+        }
+    }
+    
+    pub async fn get_max_concurrent(&self) -> usize {
+        *self.max_concurrent.read().await
+    }
+
+    pub async fn set_sync_destination(&self, dest: String) {
+        let mut w = self.sync_destination.write().await;
+        *w = dest;
+    }
+
+    pub async fn get_sync_destination(&self) -> String {
+        self.sync_destination.read().await.clone()
+    }
+
+    // Checks a newly saved destination in the background; a failure is only
+    // logged and reported in the sync status, it doesn't undo the change.
+    pub fn check_new_sync_destination(&self, dest: String) {
+        if dest.is_empty() || !self.capabilities.rclone {
+            return;
+        }
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let result = check_sync_destination(&dest).await;
+            if let Err(e) = &result {
+                warn!("Sync destination {} failed its check: {}", dest, e);
+            }
+            // Only report on the destination that is still configured.
+            if queue.get_sync_destination().await == dest {
+                queue.sync_state.write().await.destination_error = result.err();
             }
         });
+    }
+    
+    pub async fn set_sync_transfers(&self, transfers: usize) {
+        if transfers > 0 {
+            let mut w = self.sync_transfers.write().await;
+            *w = transfers.min(MAX_SYNC_TRANSFERS);
+        }
+    }
+
+    pub async fn get_sync_transfers(&self) -> usize {
+        *self.sync_transfers.read().await
+    }
+
+    pub async fn set_sync_bwlimit(&self, limit: String) {
+        let mut w = self.sync_bwlimit.write().await;
+        *w = limit;
+    }
+
+    pub async fn get_sync_bwlimit(&self) -> String {
+        self.sync_bwlimit.read().await.clone()
+    }
+
+    pub async fn set_resume_downloads(&self, enabled: bool) {
+        let mut w = self.resume_downloads.write().await;
+        *w = enabled;
+    }
+
+    pub async fn get_resume_downloads(&self) -> bool {
+        *self.resume_downloads.read().await
+    }
+
+    pub async fn set_keep_original(&self, enabled: bool) {
+        let mut w = self.keep_original.write().await;
+        *w = enabled;
+    }
+
+    pub async fn get_keep_original(&self) -> bool {
+        *self.keep_original.read().await
+    }
+
+    pub async fn set_archive_after_sync(&self, enabled: bool) {
+        let mut w = self.archive_after_sync.write().await;
+        *w = enabled;
+    }
+
+    pub async fn get_archive_after_sync(&self) -> bool {
+        *self.archive_after_sync.read().await
+    }
+
+    pub async fn set_verify_before_sync(&self, enabled: bool) {
+        let mut w = self.verify_before_sync.write().await;
+        *w = enabled;
+    }
+
+    pub async fn get_verify_before_sync(&self) -> bool {
+        *self.verify_before_sync.read().await
+    }
+
+    pub async fn set_write_description(&self, enabled: bool) {
+        let mut w = self.write_description.write().await;
+        *w = enabled;
+    }
+
+    pub async fn get_write_description(&self) -> bool {
+        *self.write_description.read().await
+    }
+
+    pub async fn set_write_comments(&self, enabled: bool) {
+        let mut w = self.write_comments.write().await;
+        *w = enabled;
+    }
+
+    pub async fn get_write_comments(&self) -> bool {
+        *self.write_comments.read().await
+    }
+
+    pub async fn set_probe_media(&self, enabled: bool) {
+        let mut w = self.probe_media.write().await;
+        *w = enabled;
+    }
+
+    pub async fn get_probe_media(&self) -> bool {
+        *self.probe_media.read().await
+    }
+
+    pub async fn set_require_stream(&self, kind: String) {
+        let mut w = self.require_stream.write().await;
+        *w = kind;
+    }
+
+    pub async fn get_require_stream(&self) -> String {
+        self.require_stream.read().await.clone()
+    }
+
+    pub async fn set_stall_timeout_seconds(&self, seconds: u64) {
+        let mut w = self.stall_timeout_seconds.write().await;
+        *w = seconds;
+    }
+
+    pub async fn get_stall_timeout_seconds(&self) -> u64 {
+        *self.stall_timeout_seconds.read().await
+    }
+
+    pub async fn set_progress_write_interval_ms(&self, ms: u64) {
+        let mut w = self.progress_write_interval_ms.write().await;
+        *w = ms;
+    }
+
+    pub async fn get_progress_write_interval_ms(&self) -> u64 {
+        *self.progress_write_interval_ms.read().await
+    }
+
+    // Job changes as they are written, and a token cancelled on shutdown.
+    pub fn subscribe(&self) -> (broadcast::Receiver<JobUpdate>, CancellationToken) {
+        (self.db.subscribe(), self.stopping.clone())
+    }
+
+    pub fn live_progress(&self, id: &str) -> Option<(i64, Option<i64>)> {
+        self.live_progress.get(id).map(|p| *p)
+    }
+
+    pub async fn set_use_download_archive(&self, enabled: bool) {
+        let mut w = self.use_download_archive.write().await;
+        *w = enabled;
+    }
+
+    pub async fn get_use_download_archive(&self) -> bool {
+        *self.use_download_archive.read().await
+    }
+
+    pub async fn set_low_disk_threshold_bytes(&self, bytes: u64) {
+        let mut w = self.low_disk_threshold_bytes.write().await;
+        *w = bytes;
+    }
+
+    pub async fn get_low_disk_threshold_bytes(&self) -> u64 {
+        *self.low_disk_threshold_bytes.read().await
+    }
+
+    // Whether free space on the data volume is below the threshold. A
+    // volume that can't be stat'ed doesn't count as low.
+    pub async fn disk_is_low(&self) -> bool {
+        let threshold = self.get_low_disk_threshold_bytes().await;
+        threshold > 0 && available_space().is_ok_and(|free| free < threshold)
+    }
+
+    pub async fn set_ffmpeg_threads(&self, threads: usize) {
+        let mut w = self.ffmpeg_threads.write().await;
+        *w = threads.min(MAX_FFMPEG_THREADS);
+    }
+
+    pub async fn get_ffmpeg_threads(&self) -> usize {
+        *self.ffmpeg_threads.read().await
+    }
+
+    pub async fn set_max_height(&self, height: u32) {
+        let mut w = self.max_height.write().await;
+        *w = height;
+    }
 
-        queue
+    pub async fn get_max_height(&self) -> u32 {
+        *self.max_height.read().await
     }
 
-    pub async fn load_initial_state(&self) {
-        if let Err(e) = self.db.reset_crashed_jobs().await {
-            error!("Failed to reset crashed jobs: {}", e);
-        }
+    pub async fn set_output_container(&self, container: String) {
+        let mut w = self.output_container.write().await;
+        *w = container;
+    }
 
-        if let Ok(jobs) = self.db.get_queued_jobs().await {
-            let mut q = self.queue.lock().unwrap();
-            for job in jobs {
-                if !q.contains(&job.id) {
-                    q.push_back(job.id);
-                }
-            }
-        }
-        self.notify.notify_one();
+    pub async fn get_output_container(&self) -> String {
+        self.output_container.read().await.clone()
     }
 
-    pub async fn add_job(&self, url: String) -> Result<crate::db::Job, anyhow::Error> {
-        let job = self.db.add_job(url).await?;
-        {
-            let mut q = self.queue.lock().unwrap();
-            q.push_back(job.id.clone());
-        }
-        self.notify.notify_one();
-        Ok(job)
+    pub async fn set_extra_ytdlp_args(&self, args: Vec<String>) {
+        let mut w = self.extra_ytdlp_args.write().await;
+        *w = args;
     }
-    
-    pub fn cancel_job(&self, id: &str) {
-        if let Some(token) = self.active_jobs.get(id) {
-            info!("Cancelling active job {}", id);
-            token.cancel();
-            return;
-        }
 
-        let mut q = self.queue.lock().unwrap();
-        if let Some(pos) = q.iter().position(|x| x == id) {
-            q.remove(pos);
-            info!("Removed job {} from pending queue", id);
+    pub async fn get_extra_ytdlp_args(&self) -> Vec<String> {
+        self.extra_ytdlp_args.read().await.clone()
+    }
+
+    pub async fn set_extra_rclone_args(&self, args: Vec<String>) {
+        let mut w = self.extra_rclone_args.write().await;
+        *w = args;
+    }
+
+    pub async fn get_extra_rclone_args(&self) -> Vec<String> {
+        self.extra_rclone_args.read().await.clone()
+    }
+
+    pub async fn set_webhook_url(&self, url: String) {
+        let mut w = self.webhook_url.write().await;
+        *w = url;
+    }
+
+    pub async fn get_webhook_url(&self) -> String {
+        self.webhook_url.read().await.clone()
+    }
+
+    pub async fn set_contact_sheet_frames(&self, frames: u32) {
+        let mut w = self.contact_sheet.write().await;
+        if w.0.frames != frames {
+            *w = (ContactSheetOptions { frames, ..w.0 }, std::time::SystemTime::now());
         }
     }
-    
-    pub async fn retry_job(&self, id: &str) -> Option<crate::db::Job> {
-        if let Ok(Some(_)) = self.db.get_job(id).await {
-            if self.db.increment_retry(id).await.is_ok() {
-                 {
-                    let mut q = self.queue.lock().unwrap();
-                    q.push_back(id.to_string());
-                }
-                self.notify.notify_one();
-                return self.db.get_job(id).await.ok().flatten();
-            }
+
+    pub async fn set_contact_sheet_tile_width(&self, tile_width: u32) {
+        let mut w = self.contact_sheet.write().await;
+        if w.0.tile_width != tile_width {
+            *w = (ContactSheetOptions { tile_width, ..w.0 }, std::time::SystemTime::now());
         }
-        None
     }
 
-    pub async fn redownload_job(&self, id: &str) -> Option<crate::db::Job> {
-        if let Ok(Some(_)) = self.db.get_job(id).await {
-            if self.db.redownload_job(id).await.is_ok() {
-                 {
-                    let mut q = self.queue.lock().unwrap();
-                    q.push_back(id.to_string());
-                }
-                self.notify.notify_one();
-                return self.db.get_job(id).await.ok().flatten();
+    // The options and when they last changed.
+    pub async fn get_contact_sheet(&self) -> (ContactSheetOptions, std::time::SystemTime) {
+        *self.contact_sheet.read().await
+    }
+
+    pub async fn set_download_budget(&self, budget: Option<DownloadBudget>) {
+        let mut w = self.download_budget.write().await;
+        *w = budget;
+    }
+
+    // `None` when no budget is configured or its usage can't be read.
+    pub async fn budget_status(&self) -> Option<BudgetStatus> {
+        let budget = (*self.download_budget.read().await)?;
+        let now = Utc::now().timestamp_millis();
+        let (period_start, used) = match self.db.budget_usage(now, budget.period_ms).await {
+            Ok(usage) => usage,
+            Err(e) => {
+                error!("Failed to read download budget: {}", e);
+                return None;
             }
-        }
-        None
+        };
+        let used = used.max(0) as u64;
+        Some(BudgetStatus {
+            limit_bytes: budget.limit_bytes,
+            used_bytes: used,
+            remaining_bytes: budget.limit_bytes.saturating_sub(used),
+            period_start,
+            resets_at: period_start + budget.period_ms,
+            exhausted: used >= budget.limit_bytes,
+        })
     }
 
-    pub async fn set_max_concurrent(&self, limit: usize) {
-        if limit > 0 {
-            let mut w = self.max_concurrent.write().await;
-            *w = limit;
-            self.notify.notify_one();
+    async fn download_options(&self) -> DownloadOptions {
+        DownloadOptions {
+            resume: self.get_resume_downloads().await,
+            keep_original: self.get_keep_original().await,
+            write_description: self.get_write_description().await,
+            write_comments: self.get_write_comments().await,
+            probe_media: self.get_probe_media().await && self.capabilities.ffprobe,
+            verify: self.get_verify_before_sync().await && self.capabilities.ffprobe,
+            require_stream: if self.capabilities.ffprobe { self.get_require_stream().await } else { "any".to_string() },
+            stall_timeout_seconds: self.get_stall_timeout_seconds().await,
+            progress_write_interval_ms: self.get_progress_write_interval_ms().await,
+            use_archive: self.get_use_download_archive().await,
+            ffmpeg_threads: self.get_ffmpeg_threads().await,
+            output_container: self.get_output_container().await,
+            subfolder: None,
+            format: None,
+            audio_only: false,
+            audio_format: None,
+            max_height: self.get_max_height().await,
+            extra_args: self.get_extra_ytdlp_args().await,
+            proxy: None,
+            headers: HashMap::new(),
+            webhook_url: Some(self.get_webhook_url().await).filter(|u| !u.is_empty() && self.capabilities.curl),
+            staged: false,
         }
     }
-    
-    pub async fn get_max_concurrent(&self) -> usize {
-        *self.max_concurrent.read().await
+
+    pub fn last_synced_at(&self) -> Option<DateTime<Utc>> {
+        std::fs::metadata(SYNC_MARKER_FILE).and_then(|meta| meta.modified()).ok().map(Into::into)
     }
 
-    pub async fn set_sync_destination(&self, dest: String) {
-        let mut w = self.sync_destination.write().await;
-        *w = dest;
+    // Deletes the job's local files and marks it `archived`, so the
+    // missing-file scan leaves it alone. Files already gone are fine.
+    pub async fn archive_job(&self, job: &Job) -> Result<(), anyhow::Error> {
+        let folder = job_folder(job);
+        for name in job.all_files() {
+            remove_job_file(&self.file_index, &folder, &name).await?;
+        }
+        self.db.mark_archived(&job.id).await
     }
 
-    pub async fn get_sync_destination(&self) -> String {
-        self.sync_destination.read().await.clone()
+    // Archives every unstarred finished job that completed before the sync
+    // that just succeeded started, so it is known to be on the remote.
+    async fn archive_synced_jobs(&self, sync_started_at: DateTime<Utc>, verified_only: bool) {
+        let jobs = match self.db.get_jobs_for_missing_scan().await {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                error!("Failed to load jobs to archive after sync: {}", e);
+                return;
+            }
+        };
+        let cutoff = sync_started_at.timestamp_millis();
+        let mut archived = 0;
+        let synced = |job: &Job| !verified_only || job.verified == Some(true);
+        for job in jobs.iter().filter(|job| !job.starred && job.completed_at.unwrap_or(job.created_at) < cutoff && synced(job)) {
+            match self.archive_job(job).await {
+                Ok(()) => archived += 1,
+                Err(e) => error!("Failed to archive job {}: {}", job.id, e),
+            }
+        }
+        info!("Archived {} synced jobs", archived);
     }
-    
+
     pub async fn get_sync_state(&self) -> SyncState {
-        let mut state = self.sync_state.read().await.clone();
+        // The marker is written by the sync task while it holds the write
+        // lock, so reading it under the read lock never sees it mid-update.
+        let guard = self.sync_state.read().await;
+        let mut state = guard.clone();
         
         if Path::new(SYNC_MARKER_FILE).exists() {
              if let Ok(meta) = std::fs::metadata(SYNC_MARKER_FILE) {
@@ -179,144 +1545,301 @@ impl DownloadQueue {
         } else {
              state.unsynced_count = self.file_index.count_files_after(DateTime::<Utc>::from(std::time::SystemTime::UNIX_EPOCH));
         }
+        drop(guard);
         
         state
     }
 
     pub async fn run_sync(&self) -> Result<String, anyhow::Error> {
-        {
-            let state = self.sync_state.read().await;
-            if state.status == "running" {
-                return Ok("Sync is already running".to_string());
-            }
+        // Hold the write lock from the "already running" check until the
+        // status is set, so concurrent calls cannot both start rclone.
+        let mut state = self.sync_state.write().await;
+        if state.status == "running" {
+            return Ok("Sync is already running".to_string());
+        }
+
+        if !self.capabilities.rclone {
+            return Err(anyhow::anyhow!("rclone not installed"));
+        }
+        if !data_root_available() {
+            return Err(anyhow::anyhow!("Data root unavailable"));
+        }
+        let verify = self.get_verify_before_sync().await;
+        if verify && !self.capabilities.ffprobe {
+            return Err(anyhow::anyhow!("ffprobe is required to verify files before syncing"));
         }
 
         let dest = self.get_sync_destination().await;
-        let cwd = std::env::current_dir()?;
-        let data_dir = cwd.join("data"); 
-        
-        info!("Starting cloud sync to {}", dest);
-        
-        {
-            let mut state = self.sync_state.write().await;
-            state.status = "running".to_string();
-            state.logs.clear();
-            state.logs.push(format!("Starting sync to {}...", dest));
-            state.error = None;
+        let started_at = Utc::now();
+        if verify {
+            // Files that were never checked are probed first, which can take
+            // a while, so rclone is started from the background task.
+            info!("Verifying files before cloud sync to {}", dest);
+            reset_sync_state(&mut state, &dest);
+            drop(state);
+            let queue = self.clone();
+            let message = format!("Sync started to {}", dest);
+            tokio::spawn(async move {
+                let child = match queue.write_sync_file_list().await {
+                    Ok(()) => queue.rclone_command(&dest, true).await.spawn().map_err(|e| anyhow::anyhow!("Failed to start rclone: {}", e)),
+                    Err(e) => Err(e),
+                };
+                match child {
+                    Ok(child) => {
+                        queue.follow_sync(child, dest, started_at, true).await;
+                        let _ = tokio::fs::remove_file(SYNC_FILES_LIST).await;
+                    }
+                    Err(e) => {
+                        error!("Cloud sync failed: {}", e);
+                        let mut s = queue.sync_state.write().await;
+                        s.status = "error".to_string();
+                        s.error = Some(e.to_string());
+                        s.logs.push(e.to_string());
+                    }
+                }
+            });
+            return Ok(message);
         }
+
+        let child = self
+            .rclone_command(&dest, false)
+            .await
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start rclone: {}", e))?;
         
-        let dest_clone = dest.clone();
-        let state_clone = self.sync_state.clone();
+        info!("Starting cloud sync to {}", dest);
+        reset_sync_state(&mut state, &dest);
+        drop(state);
         
+        let message = format!("Sync started to {}", dest);
+        let queue = self.clone();
         tokio::spawn(async move {
-            let mut child = Command::new("rclone")
-                .arg("copy")
-                .arg(&data_dir)
-                .arg(&dest_clone)
-                .arg("--ignore-existing")
-                .arg("--transfers=4")
-                .arg("--exclude")
-                .arg("jobs.sqlite*")
-                .arg("--exclude")
-                .arg(".last_sync")
-                .arg("-v")
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()
-                .expect("Failed to spawn rclone");
-
-            let stdout = child.stdout.take().expect("Failed to open stdout");
-            let stderr = child.stderr.take().expect("Failed to open stderr");
-            
-            let state_logger = state_clone.clone();
-            
-            let stderr_task = tokio::spawn(async move {
-                let mut reader = BufReader::new(stderr).lines();
-                while let Ok(Some(line)) = reader.next_line().await {
-                    let mut s = state_logger.write().await;
-                    if s.logs.len() > 100 { s.logs.remove(0); }
-                    s.logs.push(line);
+            queue.follow_sync(child, dest, started_at, false).await;
+        });
+        Ok(message)
+    }
+
+    // With `files_from`, only the files listed in SYNC_FILES_LIST are copied.
+    async fn rclone_command(&self, dest: &str, files_from: bool) -> Command {
+        let transfers = self.get_sync_transfers().await;
+        let bwlimit = self.get_sync_bwlimit().await;
+        let extra_args = self.get_extra_rclone_args().await;
+        let data_dir = std::env::current_dir().unwrap_or_default().join("data");
+
+        let mut cmd = Command::new("rclone");
+        cmd.arg("copy")
+            .arg(&data_dir)
+            .arg(dest)
+            .arg("--ignore-existing")
+            .arg(format!("--transfers={}", transfers))
+            .arg("--exclude")
+            .arg("jobs.sqlite*")
+            .arg("--exclude")
+            .arg(".last_sync")
+            .arg("--exclude")
+            .arg(".archive")
+            .arg("--exclude")
+            .arg(".uploads/**")
+            .arg("--exclude")
+            .arg(".redownload/**")
+            .arg("--exclude")
+            .arg(".zips/**")
+            .arg("-v");
+        if files_from {
+            cmd.arg("--files-from").arg(SYNC_FILES_LIST);
+        }
+        if !bwlimit.is_empty() {
+            cmd.arg(format!("--bwlimit={}", bwlimit));
+        }
+        // After the excludes, which take precedence as rclone applies the
+        // first matching filter rule.
+        cmd.args(&extra_args);
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        cmd
+    }
+
+    // Verifies finished jobs that haven't been checked yet and lists the
+    // files (and sidecars) of the verified ones, relative to the data root,
+    // in SYNC_FILES_LIST. Every other indexed file on the data root is held
+    // back and reported in the sync state.
+    async fn write_sync_file_list(&self) -> Result<(), anyhow::Error> {
+        let jobs = self.db.get_jobs_for_missing_scan().await?;
+        let mut included: HashSet<PathBuf> = HashSet::new();
+        let mut checked = 0;
+        for job in jobs {
+            let folder = job_folder(&job);
+            let files = job.all_files();
+            let verified = match job.verified {
+                Some(verified) => verified,
+                None => {
+                    let paths: Vec<PathBuf> = files.iter().map(|name| Path::new(DATA_ROOT).join(&folder).join(name)).collect();
+                    // Gone or moved to the cold tier, so not synced anyway.
+                    if paths.is_empty() || !paths.iter().all(|p| p.is_file()) {
+                        continue;
+                    }
+                    let verified = verify_files(&paths).await;
+                    self.db.set_verified(&job.id, verified).await?;
+                    checked += 1;
+                    verified
                 }
-            });
-            
-            let state_logger_out = state_clone.clone();
-             let stdout_task = tokio::spawn(async move {
-                let mut reader = BufReader::new(stdout).lines();
-                while let Ok(Some(line)) = reader.next_line().await {
-                    let mut s = state_logger_out.write().await;
-                    if s.logs.len() > 100 { s.logs.remove(0); }
-                    s.logs.push(line);
+            };
+            if verified {
+                for name in files {
+                    let relative = Path::new(&folder).join(name);
+                    included.extend(sidecar_paths(&relative).into_iter().filter(|p| Path::new(DATA_ROOT).join(p).is_file()));
+                    included.insert(relative);
                 }
-            });
+            }
+        }
+
+        let held_back: Vec<String> = self
+            .file_index
+            .all_files()
+            .into_iter()
+            .filter(|item| !item.cold)
+            .filter_map(|item| Some(Path::new(&item.path).strip_prefix(DATA_ROOT).ok()?.to_path_buf()))
+            .filter(|relative| !included.contains(relative) && !relative.to_string_lossy().starts_with('.'))
+            .map(|relative| relative.to_string_lossy().to_string())
+            .collect();
+        let mut list = String::new();
+        for path in &included {
+            list.push_str(&path.to_string_lossy());
+            list.push('\n');
+        }
+        tokio::fs::write(SYNC_FILES_LIST, list).await?;
+
+        let mut s = self.sync_state.write().await;
+        s.logs.push(format!("Verified {} new jobs; holding back {} unverified files", checked, held_back.len()));
+        s.held_back_count = Some(held_back.len());
+        s.held_back_files = held_back.into_iter().take(MAX_UPLOADED_FILES).collect();
+        Ok(())
+    }
+
+    // `verified_only` when only verified jobs were synced.
+    async fn follow_sync(&self, mut child: tokio::process::Child, dest: String, started_at: DateTime<Utc>, verified_only: bool) {
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        
+        let state_logger = self.sync_state.clone();
+        let re_copied = Regex::new(r"INFO\s*:\s*(.+): Copied \((?:new|replaced existing)\)").unwrap();
+        let re_copied_out = re_copied.clone();
+        
+        let stderr_task = tokio::spawn(async move {
+            let Some(stderr) = stderr else { return };
+            // Raw lines, since `lines()` stops at the first file name that
+            // isn't UTF-8 and leaves rclone writing to a closed pipe.
+            let mut reader = BufReader::new(stderr).split(b'\n');
+            while let Ok(Some(raw)) = reader.next_segment().await {
+                let line = String::from_utf8_lossy(&raw).trim_end_matches('\r').to_string();
+                let mut s = state_logger.write().await;
+                record_sync_line(&mut s, line, &re_copied);
+            }
+        });
+        
+        let state_logger_out = self.sync_state.clone();
+         let stdout_task = tokio::spawn(async move {
+            let Some(stdout) = stdout else { return };
+            let mut reader = BufReader::new(stdout).split(b'\n');
+            while let Ok(Some(raw)) = reader.next_segment().await {
+                let line = String::from_utf8_lossy(&raw).trim_end_matches('\r').to_string();
+                let mut s = state_logger_out.write().await;
+                record_sync_line(&mut s, line, &re_copied_out);
+            }
+        });
 
-            match child.wait().await {
-                Ok(status) => {
-                     let _ = stderr_task.await;
-                     let _ = stdout_task.await;
-                     
-                     let mut s = state_clone.write().await;
-                     if status.success() {
-                         s.status = "idle".to_string();
-                         s.logs.push("Sync completed successfully.".to_string());
-                         s.unsynced_count = 0;
-                         let _ = File::create(SYNC_MARKER_FILE);
-                         if let Ok(meta) = std::fs::metadata(SYNC_MARKER_FILE) {
-                             if let Ok(mod_time) = meta.modified() {
-                                 s.last_run = Some(mod_time.into());
-                             }
+        match child.wait().await {
+            Ok(status) => {
+                 let _ = stderr_task.await;
+                 let _ = stdout_task.await;
+                 
+                 let mut s = self.sync_state.write().await;
+                 let (transferred, skipped) = parse_rclone_stats(&s.logs);
+                 s.files_transferred = transferred;
+                 s.files_skipped = skipped;
+                 if status.success() {
+                     s.status = "idle".to_string();
+                     s.logs.push("Sync completed successfully.".to_string());
+                     s.unsynced_count = 0;
+                     let _ = File::create(SYNC_MARKER_FILE);
+                     if let Ok(meta) = std::fs::metadata(SYNC_MARKER_FILE) {
+                         if let Ok(mod_time) = meta.modified() {
+                             s.last_run = Some(mod_time.into());
                          }
-                         info!("Cloud sync completed successfully to {}", dest_clone);
-                     } else {
-                         s.status = "error".to_string();
-                         let code = status.code().unwrap_or(-1);
-                         let msg = format!("Sync failed with exit code {}", code);
-                         s.error = Some(msg.clone());
-                         s.logs.push(msg);
-                         error!("Cloud sync failed");
                      }
-                }
-                Err(e) => {
-                     let mut s = state_clone.write().await;
+                     info!("Cloud sync completed successfully to {}", dest);
+                     drop(s);
+                     if self.get_archive_after_sync().await && data_root_available() {
+                         self.archive_synced_jobs(started_at, verified_only).await;
+                     }
+                 } else {
                      s.status = "error".to_string();
-                     s.error = Some(e.to_string());
-                     s.logs.push(format!("Process error: {}", e));
-                }
+                     let code = status.code().unwrap_or(-1);
+                     let msg = format!("Sync failed with exit code {}", code);
+                     s.error = Some(msg.clone());
+                     s.logs.push(msg);
+                     error!("Cloud sync failed");
+                 }
             }
-        });
-
-        Ok(format!("Sync started to {}", dest))
+            Err(e) => {
+                 let mut s = self.sync_state.write().await;
+                 s.status = "error".to_string();
+                 s.error = Some(e.to_string());
+                 s.logs.push(format!("Process error: {}", e));
+            }
+        }
     }
-    
-    pub async fn has_job(&self, url: &str) -> bool {
-        self.db.has_active_job(url).await.unwrap_or(false)
+
+    pub async fn has_job(&self, url: &str) -> Result<bool, anyhow::Error> {
+        self.db.has_active_job(url).await
     }
 
-    async fn process_next(&self) {
+    // Starts queued jobs while slots are free. Returns how long until the
+    // budget period resets when jobs are held back by an exhausted budget.
+    // Nothing starts once shutdown has begun; the jobs stay queued for the
+    // next boot instead of being killed at the end of the grace period.
+    async fn process_next(&self) -> Option<std::time::Duration> {
         let max = *self.max_concurrent.read().await;
         
         loop {
+            if self.stopping.is_cancelled() {
+                break;
+            }
             let active_count = self.active_jobs.len();
             if active_count >= max {
                 break;
             }
+            if self.queue.lock().unwrap().is_empty() {
+                break;
+            }
+            if let Some(status) = self.budget_status().await.filter(|s| s.exhausted) {
+                let wait = status.resets_at - Utc::now().timestamp_millis();
+                return Some(std::time::Duration::from_millis(wait.max(1000) as u64));
+            }
+            // Starting downloads on a nearly full disk would only fail them.
+            if self.disk_is_low().await {
+                return Some(SCHEDULE_MAX_WAIT);
+            }
 
             let next_id = {
                 let mut q = self.queue.lock().unwrap();
-                q.pop_front()
+                q.pop_front().map(|(_, id)| id)
             };
 
             if let Some(id) = next_id {
-                if let Ok(Some(job)) = self.db.get_job(&id).await {
-                     if job.status == "queued" {
-                         self.start_download_task(job).await;
-                     } else {
-                         continue;
-                     }
+                match self.db.get_job(&id).await {
+                    Ok(Some(job)) if job.status == "queued" => self.start_download_task(job).await,
+                    // Deleted or finished without starting, so a proxy set
+                    // for its retry is never used.
+                    Ok(_) => {
+                        self.retry_proxies.remove(&id);
+                    }
+                    Err(_) => {}
                 }
             } else {
                 break;
             }
         }
+        None
     }
 
     async fn start_download_task(&self, job: crate::db::Job) {
@@ -325,163 +1848,453 @@ impl DownloadQueue {
         let db = self.db.clone();
         let file_index = self.file_index.clone();
         let active_jobs = self.active_jobs.clone();
+        let children = self.children.clone();
+        let live_progress = self.live_progress.clone();
         let notify = self.notify.clone();
-        let token = CancellationToken::new();
+        let cancel = JobCancel::new();
+        // A job that still has files from an earlier run is being
+        // redownloaded in place; those files are replaced only on success.
+        let previous_files = job.all_files();
+        let previous_folder = job_folder(&job);
+        let budget = *self.download_budget.read().await;
+        let mut options = self.download_options().await;
+        options.staged = !previous_files.is_empty();
+        options.headers = job.headers.map(|h| h.0).unwrap_or_default();
+        if let Some(container) = job.output_container {
+            options.output_container = container;
+        }
+        options.subfolder = job.subfolder;
+        options.format = job.format;
+        options.proxy = self.retry_proxies.remove(&id).map(|(_, proxy)| proxy);
+        options.audio_only = job.audio_only;
+        options.audio_format = job.audio_format;
+        if let Some(height) = job.max_height {
+            options.max_height = u32::try_from(height).unwrap_or(0);
+        }
+        if job.webhook_url.is_some() && self.capabilities.curl {
+            options.webhook_url = job.webhook_url;
+        }
         
-        active_jobs.insert(id.clone(), token.clone());
+        active_jobs.insert(id.clone(), cancel.clone());
         let _ = db.mark_downloading(&id).await;
         info!("Starting job {} for {}", id, url);
 
         tokio::spawn(async move {
-            let result = Self::run_yt_dlp(&id, &url, &db, token.clone()).await;
+            let _guard = ActiveJobGuard {
+                id: id.clone(),
+                active_jobs,
+                children: children.clone(),
+                live_progress: live_progress.clone(),
+                notify,
+            };
+            let result = Self::run_yt_dlp(&id, &url, &db, cancel.token.clone(), &children, &live_progress, &options).await;
+            children.remove(&id);
+            live_progress.remove(&id);
+            // An unwanted file (e.g. the image of a photo post) is removed
+            // rather than kept as an orphan next to a failed job.
+            let result = match result {
+                Ok(output) => match check_required_stream(&id, &output, &options).await {
+                    Ok(()) => Ok(output),
+                    Err(e) => {
+                        for name in &output.files {
+                            let path = output.folder.join(name);
+                            let _ = tokio::fs::remove_file(&path).await;
+                            for sidecar in sidecar_paths(&path) {
+                                let _ = tokio::fs::remove_file(sidecar).await;
+                            }
+                        }
+                        Err(e.into())
+                    }
+                },
+                Err(e) => Err(e),
+            };
             
             match result {
-                Ok(filename) => {
-                     let folder = get_today_folder();
-                     let full_path = folder.join(&filename);
-                     let _ = db.mark_done(&id, &filename).await;
-                     file_index.add_file(&full_path);
-                     info!("Job {} completed. File: {}", id, filename);
+                Ok(output) => {
+                     if options.probe_media {
+                         if let Some(info) = crate::media::probe(&output.folder.join(&output.filename)).await {
+                             let _ = db.set_media_info(&id, &info).await;
+                         }
+                     }
+                     let _ = db.mark_done(&id, &output.filename, &output.files).await;
+                     if options.verify {
+                         let paths: Vec<PathBuf> = output.files.iter().map(|name| output.folder.join(name)).collect();
+                         let verified = verify_files(&paths).await;
+                         if !verified {
+                             warn!("Job {}: ffprobe could not verify its files, they won't be synced", id);
+                         }
+                         let _ = db.set_verified(&id, verified).await;
+                     }
+                     for name in &output.files {
+                         file_index.add_file(&output.folder.join(name));
+                     }
+                     if let Some(budget) = budget {
+                         let mut bytes = 0;
+                         for name in &output.files {
+                             bytes += tokio::fs::metadata(output.folder.join(name)).await.map(|m| m.len()).unwrap_or(0);
+                         }
+                         if let Err(e) = db.add_budget_bytes(bytes as i64, Utc::now().timestamp_millis(), budget.period_ms).await {
+                             error!("Failed to record downloaded bytes of job {}: {}", id, e);
+                         }
+                     }
+                     let same_folder = output.folder == Path::new(DATA_ROOT).join(&previous_folder);
+                     for name in previous_files.iter().filter(|name| !(same_folder && output.files.contains(name))) {
+                         if let Err(e) = remove_job_file(&file_index, &previous_folder, name).await {
+                             warn!("Job {}: failed to remove replaced file {}: {}", id, name, e);
+                         }
+                     }
+                     info!("Job {} completed. Files: {}", id, output.files.join(", "));
                 }
                 Err(e) => {
                     let msg = e.to_string();
-                    if msg.contains("cancelled") {
+                    let cancelled = matches!(e.downcast_ref::<DownloadError>(), Some(DownloadError::Cancelled));
+                    if options.staged && !(cancelled && options.resume) {
+                        let _ = tokio::fs::remove_dir_all(Path::new(REDOWNLOAD_DIR).join(&id)).await;
+                    }
+                    if cancelled && cancel.reason() == CancelReason::Shutdown {
+                         let _ = db.requeue_interrupted(&id).await;
+                         info!("Job {} interrupted by shutdown, queued again", id);
+                    } else if cancelled {
                          if let Ok(true) = db.check_job_exists(&id).await {
                              let _ = db.mark_failed(&id, "Cancelled").await;
                          }
                          info!("Job {} cancelled", id);
+                    } else if let Some(err @ DownloadError::Stalled(_)) = e.downcast_ref::<DownloadError>() {
+                        let _ = db.mark_failed_with_kind(&id, &msg, Some(err.kind())).await;
+                        error!("Job {} stalled: {}", id, msg);
+                    } else if let Some(err @ DownloadError::Archived) = e.downcast_ref::<DownloadError>() {
+                        let _ = db.mark_failed_with_kind(&id, &msg, Some(err.kind())).await;
+                        info!("Job {} skipped: {}", id, msg);
+                    } else if let Some(err @ DownloadError::MissingStream(_)) = e.downcast_ref::<DownloadError>() {
+                        let _ = db.mark_failed_with_kind(&id, &msg, Some(err.kind())).await;
+                        warn!("Job {} failed: {}", id, msg);
                     } else {
                         let _ = db.mark_failed(&id, &msg).await;
                         error!("Job {} failed: {}", id, msg);
                     }
                 }
             }
-            
-            active_jobs.remove(&id);
-            notify.notify_one();
+
+            // Cancelled and deleted jobs are not reported.
+            if let Some(hook) = options.webhook_url {
+                if let Ok(Some(job)) = db.get_job(&id).await {
+                    if job.status == "done" || (job.status == "failed" && job.error.as_deref() != Some("Cancelled")) {
+                        tokio::spawn(async move { crate::webhook::send(&hook, job).await });
+                    }
+                }
+            }
         });
     }
 
-    async fn run_yt_dlp(id: &str, url: &str, db: &Db, token: CancellationToken) -> Result<String, anyhow::Error> {
+    fn yt_dlp_command() -> Result<Command, anyhow::Error> {
         let cwd = std::env::current_dir()?;
         let python_path = cwd.join("venv_python/bin/python");
         let yt_dlp_path = cwd.join("bin/yt-dlp");
-        let output_folder = get_today_folder();
-        let template = output_folder.join("%(title)s.%(ext)s");
 
-        let mut child = Command::new("nice")
-            .arg("-n")
+        let mut cmd = Command::new("nice");
+        cmd.arg("-n")
             .arg("10")
             .arg(python_path)
             .arg(yt_dlp_path)
-            .arg("--newline")
             .arg("--impersonate")
             .arg("chrome")
             .arg("--no-check-certificates")
             .arg("--add-header")
             .arg("Referer:https://www.tiktok.com/")
+            .env("PYTHONUTF8", "1")
+            .env("PYTHONIOENCODING", "utf-8");
+        Ok(cmd)
+    }
+
+    pub async fn list_formats(&self, url: &str) -> Result<Vec<FormatInfo>, anyhow::Error> {
+        if let Some(entry) = self.formats_cache.get(url) {
+            if entry.0.elapsed() < FORMATS_CACHE_TTL {
+                return Ok(entry.1.clone());
+            }
+        }
+
+        if !self.capabilities.yt_dlp {
+            return Err(anyhow::anyhow!("yt-dlp not installed"));
+        }
+
+        let mut cmd = Self::yt_dlp_command()?;
+        cmd.arg("--dump-single-json")
+            .arg("--no-playlist")
+            .arg("--skip-download")
+            .arg(url)
+            .kill_on_drop(true);
+
+        let output = tokio::time::timeout(std::time::Duration::from_secs(60), cmd.output())
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out fetching formats"))??;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("unknown error");
+            return Err(anyhow::anyhow!("yt-dlp failed: {}", reason.trim()));
+        }
+
+        let info: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        let formats: Vec<FormatInfo> = info["formats"]
+            .as_array()
+            .map(|list| list.iter().map(FormatInfo::from_json).collect())
+            .unwrap_or_default();
+
+        self.formats_cache.retain(|_, (at, _)| at.elapsed() < FORMATS_CACHE_TTL);
+        self.formats_cache.insert(url.to_string(), (std::time::Instant::now(), formats.clone()));
+        Ok(formats)
+    }
+
+    async fn run_yt_dlp(
+        id: &str,
+        url: &str,
+        db: &Db,
+        token: CancellationToken,
+        children: &DashMap<String, RunningChild>,
+        live_progress: &Arc<DashMap<String, (i64, Option<i64>)>>,
+        options: &DownloadOptions,
+    ) -> Result<DownloadOutput, anyhow::Error> {
+        let output_folder = match &options.subfolder {
+            Some(subfolder) => {
+                let folder = Path::new(DATA_ROOT).join(subfolder);
+                tokio::fs::create_dir_all(&folder).await?;
+                folder
+            }
+            None => get_today_folder(),
+        };
+        let flat = library_mode() == LibraryMode::FlatById;
+        let staging = options.staged.then(|| Path::new(REDOWNLOAD_DIR).join(id));
+        if let Some(staging) = &staging {
+            tokio::fs::create_dir_all(staging).await?;
+        }
+        if flat && staging.is_none() {
+            if let Some(name) = find_library_file(&output_folder, url).await {
+                info!("Job {}: {} is already in the library", id, name);
+                return Ok(DownloadOutput { folder: output_folder, filename: name.clone(), files: vec![name] });
+            }
+        }
+        let template = staging.as_deref().unwrap_or(&output_folder).join(if flat { "%(id)s.%(ext)s" } else { "%(title)s.%(ext)s" });
+
+        let container = options.output_container.as_str();
+        let mut ffmpeg_args = Vec::new();
+        if options.ffmpeg_threads > 0 {
+            ffmpeg_args.push(format!("-threads {}", options.ffmpeg_threads));
+        }
+        if container == "mp4" && !options.audio_only {
+            ffmpeg_args.push("-movflags +faststart".to_string());
+        }
+
+        // Formats without a known height pass the cap; when nothing fits
+        // under it, the smallest format is taken instead of failing.
+        let capped = format!("bv*[height<=?{0}]+ba/b[height<=?{0}]/wv*+ba/w", options.max_height);
+        let format = match (&options.format, options.audio_only) {
+            (Some(format), _) => format.as_str(),
+            (None, true) => "ba/b",
+            (None, false) if options.max_height > 0 => capped.as_str(),
+            (None, false) => "bv*+ba/best",
+        };
+        let mut cmd = Self::yt_dlp_command()?;
+        cmd.arg("--newline")
+            .arg(if options.resume { "--continue" } else { "--no-continue" })
             .arg("-f")
-            .arg("bv*+ba/best")
-            .arg("--merge-output-format")
-            .arg("mp4")
-            .arg("--remux-video")
-            .arg("mp4")
-            .arg("--postprocessor-args")
-            .arg("ffmpeg:-movflags +faststart")
-            .arg("-o")
-            .arg(template)
+            .arg(format);
+        match container {
+            // Extracted audio keeps its own codec and container unless a
+            // conversion was asked for.
+            _ if options.audio_only => {
+                cmd.arg("-x");
+                if let Some(audio_format) = options.audio_format.as_deref().filter(|f| *f != "original") {
+                    cmd.arg("--audio-format").arg(audio_format).arg("--audio-quality").arg("0");
+                }
+            }
+            "original" => {}
+            // Remuxing H.264/AAC into webm fails, so prefer streams that are
+            // already webm and keep anything else as downloaded.
+            "webm" => {
+                cmd.arg("-S").arg("ext:webm:webm").arg("--merge-output-format").arg("webm");
+            }
+            other => {
+                cmd.arg("--merge-output-format").arg(other).arg("--remux-video").arg(other);
+            }
+        }
+        if !ffmpeg_args.is_empty() {
+            cmd.arg("--postprocessor-args").arg(format!("ffmpeg:{}", ffmpeg_args.join(" ")));
+        }
+        cmd.arg("-o").arg(template);
+        if options.keep_original {
+            cmd.arg("--keep-video");
+        }
+        if options.write_description {
+            cmd.arg("--write-description");
+        }
+        // Comments only end up on disk inside the info json.
+        if options.write_comments {
+            cmd.arg("--write-comments").arg("--write-info-json");
+        }
+        // A redownload would otherwise be skipped as already archived.
+        if options.use_archive && staging.is_none() {
+            cmd.arg("--download-archive").arg(DOWNLOAD_ARCHIVE_FILE);
+        }
+        for (name, value) in &options.headers {
+            cmd.arg("--add-header").arg(format!("{}:{}", name, value));
+        }
+        cmd.args(&options.extra_args);
+        if let Some(proxy) = &options.proxy {
+            cmd.arg("--proxy").arg(proxy);
+        }
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = cmd
             .arg(url)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .kill_on_drop(true)
             .spawn()?;
 
+        let paused = Arc::new(AtomicBool::new(false));
+        if let Some(pid) = child.id() {
+            children.insert(id.to_string(), RunningChild { pid, paused: paused.clone() });
+        }
+
         let stdout = child.stdout.take().expect("Failed to open stdout");
         let stderr = child.stderr.take().expect("Failed to open stderr");
         
-        let found_filename = Arc::new(Mutex::new(String::new()));
-        let found_filename_clone = found_filename.clone();
+        let found_files = Arc::new(Mutex::new(Vec::<String>::new()));
+        let found_files_clone = found_files.clone();
+        let activity = Arc::new(Mutex::new(Activity { last: std::time::Instant::now(), postprocessing: false }));
+        let activity_clone = activity.clone();
+        let archived = Arc::new(AtomicBool::new(false));
+        let archived_clone = archived.clone();
         let db_clone = db.clone();
         let id_clone = id.to_string();
+        let live_progress = live_progress.clone();
+        let write_interval = std::time::Duration::from_millis(options.progress_write_interval_ms);
 
         let stdout_task = tokio::spawn(async move {
-            let mut reader = BufReader::new(stdout).lines();
-            let mut last_progress_update = std::time::Instant::now();
+            // Split on raw bytes so a stray non-UTF-8 byte can't end the read
+            // loop the way `lines()` would.
+            let mut reader = BufReader::new(stdout).split(b'\n');
+            let mut last_progress_write = std::time::Instant::now();
             
-            let re_progress = Regex::new(r"[download]\s+(\d+\.?\d*)%").unwrap();
-            let re_eta = Regex::new(r"ETA\s+(\d{2}:\d{2}(?:\:\d{2})?)").unwrap();
-            let re_dest = Regex::new(r"\b[dD]estination:\s+(.*)").unwrap();
-            let re_merge = Regex::new(r#"\b[mM]erger\b.*into\s+"?([^"]*)"?"#).unwrap();
-            let re_already = Regex::new(r"\b[dD]ownloaded\s+(.*)\s+has already been downloaded").unwrap();
-
-            while let Ok(Some(line)) = reader.next_line().await {
-                 if let Some(caps) = re_progress.captures(&line) {
-                    if let Some(m) = caps.get(1) {
-                        if let Ok(p) = m.as_str().parse::<f64>() {
-                            if last_progress_update.elapsed().as_secs() >= 1 {
-                                let eta = if let Some(eta_caps) = re_eta.captures(&line) {
-                                    Self::parse_eta(eta_caps.get(1).unwrap().as_str())
-                                } else {
-                                    None
-                                };
-                                let _ = db_clone.update_progress(&id_clone, p as i64, eta).await;
-                                last_progress_update = std::time::Instant::now();
-                            }
-                        }
+            let parser = OutputParser::new();
+
+            while let Ok(Some(raw)) = reader.next_segment().await {
+                 let line = String::from_utf8_lossy(&raw);
+                 let line = line.trim_end_matches('\r');
+                 let parsed = parser.parse(line);
+                 {
+                     let mut a = activity_clone.lock().unwrap();
+                     a.last = std::time::Instant::now();
+                     if let Some(postprocessing) = parsed.postprocessing {
+                         a.postprocessing = postprocessing;
+                     }
+                 }
+                 if let Some((p, eta)) = parsed.progress {
+                    live_progress.insert(id_clone.clone(), (p as i64, eta));
+                    if last_progress_write.elapsed() >= write_interval {
+                        let _ = db_clone.update_progress(&id_clone, p as i64, eta).await;
+                        last_progress_write = std::time::Instant::now();
                     }
                 }
-                
-                if let Some(caps) = re_dest.captures(&line) {
-                    if let Some(m) = caps.get(1) {
-                        let mut w = found_filename_clone.lock().unwrap();
-                        *w = m.as_str().trim().to_string();
-                    }
+
+                if let Some(file) = parsed.file {
+                    record_output(&found_files_clone, file);
                 }
-                
-                if let Some(caps) = re_merge.captures(&line) {
-                    if let Some(m) = caps.get(1) {
-                         let raw = m.as_str().trim();
-                         let mut w = found_filename_clone.lock().unwrap();
-                         *w = raw.trim_matches('"').to_string();
-                    }
+
+                if parsed.archived {
+                    archived_clone.store(true, Ordering::Relaxed);
                 }
-                
-                if let Some(caps) = re_already.captures(&line) {
-                    if let Some(m) = caps.get(1) {
-                        {
-                            let mut w = found_filename_clone.lock().unwrap();
-                            *w = m.as_str().trim().to_string();
-                        }
-                        let _ = db_clone.update_progress(&id_clone, 100, Some(0)).await;
-                    }
+
+                if parsed.already_downloaded {
+                    live_progress.insert(id_clone.clone(), (100, Some(0)));
+                    let _ = db_clone.update_progress(&id_clone, 100, Some(0)).await;
                 }
             }
         });
 
-        tokio::spawn(async move {
-             let mut reader = BufReader::new(stderr).lines();
-             while let Ok(Some(_)) = reader.next_line().await { }
+        let stderr_tail = Arc::new(Mutex::new(VecDeque::<String>::new()));
+        let stderr_tail_clone = stderr_tail.clone();
+        let stderr_task = tokio::spawn(async move {
+             let mut reader = BufReader::new(stderr).split(b'\n');
+             while let Ok(Some(raw)) = reader.next_segment().await {
+                 let line = String::from_utf8_lossy(&raw);
+                 let line = line.trim();
+                 if line.is_empty() {
+                     continue;
+                 }
+                 let mut tail = stderr_tail_clone.lock().unwrap();
+                 if tail.len() == STDERR_TAIL_LINES {
+                     tail.pop_front();
+                 }
+                 tail.push_back(line.to_string());
+             }
         });
 
+        let stall_timeout = options.stall_timeout_seconds;
+        let watchdog = async {
+            if stall_timeout == 0 {
+                return futures::future::pending::<()>().await;
+            }
+            let limit = std::time::Duration::from_secs(stall_timeout);
+            loop {
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                let mut a = activity.lock().unwrap();
+                if paused.load(Ordering::Relaxed) {
+                    a.last = std::time::Instant::now();
+                    continue;
+                }
+                if !a.postprocessing && a.last.elapsed() >= limit {
+                    return;
+                }
+            }
+        };
+
         tokio::select! {
+            _ = watchdog => {
+                kill_child(&mut child).await?;
+                Err(DownloadError::Stalled(stall_timeout).into())
+            }
             _ = token.cancelled() => {
-                child.kill().await?;
-                Err(anyhow::anyhow!("Job cancelled"))
+                kill_child(&mut child).await?;
+                if !options.resume {
+                    let last = found_files.lock().unwrap().last().cloned();
+                    if let Some(name) = last {
+                        let _ = tokio::fs::remove_file(format!("{}.part", name)).await;
+                    }
+                }
+                Err(DownloadError::Cancelled.into())
             }
             status = child.wait() => {
                 let status = status?;
                 let _ = stdout_task.await; 
                 
                 if status.success() {
-                    let name = found_filename.lock().unwrap().clone();
-                    if !name.is_empty() {
-                         let name = Path::new(&name).file_name().unwrap().to_string_lossy().to_string();
-                         Ok(name)
-                    } else {
-                         Ok("unknown.mp4".to_string())
+                    let found = found_files.lock().unwrap().clone();
+                    if found.is_empty() && archived.load(Ordering::Relaxed) {
+                        return Err(DownloadError::Archived.into());
                     }
+                    // Intermediate streams are reported too but removed after
+                    // merging, so only what is still on disk belongs to the job.
+                    let mut files: Vec<String> = found.iter()
+                        .filter(|p| Path::new(p).is_file())
+                        .map(|p| Path::new(p).file_name().unwrap_or_default().to_string_lossy().to_string())
+                        .collect();
+                    let filename = match (files.last(), found.last()) {
+                        (Some(name), _) => name.clone(),
+                        (None, Some(p)) => Path::new(p).file_name().unwrap_or_default().to_string_lossy().to_string(),
+                        (None, None) => "unknown.mp4".to_string(),
+                    };
+                    if files.is_empty() {
+                        files.push(filename.clone());
+                    }
+                    if let Some(staging) = &staging {
+                        promote_staged(staging, &output_folder).await?;
+                    }
+                    Ok(DownloadOutput { folder: output_folder, filename, files })
                 } else {
-                    Err(anyhow::anyhow!("Process exited with code {}", status.code().unwrap_or(-1)))
+                    let _ = stderr_task.await;
+                    let tail = stderr_tail.lock().unwrap().iter().cloned().collect::<Vec<_>>();
+                    Err(anyhow::anyhow!(exit_error(status.code().unwrap_or(-1), &tail)))
                 }
             }
         }
@@ -503,3 +2316,152 @@ impl DownloadQueue {
         Some(seconds)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_panicking_download_task_frees_its_slot() {
+        let active_jobs: Arc<DashMap<String, JobCancel>> = Arc::new(DashMap::new());
+        let notify = Arc::new(Notify::new());
+        active_jobs.insert("job".to_string(), JobCancel::new());
+
+        let guard = ActiveJobGuard {
+            id: "job".to_string(),
+            active_jobs: active_jobs.clone(),
+            children: Arc::new(DashMap::new()),
+            live_progress: Arc::new(DashMap::new()),
+            notify: notify.clone(),
+        };
+        let task = tokio::spawn(async move {
+            let _guard = guard;
+            panic!("download task panicked");
+        });
+
+        assert!(task.await.unwrap_err().is_panic());
+        assert!(active_jobs.is_empty());
+        // The scheduler is woken to fill the freed slot.
+        tokio::time::timeout(std::time::Duration::from_secs(1), notify.notified()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn queue_order_survives_a_restart() {
+        let dir = std::env::temp_dir().join(format!("tiak-test-{}", uuid::Uuid::new_v4()));
+        let db_path = dir.join("tiak.db");
+        let db_path = db_path.to_str().unwrap();
+        let db = Db::new(db_path).await.unwrap();
+        let mut ids = Vec::new();
+        for (url, priority) in [("https://a.test", 0), ("https://b.test", 5), ("https://c.test", 0), ("https://d.test", 5)] {
+            ids.push(db.add_job(url.to_string(), JobOptions { priority, ..Default::default() }).await.unwrap().id);
+        }
+        // A retry sends the first job to the back of its priority.
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        db.increment_retry(&ids[0]).await.unwrap();
+        drop(db);
+
+        let queue = DownloadQueue::new(Db::new(db_path).await.unwrap(), Arc::new(FileIndex::new()), Capabilities::default());
+        // Keeps the scheduler from starting the restored jobs.
+        queue.stopping.cancel();
+        queue.load_initial_state().await;
+
+        let order: Vec<String> = queue.queue.lock().unwrap().iter().map(|(_, id)| id.clone()).collect();
+        assert_eq!(order, [ids[1].clone(), ids[3].clone(), ids[2].clone(), ids[0].clone()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_yt_dlp_output_lines() {
+        let parser = OutputParser::new();
+
+        let line = parser.parse("[download]  45.3% of   10.00MiB at    1.21MiB/s ETA 00:04");
+        assert_eq!(line.progress, Some((45.3, Some(4))));
+        assert_eq!(line.postprocessing, Some(false));
+
+        let line = parser.parse("[download]   2.0% of ~   1.50GiB at  512.00KiB/s ETA 01:02:03 (frag 3/150)");
+        assert_eq!(line.progress, Some((2.0, Some(3723))));
+
+        let line = parser.parse("[download]   7.1% of ~  80.00MiB at  Unknown B/s ETA Unknown");
+        assert_eq!(line.progress, Some((7.1, None)));
+
+        let line = parser.parse("[download] 100% of   10.00MiB in 00:00:08 at 1.19MiB/s");
+        assert_eq!(line.progress, Some((100.0, None)));
+
+        let line = parser.parse("[download] Destination: data/2026-10-15/Clip [abc].f137.mp4");
+        assert_eq!(line.file, Some("data/2026-10-15/Clip [abc].f137.mp4"));
+        assert_eq!(line.progress, None);
+
+        let line = parser.parse(r#"[Merger] Merging formats into "data/2026-10-15/Clip [abc].mp4""#);
+        assert_eq!(line.file, Some("data/2026-10-15/Clip [abc].mp4"));
+        assert_eq!(line.postprocessing, Some(true));
+
+        let line = parser.parse("[download] data/2026-10-15/Clip [abc].mp4 has already been downloaded");
+        assert_eq!(line.file, Some("data/2026-10-15/Clip [abc].mp4"));
+        assert!(line.already_downloaded);
+
+        let line = parser.parse("[ExtractAudio] Destination: data/2026-10-15/Clip [abc].mp3");
+        assert_eq!(line.file, Some("data/2026-10-15/Clip [abc].mp3"));
+        assert_eq!(line.postprocessing, Some(true));
+
+        let line = parser.parse("[download] abc: Clip has already been recorded in the archive");
+        assert!(line.archived);
+        assert_eq!(line.file, None);
+
+        assert_eq!(parser.parse("[youtube] abc: Downloading webpage"), OutputLine::default());
+    }
+
+    #[test]
+    fn blocks_yt_dlp_options_in_any_form() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        for blocked in [
+            &["--exec", "rm"][..],
+            &["--output=/tmp/x"],
+            &["-o/tmp/x"],
+            &["-P/tmp"],
+            &["-afile"],
+            &["-io", "/tmp/x"],
+            &["--downloader", "aria2c"],
+            &["--external-downloader-args", "x"],
+            &["--downloader-args=aria2c:x"],
+            &["--print-to-file", "x", "/tmp/x"],
+            &["--exec-before", "x"],
+            &["--batch-f", "/tmp/x"],
+            &["--config-loc", "/tmp/x"],
+            &["--print-to-f", "x", "/tmp/x"],
+            &["--load-info=/tmp/x"],
+            &["--ffmpeg-location", "/tmp/x"],
+            &["--plugin-dirs", "/tmp"],
+            &["--alias", "x", "--exec {}"],
+            &["--postprocessor-args", "-y /tmp/x"],
+            &["--ppa", "-y /tmp/x"],
+            &["--cookies", "/tmp/x"],
+            &["--download-archive", "/tmp/x"],
+            &["--cache-dir=/tmp"],
+        ] {
+            assert!(validate_extra_ytdlp_args(&args(blocked)).is_err(), "{:?}", blocked);
+        }
+        assert!(validate_extra_ytdlp_args(&args(&["--limit-rate", "2M", "-N", "4", "--no-mtime"])).is_ok());
+        assert!(validate_extra_ytdlp_args(&args(&["--print", "title", "--netrc", "--cookies-from-browser", "firefox"])).is_ok());
+    }
+
+    #[test]
+    fn blocks_rclone_options_in_any_form() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        for blocked in [
+            &["--sftp-ssh", "sh -c x"][..],
+            &["--sftp-key-file=/root/.ssh/id_rsa"],
+            &["--cache-dir", "/tmp"],
+            &["--temp-dir", "/tmp"],
+            &["--exclude-from", "/etc/passwd"],
+            &["--include-from", "/etc/passwd"],
+            &["--filter-from", "/etc/passwd"],
+            &["--ca-cert", "/tmp/ca.pem"],
+            &["--webdav-bearer-token-command", "x"],
+            &["--rc-addr", ":5572"],
+            &["-vL"],
+        ] {
+            assert!(validate_extra_rclone_args(&args(blocked)).is_err(), "{:?}", blocked);
+        }
+        assert!(validate_extra_rclone_args(&args(&["--fast-list", "--drive-chunk-size", "64M", "-v"])).is_ok());
+    }
+}
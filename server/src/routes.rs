@@ -1,64 +1,234 @@
 use axum::{
-    extract::{Path, Query, State, Multipart, Json, DefaultBodyLimit},
-    response::{IntoResponse, Response},
-    http::{StatusCode, HeaderMap, header, HeaderValue},
-    routing::{get, post, delete},
+    extract::{State, Multipart, Json, DefaultBodyLimit, Extension},
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response},
+    http::{StatusCode, HeaderMap, header, HeaderValue, Method},
+    routing::{any, get, patch, post, delete, MethodRouter},
     Router, body::Body,
 };
-use crate::db::{Db, Job};
-use crate::queue::DownloadQueue;
-use crate::storage::{FileIndex, DATA_ROOT, get_disk_usage};
+use crate::archive;
+use crate::cleanup::{job_folder, reconcile, DuplicateMapping, ReconcileReport};
+use crate::config::Config;
+use crate::error::{ApiError, ApiJson, ApiPath, ApiQuery};
+use crate::auth::{generate_key, hash_key, Scope};
+use crate::db::{ApiKey, Db, Job, JobOptions};
+use crate::queue::{DownloadQueue, FormatInfo, PauseError, SyncState, check_sync_destination, validate_extra_rclone_args, validate_extra_ytdlp_args, validate_job_headers, validate_sync_bwlimit, validate_sync_destination, validate_audio_format, validate_format, validate_max_height, validate_output_container, validate_required_stream, validate_subfolder, MAX_FFMPEG_THREADS, MAX_SYNC_TRANSFERS, PROGRESS_WRITE_INTERVAL_RANGE_MS};
+use crate::system::Capabilities;
+use crate::upload::{self, UploadError};
+use crate::webhook::validate_webhook_url;
+use crate::share;
+use crate::zip_job::{self, ZipJobStatus};
+use crate::media::NoVideoStream;
+use crate::storage::{FileIndex, FileIndexResponse, FileItem, FileSort, SortOrder, DATA_ROOT, LIBRARY_FOLDER, available_space, index_path, remove_data_file, canonicalize_data_path, cold_root, in_data_roots, data_root_available, get_disk_usage, is_sidecar, move_file, sidecar_paths, total_space};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
 use std::sync::Arc;
-use serde::Deserialize;
-use std::path::{Path as StdPath, PathBuf};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path as StdPath, PathBuf};
 use tokio_util::io::ReaderStream;
-use zip::write::SimpleFileOptions;
-use std::io::Write;
+use std::io::{Seek, SeekFrom};
 use tokio::fs::File as AsyncFile;
+use tokio::sync::broadcast;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::compression::CompressionLayer;
+use tower_http::timeout::TimeoutLayer;
+use utoipa::{IntoParams, Modify, OpenApi, PartialSchema, ToSchema};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Db,
     pub queue: Arc<DownloadQueue>,
     pub file_index: Arc<FileIndex>,
+    pub config: Config,
+    pub capabilities: Capabilities,
 }
 
 pub fn create_router(state: AppState) -> Router {
-    Router::new()
+    let mut api = Router::new()
         .route("/", get(root))
         .route("/api/files", get(list_files).delete(delete_files))
-        .route("/api/files/zip", post(zip_files))
-        .route("/api/files/download", get(download_file))
-        .route("/api/files/stream", get(stream_file))
-        .route("/api/queue/:id", delete(delete_job))
+        .route("/api/files/flat", get(list_files_flat))
+        .route("/api/files/zip/jobs", post(create_zip_job))
+        .route("/api/files/zip/jobs/:id", get(zip_job_status).delete(delete_zip_job))
+        .route("/api/files/move", post(move_file_endpoint))
+        .route("/api/files/share", post(share_file))
+        .route("/api/files/description", get(file_description))
+        .route("/api/tags/:tag", delete(delete_tag))
+        .route("/api/queue/:id", get(get_job).delete(delete_job))
         .route("/api/system/usage", get(system_usage))
+        .route("/api/system/info", get(system_info))
+        .route("/api/system/reconcile", post(system_reconcile))
         .route("/api/settings", get(get_settings).post(set_settings))
         .route("/api/queue/list", get(list_queue))
         .route("/api/queue/add", post(add_to_queue))
+        .route("/api/queue/validate", post(validate_queue_add))
         .route("/api/queue/history", get(queue_history))
+        .route("/api/queue/metrics", get(queue_metrics))
         .route("/api/queue/export", get(export_queue))
-        .route("/api/queue/import", post(import_queue))
         .route("/api/queue/retry/:id", post(retry_job))
         .route("/api/queue/redownload/:id", post(redownload_job))
-        .route("/api/files/resolve", post(resolve_url_endpoint))
-        .route("/api/sync/run", post(sync_run))
-        .route("/api/sync/status", get(sync_status))
+        .route("/api/queue/:id/pause", post(pause_job))
+        .route("/api/queue/:id/resume", post(resume_job))
+        .route("/api/queue/:id/star", post(star_job))
+        .route("/api/queue/:id/url", patch(update_job_url))
+        .route("/api/queue/:id/archive", post(archive_job))
+        .route("/api/queue/:id/headers", get(get_job_headers))
+        .route("/api/queue/batch", post(batch_jobs))
+        .route("/api/openapi.json", get(openapi_spec));
+
+    // Features switched off in the environment answer 404 on every method;
+    // leaving them unrouted would let `/api/queue/:id` answer 405 instead.
+    let config = &state.config;
+    api = api
+        .route("/api/queue/import", gated(config.enable_import, post(import_queue)))
+        .route("/api/queue/import/archive", gated(config.enable_import, post(import_archive)))
+        .route("/api/queue/import/uploads", gated(config.enable_import, post(create_import_upload)))
+        .route("/api/queue/import/uploads/:id", gated(
+            config.enable_import,
+            get(import_upload_status).patch(append_import_upload).delete(delete_import_upload),
+        ))
+        .route("/api/queue/import/uploads/:id/complete", gated(config.enable_import, post(complete_import_upload)))
+        .route("/api/files/resolve", gated(config.enable_resolve, post(resolve_url_endpoint)))
+        .route("/api/files/formats", gated(config.enable_resolve, post(list_formats)))
+        .route("/api/sync/run", gated(config.enable_sync, post(sync_run)))
+        .route("/api/sync/status", gated(config.enable_sync, get(sync_status)))
+        .route("/api/sync/validate", gated(config.enable_sync, post(sync_validate)))
+        .route("/api/keys", gated(config.admin_api_key_hash.is_some(), get(list_api_keys).post(create_api_key)))
+        .route("/api/keys/:id", gated(config.admin_api_key_hash.is_some(), delete(delete_api_key)));
+    if state.config.compression {
+        api = api.layer(CompressionLayer::new());
+    }
+    if config.request_timeout_seconds > 0 {
+        api = api.layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            std::time::Duration::from_secs(config.request_timeout_seconds),
+        ));
+    }
+
+    // Media and archives are already compressed, so these routes are merged
+    // in after the compression layer. Downloads, zips and WebDAV transfers can
+    // legitimately run for a long time, so they are not timed out either; nor
+    // is the event stream, which stays open and must not be buffered.
+    let files = Router::new()
+        .route("/api/files/zip", get(zip_folder).post(zip_files))
+        .route("/api/files/zip/jobs/:id/download", get(download_zip_job))
+        .route("/api/files/download", get(download_file))
+        .route("/api/files/stream", get(stream_file))
+        .route("/api/files/shared/:token", get(shared_file))
+        .route("/api/queue/events", get(queue_events))
+        .route("/api/files/contact-sheet", get(contact_sheet))
+        .route("/dav", any(crate::dav::dav_root))
+        .route("/dav/", any(crate::dav::dav_root))
+        .route("/dav/*path", any(crate::dav::dav_path));
+
+    let mut router = api.merge(files)
+        .fallback(|| async { ApiError::not_found("Not found") });
+    if state.config.read_only {
+        router = router.layer(axum::middleware::from_fn(reject_writes));
+    }
+    if state.config.admin_api_key_hash.is_some() {
+        router = router.layer(axum::middleware::from_fn_with_state(state.clone(), crate::auth::require_key));
+    }
+    // A permit is held until the response head is sent, not while a body
+    // streams, so long downloads don't starve other requests. `Router::layer`
+    // wraps every route separately, so the permits must come from one
+    // shared semaphore for the limit to cover the whole server.
+    if state.config.max_concurrent_requests > 0 {
+        router = router.layer(GlobalConcurrencyLimitLayer::new(state.config.max_concurrent_requests));
+    }
+
+    router
         .layer(DefaultBodyLimit::max(10 * 1024 * 1024))
         .with_state(state)
 }
 
+fn gated(enabled: bool, route: MethodRouter<AppState>) -> MethodRouter<AppState> {
+    if enabled {
+        route
+    } else {
+        any(|| async { ApiError::not_found("This feature is disabled on this server") })
+    }
+}
+
+// Zipping a selection is a POST only because the paths don't fit in a query
+// string; it reads files without changing anything, so demos keep it.
+async fn reject_writes(req: axum::extract::Request, next: axum::middleware::Next) -> Response {
+    let path = req.uri().path();
+    let is_write = matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE);
+    // Zip jobs can be made and removed; they are the caller's own archives.
+    let zip_job = path == "/api/files/zip/jobs" || (*req.method() == Method::DELETE && path.starts_with("/api/files/zip/jobs/"));
+    if is_write && path.starts_with("/api/") && path != "/api/files/zip" && !zip_job {
+        return ApiError::forbidden("Server is in read-only mode").into_response();
+    }
+    next.run(req).await
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Tiak Server"),
+    paths(
+        list_files, list_files_flat, delete_files, zip_files, zip_folder, create_zip_job, zip_job_status, download_zip_job, delete_zip_job, download_file, stream_file, share_file, shared_file, contact_sheet, file_description, move_file_endpoint,
+        delete_tag, resolve_url_endpoint, list_formats,
+        list_queue, queue_events, add_to_queue, validate_queue_add, queue_history, queue_metrics, export_queue, import_queue, import_archive,
+        create_import_upload, import_upload_status, append_import_upload, delete_import_upload, complete_import_upload,
+        retry_job, redownload_job, pause_job, resume_job, star_job, update_job_url, archive_job, get_job, get_job_headers, delete_job, batch_jobs,
+        system_usage, system_info, system_reconcile, get_settings, set_settings,
+        sync_run, sync_status, sync_validate,
+        list_api_keys, create_api_key, delete_api_key,
+    ),
+    components(schemas(Job, ApiKey, FileItem, FileIndexResponse, SyncState, FormatInfo, Capabilities, ReconcileReport, DuplicateMapping, ZipJobStatus)),
+    modifiers(&JobTimings)
+)]
+struct ApiDoc;
+
+// Job serializes its derived timing fields by hand, so the derived schema
+// does not know about them.
+struct JobTimings;
+
+impl Modify for JobTimings {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        use utoipa::openapi::{RefOr, Schema};
+        let job = openapi.components.as_mut().and_then(|c| c.schemas.get_mut("Job"));
+        if let Some(RefOr::T(Schema::Object(job))) = job {
+            for field in ["queueWaitMs", "downloadDurationMs"] {
+                job.properties.insert(field.to_string(), <Option<i64> as PartialSchema>::schema());
+            }
+        }
+    }
+}
+
+async fn openapi_spec(State(state): State<AppState>) -> Json<utoipa::openapi::OpenApi> {
+    let mut doc = ApiDoc::openapi();
+    let config = &state.config;
+    let disabled: Vec<&str> = [
+        (!config.enable_import).then_some("/api/queue/import"),
+        (!config.enable_resolve).then_some("/api/files/resolve"),
+        (!config.enable_resolve).then_some("/api/files/formats"),
+        (!config.enable_sync).then_some("/api/sync/"),
+    ].into_iter().flatten().collect();
+    doc.paths.paths.retain(|path, _| !disabled.iter().any(|prefix| path.starts_with(prefix)));
+    Json(doc)
+}
+
 async fn root() -> &'static str {
     "Tiak Server is running (Rust)"
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct ResolvePayload {
     url: String,
 }
 
-async fn resolve_url_endpoint(Json(payload): Json<ResolvePayload>) -> impl IntoResponse {
+#[utoipa::path(
+    post, path = "/api/files/resolve", tag = "files", request_body = ResolvePayload,
+    responses((status = 200, description = "Resolved URL"))
+)]
+async fn resolve_url_endpoint(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<ResolvePayload>,
+) -> impl IntoResponse {
     let url = payload.url;
-    if !url.starts_with("http") {
+    if !url.starts_with("http") || !state.capabilities.curl {
         return Json(serde_json::json!({ "url": url })).into_response();
     }
 
@@ -68,6 +238,23 @@ async fn resolve_url_endpoint(Json(payload): Json<ResolvePayload>) -> impl IntoR
     }
 }
 
+#[utoipa::path(
+    post, path = "/api/files/formats", tag = "files", request_body = ResolvePayload,
+    responses((status = 200, description = "Available formats", body = Vec<FormatInfo>), (status = 400), (status = 502))
+)]
+async fn list_formats(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<ResolvePayload>,
+) -> Result<Response, ApiError> {
+    if !payload.url.starts_with("http") {
+        return Err(ApiError::bad_request("Invalid URL"));
+    }
+
+    let formats = state.queue.list_formats(&payload.url).await
+        .map_err(|e| ApiError::bad_gateway(e.to_string()))?;
+    Ok(Json(serde_json::json!({ "url": payload.url, "formats": formats })).into_response())
+}
+
 async fn resolve_url(url: &str) -> Result<String, anyhow::Error> {
     use tokio::process::Command;
     
@@ -89,27 +276,128 @@ async fn resolve_url(url: &str) -> Result<String, anyhow::Error> {
     }
 }
 
-async fn list_files(State(state): State<AppState>) -> impl IntoResponse {
-    Json(state.file_index.get_index())
+// Clients that would rather retry than render a partial library send this
+// header and get a 503 until the first index build has finished.
+const REQUIRE_INDEX_READY_HEADER: &str = "x-require-index-ready";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListFilesQuery {
+    /// Only list files of starred jobs
+    starred: Option<bool>,
 }
 
-#[derive(Deserialize)]
+#[utoipa::path(
+    get, path = "/api/files", tag = "files",
+    params(
+        ListFilesQuery,
+        ("x-require-index-ready" = Option<String>, Header, description = "Return 503 until the first index build has finished"),
+    ),
+    responses((status = 200, body = FileIndexResponse), (status = 503, description = "File index is still building"))
+)]
+async fn list_files(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<ListFilesQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    if headers.contains_key(REQUIRE_INDEX_READY_HEADER) && !state.file_index.is_ready() {
+        return Err(ApiError::unavailable("File index is still building").with_retry_after(5));
+    }
+    // Starring does not touch the index, so the filtered listing has no ETag.
+    if query.starred == Some(true) {
+        let names: HashSet<String> = state.db.get_starred_jobs().await?
+            .iter()
+            .flat_map(|job| job.all_files())
+            .collect();
+        let mut index = state.file_index.get_index();
+        index.by_date.retain(|_, files| {
+            files.retain(|f| names.contains(&f.name));
+            !files.is_empty()
+        });
+        let mut response = Json(index).into_response();
+        response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        return Ok(response);
+    }
+    let etag = state.file_index.etag();
+    let mut response = if etag_matches(&headers, &etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        Json(state.file_index.get_index()).into_response()
+    };
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    Ok(response)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct FlatFilesQuery {
+    page: Option<i64>,
+    limit: Option<i64>,
+    /// `date` (default), `size` or `name`
+    #[serde(default)]
+    sort: FileSort,
+    /// `asc` or `desc`; names default to `asc`, dates and sizes to `desc`
+    order: Option<SortOrder>,
+}
+
+#[utoipa::path(
+    get, path = "/api/files/flat", tag = "files", params(FlatFilesQuery),
+    responses((status = 200, description = "Page of indexed files"))
+)]
+async fn list_files_flat(
+    State(state): State<AppState>,
+    ApiQuery(q): ApiQuery<FlatFilesQuery>,
+) -> Result<Response, ApiError> {
+    let page = q.page.unwrap_or(1).max(1);
+    let limit = q.limit.unwrap_or(50).clamp(1, state.config.max_page_size);
+    let offset = (page - 1).saturating_mul(limit);
+    let order = q.order.unwrap_or(match q.sort {
+        FileSort::Name => SortOrder::Asc,
+        FileSort::Date | FileSort::Size => SortOrder::Desc,
+    });
+
+    let (items, total) = state.file_index.page(q.sort, order, offset as usize, limit as usize);
+    Ok(Json(serde_json::json!({
+        "items": items,
+        "total": total,
+        "page": page,
+        "limit": limit,
+        "maxPageSize": state.config.max_page_size,
+        "indexing": state.file_index.is_indexing(),
+        "ready": state.file_index.is_ready()
+    })).into_response())
+}
+
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers.get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"))
+}
+
+#[derive(Deserialize, ToSchema)]
 struct DeleteFilesPayload {
     paths: Vec<String>,
 }
 
+#[utoipa::path(
+    delete, path = "/api/files", tag = "files", request_body = DeleteFilesPayload,
+    responses((status = 200, description = "Deleted files and jobs summary"))
+)]
 async fn delete_files(
     State(state): State<AppState>,
-    Json(payload): Json<DeleteFilesPayload>,
+    ApiJson(payload): ApiJson<DeleteFilesPayload>,
 ) -> impl IntoResponse {
     let mut deleted = Vec::new();
     let mut errors: Vec<serde_json::Value> = Vec::new();
 
     for p in payload.paths {
-        let abs_path = StdPath::new(&p).canonicalize().unwrap_or_else(|_| PathBuf::from(&p));
+        let abs_path = canonicalize_data_path(&p).unwrap_or_else(|| PathBuf::from(&p));
         let data_root = StdPath::new(DATA_ROOT).canonicalize().unwrap_or_else(|_| PathBuf::from(DATA_ROOT));
 
-        if !abs_path.starts_with(&data_root) {
+        if !in_data_roots(&abs_path) {
             errors.push(serde_json::json!({ "path": p, "error": "Access denied" }));
             continue;
         }
@@ -120,229 +408,1269 @@ async fn delete_files(
         }
 
         if abs_path.exists() {
-             if let Err(e) = tokio::fs::remove_file(&abs_path).await {
+             if let Err(e) = remove_data_file(&state.file_index, &abs_path, &data_root).await {
                  errors.push(serde_json::json!({ "path": p, "error": e.to_string() }));
              } else {
-                 state.file_index.remove_file(&abs_path.to_string_lossy());
                  deleted.push(p.clone());
-                 
-                 if let Some(parent) = abs_path.parent() {
-                     if parent.starts_with(&data_root) && parent != data_root {
-                         let _ = tokio::fs::remove_dir(parent).await;
-                     }
-                 }
              }
         } else {
              deleted.push(p);
         }
     }
     
-    if !deleted.is_empty() {
+    Json(serde_json::json!({ "deleted": deleted, "errors": errors }))
+}
+
+// The folder's directory on each data root it exists on, primary first.
+fn data_folders(folder: &str) -> Vec<PathBuf> {
+    std::iter::once(StdPath::new(DATA_ROOT))
+        .chain(cold_root())
+        .filter_map(|root| {
+            let root = root.canonicalize().ok()?;
+            let dir = root.join(folder).canonicalize().ok()?;
+            (dir.starts_with(&root) && dir != root && dir.is_dir()).then_some(dir)
+        })
+        .collect()
+}
+
+// Deletes the jobs that produced the file at `path` (as indexed, under
+// `data/`). Same-named files in other folders belong to other jobs.
+async fn delete_file_jobs(state: &AppState, path: &StdPath) -> anyhow::Result<u64> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let folder = path.parent().and_then(|p| p.strip_prefix(DATA_ROOT).ok()).unwrap_or(StdPath::new(""));
+    let mut deleted = 0;
+    for job in state.db.get_jobs_by_filename(&name).await? {
+        if StdPath::new(&job_folder(&job)) == folder {
+            state.db.delete_job(&job.id).await?;
+            deleted += 1;
+        }
     }
+    Ok(deleted)
+}
 
-    Json(serde_json::json!({ "deleted": deleted, "errors": errors }))
+// A single top-level folder. Hidden ones belong to the server (staged
+// redownloads, zips, uploads), and in flat-by-id mode `library` holds every
+// download.
+fn is_deletable_tag(tag: &str) -> bool {
+    validate_subfolder(tag).is_ok() && !tag.contains('/') && tag != LIBRARY_FOLDER && !tag.contains("jobs.sqlite")
 }
 
+#[utoipa::path(
+    delete, path = "/api/tags/{tag}", tag = "files", params(("tag" = String, Path)),
+    responses((status = 200, description = "Folder deleted"), (status = 400), (status = 404))
+)]
+async fn delete_tag(
+    State(state): State<AppState>,
+    ApiPath(tag): ApiPath<String>,
+) -> Result<Response, ApiError> {
+    if !is_deletable_tag(&tag) {
+        return Err(ApiError::bad_request("Invalid tag"));
+    }
+
+    let data_root = StdPath::new(DATA_ROOT).canonicalize().unwrap_or_else(|_| PathBuf::from(DATA_ROOT));
+    let folders = data_folders(&tag);
+    if folders.is_empty() {
+        return Err(ApiError::not_found("Tag not found"));
+    }
+
+    let walk_roots = folders.clone();
+    let files = tokio::task::spawn_blocking(move || {
+        walk_roots.iter()
+            .flat_map(|root| walkdir::WalkDir::new(root).into_iter().filter_map(|e| e.ok()))
+            .filter(|e| e.file_type().is_file())
+            .map(|e| (e.path().to_path_buf(), e.metadata().map(|m| m.len()).unwrap_or(0)))
+            .collect::<Vec<_>>()
+    }).await.unwrap_or_default();
+
+    let mut files_deleted = 0;
+    let mut bytes_deleted = 0;
+    let mut jobs_deleted = 0;
+    let mut errors: Vec<serde_json::Value> = Vec::new();
+
+    for (path, size) in files {
+        if let Err(e) = remove_data_file(&state.file_index, &path, &data_root).await {
+            errors.push(serde_json::json!({ "path": index_path(&path, &data_root).to_string_lossy(), "error": e.to_string() }));
+            continue;
+        }
+        files_deleted += 1;
+        bytes_deleted += size;
+
+        match delete_file_jobs(&state, &index_path(&path, &data_root)).await {
+            Ok(count) => jobs_deleted += count,
+            Err(e) => errors.push(serde_json::json!({ "path": index_path(&path, &data_root).to_string_lossy(), "error": ApiError::database(e).message() })),
+        }
+    }
+
+    if errors.is_empty() {
+        for folder in &folders {
+            let _ = tokio::fs::remove_dir_all(folder).await;
+        }
+    }
+
+    Ok(Json(serde_json::json!({
+        "tag": tag,
+        "filesDeleted": files_deleted,
+        "bytesDeleted": bytes_deleted,
+        "jobsDeleted": jobs_deleted,
+        "errors": errors
+    })).into_response())
+}
+
+#[derive(Deserialize, ToSchema)]
+struct MoveFilePayload {
+    from: String,
+    to: String,
+}
+
+// Validates a client supplied destination such as `data/project/clip.mp4`,
+// returning the path relative to DATA_ROOT. Only plain components are
+// accepted and the file must live inside a folder, like downloads do.
+fn sanitize_destination(to: &str) -> Option<PathBuf> {
+    let mut components = StdPath::new(to).components();
+    if components.next() != Some(Component::Normal(DATA_ROOT.as_ref())) {
+        return None;
+    }
+
+    let mut relative = PathBuf::new();
+    for component in components {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            _ => return None,
+        }
+    }
+
+    if relative.components().count() < 2 || relative.to_string_lossy().contains("jobs.sqlite") {
+        return None;
+    }
+    Some(relative)
+}
+
+#[utoipa::path(
+    post, path = "/api/files/move", tag = "files", request_body = MoveFilePayload,
+    responses((status = 200, description = "File moved"), (status = 400), (status = 403), (status = 404), (status = 409))
+)]
+async fn move_file_endpoint(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<MoveFilePayload>,
+) -> Result<Response, ApiError> {
+    let data_root = StdPath::new(DATA_ROOT).canonicalize().unwrap_or_else(|_| PathBuf::from(DATA_ROOT));
+    let from_abs = canonicalize_data_path(&payload.from)
+        .ok_or_else(|| ApiError::not_found("File not found"))?;
+
+    if !in_data_roots(&from_abs) || !from_abs.is_file() {
+        return Err(ApiError::forbidden("Access denied"));
+    }
+
+    let to_relative = sanitize_destination(&payload.to)
+        .ok_or_else(|| ApiError::bad_request("Invalid destination path"))?;
+    let to_abs = data_root.join(&to_relative);
+
+    if let Err(e) = move_file(&from_abs, &to_abs).await {
+        if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::AlreadyExists) {
+            return Err(ApiError::conflict("Destination already exists"));
+        }
+        return Err(e.into());
+    }
+
+    let from_index = index_path(&from_abs, &data_root);
+    let to_index = StdPath::new(DATA_ROOT).join(&to_relative);
+    state.file_index.remove_file(&from_index.to_string_lossy());
+    state.file_index.add_file(&to_index);
+
+    // Only the jobs whose folder held the file; a file of the same name in
+    // another folder is another job's. A job that moves with its only file
+    // is pointed at the new folder through `subfolder`.
+    let old_name = from_abs.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let new_name = to_abs.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let old_folder = from_index.parent().and_then(|p| p.strip_prefix(DATA_ROOT).ok()).unwrap_or(StdPath::new(""));
+    let new_folder = to_relative.parent().unwrap_or(StdPath::new(""));
+    let moved = old_folder != new_folder;
+    let mut jobs_updated = 0;
+    for job in state.db.get_jobs_by_filename(&old_name).await? {
+        if !matches!(job.status.as_str(), "done" | "imported") || StdPath::new(&job_folder(&job)) != old_folder {
+            continue;
+        }
+        if moved && job.all_files().len() > 1 {
+            continue;
+        }
+        let subfolder = moved.then(|| new_folder.to_string_lossy());
+        state.db.rename_job_file(&job.id, &old_name, &new_name, subfolder.as_deref()).await?;
+        jobs_updated += 1;
+    }
+
+    Ok(Json(serde_json::json!({
+        "from": from_index.to_string_lossy(),
+        "to": to_index.to_string_lossy(),
+        "jobsUpdated": jobs_updated
+    })).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/api/queue/{id}", tag = "queue", params(("id" = String, Path)),
+    responses((status = 200, body = Job), (status = 404))
+)]
+async fn get_job(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+) -> Result<Response, ApiError> {
+    let mut job = state.db.get_job(&id).await?.ok_or_else(|| ApiError::not_found("Job not found"))?;
+    if let Some((progress, eta)) = state.queue.live_progress(&job.id) {
+        job.progress = progress;
+        job.eta = eta;
+    }
+    Ok(Json(job).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/api/queue/{id}/headers", tag = "queue", params(("id" = String, Path)),
+    responses((status = 200, description = "The job's request headers (`{}` when it has none) and its own webhook URL"), (status = 404))
+)]
+async fn get_job_headers(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+) -> Result<Response, ApiError> {
+    let job = state.db.get_job(&id).await?.ok_or_else(|| ApiError::not_found("Job not found"))?;
+    Ok(Json(serde_json::json!({
+        "headers": job.headers.map(|h| h.0).unwrap_or_default(),
+        "webhookUrl": job.webhook_url,
+    })).into_response())
+}
+
+#[utoipa::path(
+    delete, path = "/api/queue/{id}", tag = "queue", params(("id" = String, Path)),
+    responses((status = 200, description = "Job deleted"), (status = 404))
+)]
 async fn delete_job(
     State(state): State<AppState>,
-    Path(id): Path<String>,
-) -> Response {
-    state.queue.cancel_job(&id);
-    if let Ok(true) = state.db.check_job_exists(&id).await {
-        let _ = state.db.delete_job(&id).await;
-        return Json(serde_json::json!({ "success": true, "id": id })).into_response();
+    ApiPath(id): ApiPath<String>,
+) -> Result<Response, ApiError> {
+    let result = apply_job_action(&state, JobAction::Delete, &id).await?;
+    Ok(Json(result).into_response())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ReconcileQuery {
+    /// Mark orphan jobs missing and drop duplicate job rows.
+    #[serde(default)]
+    fix: bool,
+}
+
+#[utoipa::path(
+    post, path = "/api/system/reconcile", tag = "system", params(ReconcileQuery),
+    responses((status = 200, body = ReconcileReport), (status = 503))
+)]
+async fn system_reconcile(
+    State(state): State<AppState>,
+    ApiQuery(q): ApiQuery<ReconcileQuery>,
+) -> Result<Response, ApiError> {
+    // Against an empty or half-built index every job would look orphaned.
+    if !data_root_available() {
+        return Err(ApiError::unavailable("Data root unavailable"));
     }
-    (StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": "Job not found" }))).into_response()
+    if !state.file_index.is_ready() || state.file_index.is_indexing() {
+        return Err(ApiError::unavailable("File index is still building"));
+    }
+    let report = reconcile(&state.db, &state.file_index, q.fix).await?;
+    Ok(Json(report).into_response())
 }
 
-async fn system_usage() -> Response {
-    match get_disk_usage().await {
-        Ok((size, count)) => Json(serde_json::json!({ "totalSize": size, "fileCount": count })).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get disk usage: {}", e)).into_response()
+#[utoipa::path(
+    get, path = "/api/system/usage", tag = "system",
+    responses((status = 200, description = "Total size and file count of the library"), (status = 503, description = "Data root unavailable"))
+)]
+async fn system_usage(State(state): State<AppState>) -> Result<Response, ApiError> {
+    if !data_root_available() {
+        return Err(ApiError::unavailable("Data root unavailable"));
     }
+    let threshold = state.queue.get_low_disk_threshold_bytes().await;
+    // The volume can vanish between the check above and the stat, so these
+    // are reported as null rather than failing the whole request.
+    let free = available_space().ok();
+    let total = total_space().ok();
+    let low_disk = threshold > 0 && free.is_some_and(|free| free < threshold);
+    let (size, count) = match state.file_index.disk_usage() {
+        Some(usage) => usage,
+        None => get_disk_usage().await
+            .map_err(|e| ApiError::internal(format!("Failed to get disk usage: {}", e)))?,
+    };
+    Ok(Json(serde_json::json!({
+        "totalSize": size,
+        "fileCount": count,
+        "freeBytes": free,
+        "totalBytes": total,
+        "lowDisk": low_disk,
+        "budget": state.queue.budget_status().await
+    })).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/api/system/info", tag = "system",
+    responses((status = 200, description = "Server version and detected tools"))
+)]
+async fn system_info(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "tools": state.capabilities,
+        "dataRootAvailable": data_root_available(),
+        "readOnly": state.config.read_only,
+        "index": state.file_index.status(),
+        "features": {
+            "sync": state.config.enable_sync,
+            "resolve": state.config.enable_resolve,
+            "import": state.config.enable_import
+        }
+    }))
 }
 
+#[utoipa::path(
+    get, path = "/api/settings", tag = "settings",
+    responses((status = 200, description = "Current settings"))
+)]
 async fn get_settings(State(state): State<AppState>) -> impl IntoResponse {
+    settings_json(&state).await
+}
+
+async fn settings_json(state: &AppState) -> Json<serde_json::Value> {
     let max = state.queue.get_max_concurrent().await;
     let sync_dest = state.queue.get_sync_destination().await;
-    Json(serde_json::json!({ "maxConcurrent": max, "syncDestination": sync_dest }))
+    let sync_transfers = state.queue.get_sync_transfers().await;
+    let sync_bwlimit = state.queue.get_sync_bwlimit().await;
+    let resume = state.queue.get_resume_downloads().await;
+    let keep_original = state.queue.get_keep_original().await;
+    let archive_after_sync = state.queue.get_archive_after_sync().await;
+    let verify_before_sync = state.queue.get_verify_before_sync().await;
+    let write_description = state.queue.get_write_description().await;
+    let write_comments = state.queue.get_write_comments().await;
+    let probe_media = state.queue.get_probe_media().await;
+    let require_stream = state.queue.get_require_stream().await;
+    let stall_timeout = state.queue.get_stall_timeout_seconds().await;
+    let progress_write_interval = state.queue.get_progress_write_interval_ms().await;
+    let use_archive = state.queue.get_use_download_archive().await;
+    let low_disk_threshold = state.queue.get_low_disk_threshold_bytes().await;
+    let ffmpeg_threads = state.queue.get_ffmpeg_threads().await;
+    let max_height = state.queue.get_max_height().await;
+    let output_container = state.queue.get_output_container().await;
+    let extra_args = state.queue.get_extra_ytdlp_args().await;
+    let extra_rclone_args = state.queue.get_extra_rclone_args().await;
+    let webhook_url = state.queue.get_webhook_url().await;
+    let (contact_sheet, _) = state.queue.get_contact_sheet().await;
+    Json(serde_json::json!({
+        "maxConcurrent": max,
+        "syncDestination": sync_dest,
+        "syncTransfers": sync_transfers,
+        "syncBwlimit": sync_bwlimit,
+        "resumeDownloads": resume,
+        "keepOriginal": keep_original,
+        "archiveAfterSync": archive_after_sync,
+        "verifyBeforeSync": verify_before_sync,
+        "writeDescription": write_description,
+        "writeComments": write_comments,
+        "probeMedia": probe_media,
+        "requireStream": require_stream,
+        "stallTimeoutSeconds": stall_timeout,
+        "progressWriteIntervalMs": progress_write_interval,
+        "useDownloadArchive": use_archive,
+        "lowDiskThresholdBytes": low_disk_threshold,
+        "ffmpegThreads": ffmpeg_threads,
+        "maxHeight": max_height,
+        "outputContainer": output_container,
+        "extraYtdlpArgs": extra_args,
+        "extraRcloneArgs": extra_rclone_args,
+        "webhookUrl": webhook_url,
+        "contactSheetFrames": contact_sheet.frames,
+        "contactSheetTileWidth": contact_sheet.tile_width
+    }))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct SettingsPayload {
     #[serde(rename = "maxConcurrent")]
     max_concurrent: usize,
     #[serde(rename = "syncDestination", default)]
     sync_destination: Option<String>,
+    #[serde(rename = "syncTransfers", default)]
+    sync_transfers: Option<usize>,
+    #[serde(rename = "syncBwlimit", default)]
+    sync_bwlimit: Option<String>,
+    #[serde(rename = "resumeDownloads", default)]
+    resume_downloads: Option<bool>,
+    #[serde(rename = "keepOriginal", default)]
+    keep_original: Option<bool>,
+    #[serde(rename = "archiveAfterSync", default)]
+    archive_after_sync: Option<bool>,
+    #[serde(rename = "verifyBeforeSync", default)]
+    verify_before_sync: Option<bool>,
+    #[serde(rename = "writeDescription", default)]
+    write_description: Option<bool>,
+    #[serde(rename = "writeComments", default)]
+    write_comments: Option<bool>,
+    #[serde(rename = "probeMedia", default)]
+    probe_media: Option<bool>,
+    #[serde(rename = "requireStream", default)]
+    require_stream: Option<String>,
+    #[serde(rename = "stallTimeoutSeconds", default)]
+    stall_timeout_seconds: Option<u64>,
+    #[serde(rename = "progressWriteIntervalMs", default)]
+    progress_write_interval_ms: Option<u64>,
+    #[serde(rename = "useDownloadArchive", default)]
+    use_download_archive: Option<bool>,
+    #[serde(rename = "lowDiskThresholdBytes", default)]
+    low_disk_threshold_bytes: Option<u64>,
+    #[serde(rename = "ffmpegThreads", default)]
+    ffmpeg_threads: Option<usize>,
+    #[serde(rename = "maxHeight", default)]
+    max_height: Option<u32>,
+    #[serde(rename = "outputContainer", default)]
+    output_container: Option<String>,
+    #[serde(rename = "extraYtdlpArgs", default)]
+    extra_ytdlp_args: Option<Vec<String>>,
+    #[serde(rename = "extraRcloneArgs", default)]
+    extra_rclone_args: Option<Vec<String>>,
+    #[serde(rename = "webhookUrl", default)]
+    webhook_url: Option<String>,
+    #[serde(rename = "contactSheetFrames", default)]
+    contact_sheet_frames: Option<u32>,
+    #[serde(rename = "contactSheetTileWidth", default)]
+    contact_sheet_tile_width: Option<u32>,
 }
 
+#[utoipa::path(
+    post, path = "/api/settings", tag = "settings", request_body = SettingsPayload,
+    responses((status = 200, description = "Updated settings"), (status = 400, description = "Nothing applied; `details` lists every invalid field"))
+)]
 async fn set_settings(
     State(state): State<AppState>,
-    Json(payload): Json<SettingsPayload>,
-) -> impl IntoResponse {
+    ApiJson(payload): ApiJson<SettingsPayload>,
+) -> Result<Response, ApiError> {
+    let errors = validate_settings(&payload);
+    if !errors.is_empty() {
+        return Err(ApiError::bad_request("Invalid settings").with_details(errors));
+    }
+
+    let guard = state.queue.lock_settings().await;
     state.queue.set_max_concurrent(payload.max_concurrent).await;
     if let Some(dest) = payload.sync_destination {
+        let dest = dest.trim().to_string();
+        if dest != state.queue.get_sync_destination().await {
+            state.queue.check_new_sync_destination(dest.clone());
+        }
         state.queue.set_sync_destination(dest).await;
     }
+    if let Some(transfers) = payload.sync_transfers {
+        state.queue.set_sync_transfers(transfers).await;
+    }
+    if let Some(limit) = payload.sync_bwlimit {
+        state.queue.set_sync_bwlimit(limit.trim().to_string()).await;
+    }
+    if let Some(resume) = payload.resume_downloads {
+        state.queue.set_resume_downloads(resume).await;
+    }
+    if let Some(keep) = payload.keep_original {
+        state.queue.set_keep_original(keep).await;
+    }
+    if let Some(enabled) = payload.archive_after_sync {
+        state.queue.set_archive_after_sync(enabled).await;
+    }
+    if let Some(enabled) = payload.verify_before_sync {
+        state.queue.set_verify_before_sync(enabled).await;
+    }
+    if let Some(enabled) = payload.write_description {
+        state.queue.set_write_description(enabled).await;
+    }
+    if let Some(enabled) = payload.write_comments {
+        state.queue.set_write_comments(enabled).await;
+    }
+    if let Some(enabled) = payload.probe_media {
+        state.queue.set_probe_media(enabled).await;
+    }
+    if let Some(kind) = payload.require_stream {
+        state.queue.set_require_stream(kind).await;
+    }
+    if let Some(seconds) = payload.stall_timeout_seconds {
+        state.queue.set_stall_timeout_seconds(seconds).await;
+    }
+    if let Some(ms) = payload.progress_write_interval_ms {
+        state.queue.set_progress_write_interval_ms(ms).await;
+    }
+    if let Some(enabled) = payload.use_download_archive {
+        state.queue.set_use_download_archive(enabled).await;
+    }
+    if let Some(bytes) = payload.low_disk_threshold_bytes {
+        state.queue.set_low_disk_threshold_bytes(bytes).await;
+    }
+    if let Some(threads) = payload.ffmpeg_threads {
+        state.queue.set_ffmpeg_threads(threads).await;
+    }
+    if let Some(height) = payload.max_height {
+        state.queue.set_max_height(height).await;
+    }
+    if let Some(container) = payload.output_container {
+        state.queue.set_output_container(container).await;
+    }
+    if let Some(args) = payload.extra_ytdlp_args {
+        state.queue.set_extra_ytdlp_args(args).await;
+    }
+    if let Some(args) = payload.extra_rclone_args {
+        state.queue.set_extra_rclone_args(args).await;
+    }
+    if let Some(url) = payload.webhook_url {
+        state.queue.set_webhook_url(url.trim().to_string()).await;
+    }
+    if let Some(frames) = payload.contact_sheet_frames {
+        state.queue.set_contact_sheet_frames(frames).await;
+    }
+    if let Some(width) = payload.contact_sheet_tile_width {
+        state.queue.set_contact_sheet_tile_width(width).await;
+    }
     
-    let max = state.queue.get_max_concurrent().await;
-    let sync_dest = state.queue.get_sync_destination().await;
-    Json(serde_json::json!({ "maxConcurrent": max, "syncDestination": sync_dest }))
+    drop(guard);
+
+    Ok(settings_json(&state).await.into_response())
+}
+
+fn validate_settings(payload: &SettingsPayload) -> Vec<String> {
+    let mut errors = Vec::new();
+    if payload.max_concurrent == 0 {
+        errors.push("maxConcurrent must be greater than 0".to_string());
+    }
+    if let Some(dest) = &payload.sync_destination {
+        if let Err(e) = validate_sync_destination(dest.trim()) {
+            errors.push(e);
+        }
+    }
+    if let Some(transfers) = payload.sync_transfers {
+        if transfers == 0 || transfers > MAX_SYNC_TRANSFERS {
+            errors.push(format!("syncTransfers must be between 1 and {}", MAX_SYNC_TRANSFERS));
+        }
+    }
+    if let Some(limit) = &payload.sync_bwlimit {
+        if let Err(e) = validate_sync_bwlimit(limit.trim()) {
+            errors.push(e);
+        }
+    }
+    if let Some(ms) = payload.progress_write_interval_ms {
+        if !PROGRESS_WRITE_INTERVAL_RANGE_MS.contains(&ms) {
+            errors.push(format!(
+                "progressWriteIntervalMs must be between {} and {}",
+                PROGRESS_WRITE_INTERVAL_RANGE_MS.start(),
+                PROGRESS_WRITE_INTERVAL_RANGE_MS.end()
+            ));
+        }
+    }
+    if let Some(kind) = &payload.require_stream {
+        if let Err(e) = validate_required_stream(kind) {
+            errors.push(e);
+        }
+    }
+    if let Some(threads) = payload.ffmpeg_threads {
+        if threads > MAX_FFMPEG_THREADS {
+            errors.push(format!("ffmpegThreads must be between 0 and {}", MAX_FFMPEG_THREADS));
+        }
+    }
+    if let Some(height) = payload.max_height {
+        if let Err(e) = validate_max_height(height) {
+            errors.push(e);
+        }
+    }
+    if let Some(container) = &payload.output_container {
+        if let Err(e) = validate_output_container(container) {
+            errors.push(e);
+        }
+    }
+    if let Some(args) = &payload.extra_ytdlp_args {
+        if let Err(e) = validate_extra_ytdlp_args(args) {
+            errors.push(e);
+        }
+    }
+    if let Some(args) = &payload.extra_rclone_args {
+        if let Err(e) = validate_extra_rclone_args(args) {
+            errors.push(e);
+        }
+    }
+    if let Some(url) = &payload.webhook_url {
+        let url = url.trim();
+        if !url.is_empty() {
+            if let Err(e) = validate_webhook_url(url) {
+                errors.push(e);
+            }
+        }
+    }
+    if let Some(frames) = payload.contact_sheet_frames {
+        if !CONTACT_SHEET_FRAMES_RANGE.contains(&frames) {
+            errors.push(format!(
+                "contactSheetFrames must be between {} and {}",
+                CONTACT_SHEET_FRAMES_RANGE.start(),
+                CONTACT_SHEET_FRAMES_RANGE.end()
+            ));
+        }
+    }
+    if let Some(width) = payload.contact_sheet_tile_width {
+        if !CONTACT_SHEET_TILE_WIDTH_RANGE.contains(&width) {
+            errors.push(format!(
+                "contactSheetTileWidth must be between {} and {}",
+                CONTACT_SHEET_TILE_WIDTH_RANGE.start(),
+                CONTACT_SHEET_TILE_WIDTH_RANGE.end()
+            ));
+        }
+    }
+    errors
+}
+
+#[utoipa::path(
+    post, path = "/api/sync/run", tag = "sync",
+    responses((status = 200, description = "Sync started"), (status = 500))
+)]
+async fn sync_run(State(state): State<AppState>) -> Result<Response, ApiError> {
+    let msg = state.queue.run_sync().await?;
+    Ok(Json(serde_json::json!({ "success": true, "message": msg })).into_response())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SyncValidateQuery {
+    /// Destination to check instead of the configured `syncDestination`
+    destination: Option<String>,
+}
+
+#[utoipa::path(
+    post, path = "/api/sync/validate", tag = "sync", params(SyncValidateQuery),
+    responses((status = 200, description = "Destination is reachable"), (status = 400), (status = 501), (status = 502, description = "rclone could not list the destination"))
+)]
+async fn sync_validate(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<SyncValidateQuery>,
+) -> Result<Response, ApiError> {
+    if !state.capabilities.rclone {
+        return Err(ApiError::not_implemented("rclone not installed"));
+    }
+    let dest = match query.destination {
+        Some(dest) => dest.trim().to_string(),
+        None => state.queue.get_sync_destination().await,
+    };
+    if dest.is_empty() {
+        return Err(ApiError::bad_request("No sync destination configured"));
+    }
+    validate_sync_destination(&dest).map_err(ApiError::bad_request)?;
+    check_sync_destination(&dest).await.map_err(ApiError::bad_gateway)?;
+    Ok(Json(serde_json::json!({ "success": true, "destination": dest })).into_response())
+}
+
+const MAX_API_KEY_NAME_LEN: usize = 100;
+
+#[derive(Deserialize, ToSchema)]
+struct CreateApiKeyPayload {
+    name: String,
+    scope: Scope,
+}
+
+#[utoipa::path(
+    get, path = "/api/keys", tag = "keys",
+    responses((status = 200, body = Vec<ApiKey>))
+)]
+async fn list_api_keys(State(state): State<AppState>) -> Result<Response, ApiError> {
+    Ok(Json(state.db.list_api_keys().await?).into_response())
+}
+
+#[utoipa::path(
+    post, path = "/api/keys", tag = "keys", request_body = CreateApiKeyPayload,
+    responses((status = 201, description = "The new key, including its secret `key`, which is not shown again"), (status = 400))
+)]
+async fn create_api_key(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<CreateApiKeyPayload>,
+) -> Result<Response, ApiError> {
+    let name = payload.name.trim();
+    if name.is_empty() || name.len() > MAX_API_KEY_NAME_LEN || name.chars().any(char::is_control) {
+        return Err(ApiError::bad_request(format!("name must be between 1 and {} characters", MAX_API_KEY_NAME_LEN)));
+    }
+    let key = generate_key();
+    let record = state.db.create_api_key(name, payload.scope.as_str(), &hash_key(&key), &key[..12]).await?;
+    let mut body = serde_json::json!(record);
+    body["key"] = serde_json::json!(key);
+    Ok((StatusCode::CREATED, Json(body)).into_response())
 }
 
-async fn sync_run(State(state): State<AppState>) -> impl IntoResponse {
-    match state.queue.run_sync().await {
-        Ok(msg) => Json(serde_json::json!({ "success": true, "message": msg })).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "success": false, "error": e.to_string() }))).into_response()
+#[utoipa::path(
+    delete, path = "/api/keys/{id}", tag = "keys", params(("id" = String, Path)),
+    responses((status = 200, description = "Key revoked"), (status = 404))
+)]
+async fn delete_api_key(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+) -> Result<Response, ApiError> {
+    if !state.db.delete_api_key(&id).await? {
+        return Err(ApiError::not_found("API key not found"));
     }
+    Ok(Json(serde_json::json!({ "success": true })).into_response())
 }
 
+#[utoipa::path(
+    get, path = "/api/sync/status", tag = "sync",
+    responses((status = 200, body = SyncState))
+)]
 async fn sync_status(State(state): State<AppState>) -> impl IntoResponse {
     Json(state.queue.get_sync_state().await)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct ZipPayload {
     paths: Vec<String>,
 }
 
+#[utoipa::path(
+    post, path = "/api/files/zip", tag = "files", request_body = ZipPayload,
+    responses(
+        (status = 200, description = "Zip archive", content_type = "application/zip",
+            headers(("x-skipped-count" = usize, description = "Requested paths left out because they are not files in the data roots"))),
+        (status = 400), (status = 404, description = "None of the requested files exist")
+    )
+)]
 async fn zip_files(
     State(_state): State<AppState>,
-    Json(payload): Json<ZipPayload>,
-) -> Response {
-    let paths = payload.paths;
-    if paths.is_empty() {
-        return (StatusCode::BAD_REQUEST, "No files to zip").into_response();
+    ApiJson(payload): ApiJson<ZipPayload>,
+) -> Result<Response, ApiError> {
+    if payload.paths.is_empty() {
+        return Err(ApiError::bad_request("No files to zip"));
     }
-    
-    let res = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, anyhow::Error> {
-        let mut buffer = Vec::new();
-        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(&mut buffer));
-        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
-
-        for p in paths {
-             let abs_path = StdPath::new(&p).canonicalize().unwrap_or_else(|_| PathBuf::from(&p));
-             let data_root = StdPath::new(DATA_ROOT).canonicalize().unwrap_or_else(|_| PathBuf::from(DATA_ROOT));
-             
-             if !abs_path.starts_with(&data_root) { continue; }
-             
-             if abs_path.is_file() {
-                 let name = abs_path.file_name().unwrap().to_string_lossy();
-                 zip.start_file(name, options)?;
-                 let content = std::fs::read(&abs_path)?;
-                 zip.write_all(&content)?;
-             }
-        }
-        zip.finish()?;
-        Ok(buffer)
-    }).await;
+    let (entries, skipped) = selected_zip_entries(&payload.paths)?;
+    let mut response = zip_response(entries, "videos.zip").await?;
+    response.headers_mut().insert(SKIPPED_COUNT_HEADER, HeaderValue::from(skipped));
+    Ok(response)
+}
+
+pub const SKIPPED_COUNT_HEADER: &str = "x-skipped-count";
 
-    match res {
-        Ok(Ok(buffer)) => {
-            let mut headers = HeaderMap::new();
-            headers.insert(header::CONTENT_TYPE, "application/zip".parse().unwrap());
-            headers.insert(header::CONTENT_DISPOSITION, "attachment; filename=\"videos.zip\"".parse().unwrap());
-            (headers, buffer).into_response()
+// Also counts the paths that don't name a file in the data roots; 404 when
+// that is all of them.
+fn selected_zip_entries(paths: &[String]) -> Result<(Vec<(PathBuf, String)>, usize), ApiError> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut skipped = 0;
+    for path in paths {
+        match canonicalize_data_path(path).filter(|abs| in_data_roots(abs) && abs.is_file()) {
+            Some(abs) if !files.contains(&abs) => files.push(abs),
+            Some(_) => {}
+            None => skipped += 1,
         }
-        _ => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create zip").into_response(),
     }
+    if files.is_empty() {
+        return Err(ApiError::not_found("None of the requested files exist"));
+    }
+    Ok((zip_entry_names(files), skipped))
 }
 
-#[derive(Deserialize)]
-struct FileQuery {
-    path: String,
+// Files are stored under their bare name unless another selected file has
+// the same one; those keep their path under the data root instead, e.g.
+// `2024-01-01/video.mp4`, so extracting the zip doesn't overwrite them.
+fn zip_entry_names(files: Vec<PathBuf>) -> Vec<(PathBuf, String)> {
+    let file_name = |abs: &PathBuf| abs.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for abs in &files {
+        *counts.entry(file_name(abs)).or_default() += 1;
+    }
+    let data_root = StdPath::new(DATA_ROOT).canonicalize().unwrap_or_else(|_| PathBuf::from(DATA_ROOT));
+    let mut used = std::collections::HashSet::new();
+    files
+        .into_iter()
+        .map(|abs| {
+            let name = file_name(&abs);
+            let mut entry = if counts[&name] > 1 {
+                let relative = index_path(&abs, &data_root);
+                relative.strip_prefix(DATA_ROOT).unwrap_or(&relative).to_string_lossy().to_string()
+            } else {
+                name
+            };
+            // The same relative path on both tiers.
+            let base = entry.clone();
+            let (stem, ext) = match base.rsplit_once('.') {
+                Some((stem, ext)) if !stem.is_empty() && !stem.ends_with('/') => (stem, format!(".{}", ext)),
+                _ => (base.as_str(), String::new()),
+            };
+            let mut n = 1;
+            while !used.insert(entry.clone()) {
+                n += 1;
+                entry = format!("{} ({}){}", stem, n, ext);
+            }
+            (abs, entry)
+        })
+        .collect()
 }
 
-async fn download_file(
-    Query(params): Query<FileQuery>,
-) -> Response {
-    let p = params.path;
-    let abs_path = StdPath::new(&p).canonicalize().unwrap_or_else(|_| PathBuf::from(&p));
-    let data_root = StdPath::new(DATA_ROOT).canonicalize().unwrap_or_else(|_| PathBuf::from(DATA_ROOT));
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ZipFolderQuery {
+    folder: String,
+}
+
+#[utoipa::path(
+    get, path = "/api/files/zip", tag = "files", params(ZipFolderQuery),
+    responses((status = 200, description = "Zip archive of the folder", content_type = "application/zip"), (status = 400), (status = 404))
+)]
+async fn zip_folder(ApiQuery(q): ApiQuery<ZipFolderQuery>) -> Result<Response, ApiError> {
+    let entries = folder_zip_entries(&q.folder).await?;
+    zip_response(entries, &format!("{}.zip", q.folder)).await
+}
 
-    if !abs_path.starts_with(&data_root) {
-        return (StatusCode::FORBIDDEN, "Access denied").into_response();
+async fn folder_zip_entries(folder: &str) -> Result<Vec<(PathBuf, String)>, ApiError> {
+    if folder.is_empty() || folder == "." || folder == ".." || folder.contains(['/', '\\']) || folder.contains("jobs.sqlite") {
+        return Err(ApiError::bad_request("Invalid folder"));
     }
 
-    if !abs_path.exists() {
-        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    let dirs = data_folders(folder);
+    if dirs.is_empty() {
+        return Err(ApiError::not_found("Folder not found"));
     }
-    
-    let metadata = match tokio::fs::metadata(&abs_path).await {
-        Ok(meta) => meta,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read metadata: {}", e)).into_response(),
+
+    let entries = tokio::task::spawn_blocking(move || {
+        // Keyed by name so a file on both tiers is only zipped once.
+        let mut entries = std::collections::BTreeMap::new();
+        for walk_root in &dirs {
+            let files = walkdir::WalkDir::new(walk_root)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file());
+            for e in files {
+                let name = e.path().strip_prefix(walk_root).unwrap_or(e.path()).to_string_lossy().to_string();
+                entries.entry(name).or_insert_with(|| e.path().to_path_buf());
+            }
+        }
+        entries.into_iter().map(|(name, path)| (path, name)).collect::<Vec<_>>()
+    }).await.unwrap_or_default();
+    Ok(entries)
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ZipJobPayload {
+    /// Files to zip, as for `POST /api/files/zip`
+    paths: Option<Vec<String>>,
+    /// A top-level folder, as for `GET /api/files/zip`
+    folder: Option<String>,
+    /// Every indexed file, stored under its path below the data root
+    #[serde(default)]
+    all: bool,
+}
+
+#[utoipa::path(
+    post, path = "/api/files/zip/jobs", tag = "files", request_body = ZipJobPayload,
+    responses(
+        (status = 202, body = ZipJobStatus), (status = 400), (status = 403, description = "`all` without the write scope or in read-only mode"), (status = 404),
+        (status = 429, description = "Too many zip jobs queued or running"), (status = 507, description = "Not enough free space for the archive")
+    )
+)]
+async fn create_zip_job(
+    State(state): State<AppState>,
+    scope: Option<Extension<Scope>>,
+    ApiJson(payload): ApiJson<ZipJobPayload>,
+) -> Result<Response, ApiError> {
+    let (entries, name) = match (payload.paths, payload.folder, payload.all) {
+        (Some(paths), None, false) => (selected_zip_entries(&paths)?.0, "videos.zip".to_string()),
+        (None, Some(folder), false) => (folder_zip_entries(&folder).await?, format!("{}.zip", folder)),
+        (None, None, true) => {
+            // A copy of the whole library is too big to hand to read-only
+            // callers.
+            if state.config.read_only {
+                return Err(ApiError::forbidden("Zipping the whole library is disabled in read-only mode"));
+            }
+            if scope.is_some_and(|Extension(scope)| scope < Scope::Write) {
+                return Err(ApiError::forbidden("Zipping the whole library needs the 'write' scope"));
+            }
+            let mut names = HashSet::new();
+            let entries = state.file_index.all_files()
+                .into_iter()
+                .filter_map(|item| {
+                    let abs = canonicalize_data_path(&item.path)?;
+                    let name = item.path.strip_prefix(DATA_ROOT)?.trim_start_matches('/').to_string();
+                    names.insert(name.clone()).then_some((abs, name))
+                })
+                .collect();
+            (entries, "library.zip".to_string())
+        }
+        _ => return Err(ApiError::bad_request("Expected exactly one of paths, folder or all")),
+    };
+    if entries.is_empty() {
+        return Err(ApiError::bad_request("No files to zip"));
+    }
+    let id = zip_job::start(entries, name).await.map_err(|e| match e {
+        zip_job::StartError::TooMany => ApiError::new(StatusCode::TOO_MANY_REQUESTS, "too_many_zip_jobs", e.to_string()),
+        zip_job::StartError::NoSpace { .. } => ApiError::new(StatusCode::INSUFFICIENT_STORAGE, "insufficient_storage", e.to_string()),
+        zip_job::StartError::Io(_) => ApiError::internal(e.to_string()),
+    })?;
+    let status = zip_job::status(&id).await.ok_or_else(|| ApiError::internal("Zip job vanished"))?;
+    Ok((StatusCode::ACCEPTED, Json(status)).into_response())
+}
+
+fn zip_job_id(id: &str) -> Result<uuid::Uuid, ApiError> {
+    uuid::Uuid::parse_str(id).map_err(|_| ApiError::not_found("Zip job not found"))
+}
+
+#[utoipa::path(
+    get, path = "/api/files/zip/jobs/{id}", tag = "files", params(("id" = String, Path)),
+    responses((status = 200, body = ZipJobStatus), (status = 404))
+)]
+async fn zip_job_status(ApiPath(id): ApiPath<String>) -> Result<Response, ApiError> {
+    let status = zip_job::status(&zip_job_id(&id)?).await.ok_or_else(|| ApiError::not_found("Zip job not found"))?;
+    Ok(Json(status).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/api/files/zip/jobs/{id}/download", tag = "files", params(("id" = String, Path)),
+    responses(
+        (status = 200, description = "Zip archive", content_type = "application/zip"), (status = 206, description = "Partial content"),
+        (status = 404), (status = 409, description = "The archive is not finished"), (status = 416)
+    )
+)]
+async fn download_zip_job(
+    ApiPath(id): ApiPath<String>,
+    req: axum::extract::Request,
+) -> Result<Response, ApiError> {
+    let id = zip_job_id(&id)?;
+    let Some(path) = zip_job::archive_path(&id).await else {
+        return match zip_job::status(&id).await {
+            Some(status) => Err(ApiError::conflict(format!("Zip job is {}", status.status))),
+            None => Err(ApiError::not_found("Zip job not found")),
+        };
+    };
+    let mut response = serve_file(&path, req.headers()).await;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    response.headers_mut().insert(header::CONTENT_DISPOSITION, attachment_disposition(&name));
+    Ok(response)
+}
+
+#[utoipa::path(
+    delete, path = "/api/files/zip/jobs/{id}", tag = "files", params(("id" = String, Path)),
+    responses((status = 200, description = "Zip job and archive removed"), (status = 404))
+)]
+async fn delete_zip_job(ApiPath(id): ApiPath<String>) -> Result<Response, ApiError> {
+    if !zip_job::remove(&zip_job_id(&id)?).await {
+        return Err(ApiError::not_found("Zip job not found"));
+    }
+    Ok(Json(serde_json::json!({ "success": true })).into_response())
+}
+
+// The archive is written to an already unlinked temp file inside the data
+// root and streamed from there, so zipping a large folder does not hold the
+// whole archive in memory (or fill a small tmpfs).
+fn write_zip(entries: Vec<(PathBuf, String)>) -> Result<std::fs::File, anyhow::Error> {
+    let tmp_path = StdPath::new(DATA_ROOT).join(format!(".zip-{}.tmp", uuid::Uuid::new_v4()));
+    let file = std::fs::OpenOptions::new().read(true).write(true).create_new(true).open(&tmp_path)?;
+    let _ = std::fs::remove_file(&tmp_path);
+
+    let mut file = zip_job::write_entries(file, &entries, |_, _| {})?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+async fn zip_response(entries: Vec<(PathBuf, String)>, filename: &str) -> Result<Response, ApiError> {
+    let file = match tokio::task::spawn_blocking(move || write_zip(entries)).await {
+        Ok(Ok(file)) => file,
+        _ => return Err(ApiError::internal("Failed to create zip")),
     };
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let body = Body::from_stream(ReaderStream::new(AsyncFile::from_std(file)));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/zip"));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    headers.insert(header::CONTENT_DISPOSITION, attachment_disposition(filename));
+    Ok((headers, body).into_response())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct FileQuery {
+    path: String,
+}
+
+#[utoipa::path(
+    get, path = "/api/files/download", tag = "files", params(FileQuery),
+    responses((status = 200, description = "File attachment"), (status = 403), (status = 404))
+)]
+async fn download_file(
+    ApiQuery(params): ApiQuery<FileQuery>,
+) -> Result<Response, ApiError> {
+    let abs_path = resolve_data_file(&params.path)?;
+    
+    let metadata = tokio::fs::metadata(&abs_path).await
+        .map_err(|e| ApiError::internal(format!("Failed to read metadata: {}", e)))?;
 
     let file_size = metadata.len();
     let modified = metadata.modified().unwrap_or(std::time::SystemTime::UNIX_EPOCH);
     let last_modified = chrono::DateTime::<chrono::Utc>::from(modified).format("%a, %d %b %Y %H:%M:%S GMT").to_string();
     let etag = format!(r#""{}-{}""#, file_size, modified.duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs());
     
-    match AsyncFile::open(&abs_path).await {
-        Ok(file) => {
-             let stream = ReaderStream::new(file);
-             let body = Body::from_stream(stream);
-             let filename = abs_path.file_name().unwrap().to_string_lossy().to_string();
-             
-             let mut headers = HeaderMap::new();
-             headers.insert(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename).parse().unwrap());
-             headers.insert(header::CONTENT_LENGTH, HeaderValue::from_str(&file_size.to_string()).unwrap());
-             headers.insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
-             headers.insert(header::LAST_MODIFIED, HeaderValue::from_str(&last_modified).unwrap());
-             headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=3600"));
-             
-             (headers, body).into_response()
+    let file = AsyncFile::open(&abs_path).await
+        .map_err(|_| ApiError::internal("Failed to open file"))?;
+    let stream = ReaderStream::new(file);
+    let body = Body::from_stream(stream);
+    let filename = abs_path.file_name().unwrap().to_string_lossy().to_string();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_DISPOSITION, attachment_disposition(&filename));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from_str(&file_size.to_string()).unwrap());
+    headers.insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+    headers.insert(header::LAST_MODIFIED, HeaderValue::from_str(&last_modified).unwrap());
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=3600"));
+
+    Ok((headers, body).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/api/files/stream", tag = "files", params(FileQuery),
+    responses((status = 200, description = "File contents"), (status = 206, description = "Partial content"), (status = 403), (status = 404), (status = 416))
+)]
+async fn stream_file(
+    ApiQuery(params): ApiQuery<FileQuery>,
+    req: axum::extract::Request,
+) -> Result<Response, ApiError> {
+    let abs_path = resolve_data_file(&params.path)?;
+    Ok(serve_file(&abs_path, req.headers()).await)
+}
+
+const SHARE_TTL_DEFAULT_SECONDS: i64 = 24 * 60 * 60;
+const SHARE_TTL_MAX_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Deserialize, ToSchema)]
+struct SharePayload {
+    path: String,
+    /// How long the link works, up to a week (default a day)
+    #[serde(rename = "expiresInSeconds", default)]
+    expires_in_seconds: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ShareResponse {
+    token: String,
+    /// Relative to the server, e.g. `/api/files/shared/<token>`
+    url: String,
+    #[serde(rename = "expiresAt")]
+    expires_at: i64,
+}
+
+#[utoipa::path(
+    post, path = "/api/files/share", tag = "files", request_body = SharePayload,
+    responses((status = 200, body = ShareResponse), (status = 400), (status = 403), (status = 404))
+)]
+async fn share_file(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<SharePayload>,
+) -> Result<Response, ApiError> {
+    let ttl = payload.expires_in_seconds.unwrap_or(SHARE_TTL_DEFAULT_SECONDS);
+    if !(1..=SHARE_TTL_MAX_SECONDS).contains(&ttl) {
+        return Err(ApiError::bad_request(format!("expiresInSeconds must be between 1 and {}", SHARE_TTL_MAX_SECONDS)));
+    }
+    if !resolve_data_file(&payload.path)?.is_file() {
+        return Err(ApiError::bad_request("Only files can be shared"));
+    }
+    let expires_at = chrono::Utc::now().timestamp_millis() + ttl * 1000;
+    let token = share::sign(&state.config.share_secret, &payload.path, expires_at);
+    Ok(Json(ShareResponse { url: format!("/api/files/shared/{}", token), token, expires_at }).into_response())
+}
+
+// Needs no API key; the token itself is the permission.
+#[utoipa::path(
+    get, path = "/api/files/shared/{token}", tag = "files", params(("token" = String, Path)),
+    responses((status = 200, description = "File contents"), (status = 206, description = "Partial content"), (status = 403), (status = 404), (status = 416))
+)]
+async fn shared_file(
+    State(state): State<AppState>,
+    ApiPath(token): ApiPath<String>,
+    req: axum::extract::Request,
+) -> Result<Response, ApiError> {
+    let path = share::verify(&state.config.share_secret, &token, chrono::Utc::now().timestamp_millis())
+        .map_err(ApiError::forbidden)?;
+    let abs_path = resolve_data_file(&path)?;
+    Ok(serve_file(&abs_path, req.headers()).await)
+}
+
+const CONTACT_SHEET_FRAMES_RANGE: std::ops::RangeInclusive<u32> = 1..=100;
+const CONTACT_SHEET_TILE_WIDTH_RANGE: std::ops::RangeInclusive<u32> = 32..=1920;
+
+#[utoipa::path(
+    get, path = "/api/files/contact-sheet", tag = "files", params(FileQuery),
+    responses(
+        (status = 200, description = "JPEG grid of evenly spaced frames", content_type = "image/jpeg"),
+        (status = 400, description = "Not a video"), (status = 403), (status = 404), (status = 501, description = "ffmpeg or ffprobe not installed")
+    )
+)]
+async fn contact_sheet(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<FileQuery>,
+    req: axum::extract::Request,
+) -> Result<Response, ApiError> {
+    if !(state.capabilities.ffmpeg && state.capabilities.ffprobe) {
+        return Err(ApiError::not_implemented("ffmpeg and ffprobe are required for contact sheets"));
+    }
+    let abs_path = resolve_data_file(&params.path)?;
+    let name = abs_path.file_name().unwrap_or_default().to_string_lossy();
+    if !abs_path.is_file() || is_sidecar(&name) {
+        return Err(ApiError::bad_request("Not a video file"));
+    }
+    let data_root = StdPath::new(DATA_ROOT).canonicalize().unwrap_or_else(|_| PathBuf::from(DATA_ROOT));
+    let cached = crate::media::contact_sheet_path(&index_path(&abs_path, &data_root).to_string_lossy());
+    let (options, changed_at) = state.queue.get_contact_sheet().await;
+    // A read-only server keeps nothing: a sheet that isn't cached yet is
+    // made in a temporary file, removed once it is opened for the response.
+    let temporary = state.config.read_only && !crate::media::contact_sheet_is_fresh(&abs_path, &cached, changed_at);
+    let sheet = if temporary {
+        std::env::temp_dir().join(format!("tiak-contact-{}.jpg", uuid::Uuid::new_v4()))
+    } else {
+        cached
+    };
+    crate::media::contact_sheet(&abs_path, &sheet, options, changed_at).await.map_err(|e| {
+        if e.is::<NoVideoStream>() {
+            ApiError::bad_request(e.to_string())
+        } else {
+            ApiError::internal(format!("Failed to create contact sheet: {}", e))
+        }
+    })?;
+    let response = serve_file(&sheet, req.headers()).await;
+    if temporary {
+        let _ = tokio::fs::remove_file(&sheet).await;
+    }
+    Ok(response)
+}
+
+#[derive(Serialize, ToSchema)]
+struct FileDescription {
+    description: Option<String>,
+    comments: Option<Vec<FileComment>>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FileComment {
+    author: Option<String>,
+    text: Option<String>,
+    #[serde(rename = "likeCount")]
+    like_count: Option<i64>,
+    timestamp: Option<i64>,
+}
+
+#[utoipa::path(
+    get, path = "/api/files/description", tag = "files", params(FileQuery),
+    responses((status = 200, body = FileDescription), (status = 403), (status = 404, description = "File or its description/comments not found"))
+)]
+async fn file_description(ApiQuery(params): ApiQuery<FileQuery>) -> Result<Response, ApiError> {
+    let abs_path = resolve_data_file(&params.path)?;
+    let [description_path, info_path, _] = sidecar_paths(&abs_path);
+
+    let description = tokio::fs::read_to_string(description_path).await.ok();
+    let comments = match tokio::fs::read(info_path).await {
+        Ok(bytes) => {
+            let info: serde_json::Value = serde_json::from_slice(&bytes)
+                .map_err(|e| ApiError::internal(format!("Failed to parse info json: {}", e)))?;
+            info["comments"].as_array().map(|list| list.iter().map(|c| FileComment {
+                author: c["author"].as_str().map(str::to_string),
+                text: c["text"].as_str().map(str::to_string),
+                like_count: c["like_count"].as_i64(),
+                timestamp: c["timestamp"].as_i64(),
+            }).collect())
         }
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to open file").into_response()
+        Err(_) => None,
+    };
+
+    if description.is_none() && comments.is_none() {
+        return Err(ApiError::not_found("No description or comments saved for this file"));
+    }
+    Ok(Json(FileDescription { description, comments }).into_response())
+}
+
+// Resolves a client supplied `data/...` path, refusing anything that
+// canonicalizes to outside DATA_ROOT and the cold root.
+fn resolve_data_file(p: &str) -> Result<PathBuf, ApiError> {
+    let abs_path = canonicalize_data_path(p).unwrap_or_else(|| PathBuf::from(p));
+
+    if !in_data_roots(&abs_path) {
+        return Err(ApiError::forbidden("Access denied"));
+    }
+    if !abs_path.exists() {
+        return Err(ApiError::not_found("File not found"));
     }
+    Ok(abs_path)
 }
 
-async fn stream_file(
-    Query(params): Query<FileQuery>,
-    req: axum::extract::Request,
-) -> Response {
-    let p = params.path;
-    let abs_path = StdPath::new(&p).canonicalize().unwrap_or_else(|_| PathBuf::from(&p));
-    let data_root = StdPath::new(DATA_ROOT).canonicalize().unwrap_or_else(|_| PathBuf::from(DATA_ROOT));
+// Characters RFC 5987 allows unencoded in `filename*`.
+const FILENAME_ATTR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!').remove(b'#').remove(b'$').remove(b'&').remove(b'+').remove(b'-')
+    .remove(b'.').remove(b'^').remove(b'_').remove(b'`').remove(b'|').remove(b'~');
 
-    if !abs_path.starts_with(&data_root) {
-        return (StatusCode::FORBIDDEN, "Access denied").into_response();
-    }
+// `filename` is an ASCII fallback for old clients, with quotes, backslashes,
+// control and non-ASCII characters replaced; browsers use the UTF-8
+// `filename*` instead.
+fn attachment_disposition(filename: &str) -> HeaderValue {
+    let fallback: String = filename
+        .chars()
+        .map(|c| if c == ' ' || (c.is_ascii_graphic() && c != '"' && c != '\\') { c } else { '_' })
+        .collect();
+    let value = format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(filename, FILENAME_ATTR)
+    );
+    HeaderValue::from_str(&value).unwrap_or(HeaderValue::from_static("attachment"))
+}
 
-    if !abs_path.exists() {
-        return (StatusCode::NOT_FOUND, "File not found").into_response();
+pub fn content_type_for(path: &StdPath) -> &'static str {
+    let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    match ext.as_str() {
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "mov" => "video/quicktime",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "opus" | "ogg" => "audio/ogg",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        "json" => "application/json",
+        "txt" | "description" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
     }
+}
 
-    let metadata = match tokio::fs::metadata(&abs_path).await {
+// Serves a file that has already been validated to live under DATA_ROOT,
+// honoring conditional and Range requests.
+pub async fn serve_file(abs_path: &StdPath, req_headers: &HeaderMap) -> Response {
+    let metadata = match tokio::fs::metadata(abs_path).await {
         Ok(meta) => meta,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read metadata: {}", e)).into_response(),
+        Err(e) => return ApiError::internal(format!("Failed to read metadata: {}", e)).into_response(),
     };
 
+    let content_type = HeaderValue::from_static(content_type_for(abs_path));
     let file_size = metadata.len();
     let modified = metadata.modified().unwrap_or(std::time::SystemTime::UNIX_EPOCH);
     let last_modified = chrono::DateTime::<chrono::Utc>::from(modified).format("%a, %d %b %Y %H:%M:%S GMT").to_string();
     
     let etag = format!(r#""{}-{}""#, file_size, modified.duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs());
 
-    if let Some(if_none_match) = req.headers().get(header::IF_NONE_MATCH) {
+    if let Some(if_none_match) = req_headers.get(header::IF_NONE_MATCH) {
         if if_none_match.to_str().unwrap_or("") == etag {
             return StatusCode::NOT_MODIFIED.into_response();
         }
     }
 
-    let range_header = req.headers().get(header::RANGE);
-    
+    let range_header = req_headers.get(header::RANGE);
+
+    // An empty file has no satisfiable byte range.
+    if range_header.is_some() && file_size == 0 {
+        let mut response = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_RANGE, HeaderValue::from_static("bytes */0"));
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        return response;
+    }
+
     if let Some(range) = range_header {
         if let Some((start, end)) = parse_range_header(range.to_str().unwrap_or(""), file_size) {
             use tokio::io::{AsyncReadExt, AsyncSeekExt};
             
-            let mut file = match AsyncFile::open(&abs_path).await {
+            let mut file = match AsyncFile::open(abs_path).await {
                 Ok(f) => f,
-                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open file: {}", e)).into_response(),
+                Err(e) => return ApiError::internal(format!("Failed to open file: {}", e)).into_response(),
             };
 
             if let Err(e) = file.seek(std::io::SeekFrom::Start(start)).await {
-                return (StatusCode::INTERNAL_SERVER_ERROR, format!("Seek failed: {}", e)).into_response();
+                return ApiError::internal(format!("Seek failed: {}", e)).into_response();
             }
 
             let take_len = end - start + 1;
@@ -353,7 +1681,7 @@ async fn stream_file(
             *response.status_mut() = StatusCode::PARTIAL_CONTENT;
             
             let headers = response.headers_mut();
-            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("video/mp4")); 
+            headers.insert(header::CONTENT_TYPE, content_type.clone());
             headers.insert(
                 header::CONTENT_RANGE,
                 HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, file_size)).unwrap()
@@ -371,14 +1699,14 @@ async fn stream_file(
         }
     }
 
-    match AsyncFile::open(&abs_path).await {
+    match AsyncFile::open(abs_path).await {
         Ok(file) => {
             let stream = ReaderStream::new(file);
             let body = Body::from_stream(stream);
             
             let mut response = Response::new(body);
             let headers = response.headers_mut();
-            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("video/mp4"));
+            headers.insert(header::CONTENT_TYPE, content_type);
             headers.insert(header::CONTENT_LENGTH, HeaderValue::from_str(&file_size.to_string()).unwrap());
             headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
             headers.insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
@@ -387,12 +1715,12 @@ async fn stream_file(
             
             response
         }
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to open file").into_response()
+        Err(_) => ApiError::internal("Failed to open file").into_response()
     }
 }
 
 fn parse_range_header(range: &str, file_size: u64) -> Option<(u64, u64)> {
-    if !range.starts_with("bytes=") {
+    if file_size == 0 || !range.starts_with("bytes=") {
         return None;
     }
     
@@ -422,137 +1750,1013 @@ fn parse_range_header(range: &str, file_size: u64) -> Option<(u64, u64)> {
     Some((start, end))
 }
 
-async fn list_queue(State(state): State<AppState>) -> Response {
-    if let Ok(jobs) = state.db.get_all_jobs().await {
-        Json(jobs).into_response()
-    } else {
-        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch jobs").into_response()
+#[utoipa::path(
+    get, path = "/api/queue/list", tag = "queue",
+    responses((status = 200, body = Vec<Job>))
+)]
+async fn list_queue(State(state): State<AppState>) -> Result<Response, ApiError> {
+    let mut jobs = state.db.get_all_jobs().await?;
+    // Running jobs report the latest parsed progress, which may be ahead
+    // of what has been written to the database.
+    for job in &mut jobs {
+        if let Some((progress, eta)) = state.queue.live_progress(&job.id) {
+            job.progress = progress;
+            job.eta = eta;
+        }
     }
+    Ok(Json(jobs).into_response())
 }
 
-#[derive(Deserialize)]
+// Each update is an SSE event named after its `type`, with the whole
+// `JobUpdate` as data. A subscriber that falls behind gets a `lagged` event
+// with the number of updates it missed and should refetch the list.
+#[utoipa::path(
+    get, path = "/api/queue/events", tag = "queue",
+    responses((status = 200, description = "text/event-stream of job updates"))
+)]
+async fn queue_events(State(state): State<AppState>) -> Sse<impl futures::Stream<Item = Result<Event, Infallible>>> {
+    let (mut updates, stopping) = state.queue.subscribe();
+    // Dropped with the response when the client disconnects, which also
+    // drops the subscription.
+    let stream = async_stream::stream! {
+        loop {
+            let update = tokio::select! {
+                update = updates.recv() => update,
+                _ = stopping.cancelled() => break,
+            };
+            match update {
+                Ok(update) => {
+                    if let Ok(event) = Event::default().event(update.kind).json_data(&update) {
+                        yield Ok(event);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    yield Ok(Event::default().event("lagged").data(missed.to_string()));
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    };
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[derive(Deserialize, ToSchema)]
 struct AddQueuePayload {
     urls: String,
+    #[serde(default)]
+    headers: Option<HashMap<String, String>>,
+    #[serde(rename = "outputContainer", default)]
+    output_container: Option<String>,
+    #[serde(default)]
+    subfolder: Option<String>,
+    // A project folder, i.e. a single-level `subfolder`.
+    #[serde(default)]
+    tag: Option<String>,
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(rename = "audioOnly", default)]
+    audio_only: bool,
+    // `mp3` converts extracted audio; only with `audioOnly`.
+    #[serde(rename = "audioFormat", default)]
+    audio_format: Option<String>,
+    #[serde(default)]
+    priority: i64,
+    // Unix timestamp in milliseconds before which the jobs don't start.
+    #[serde(rename = "startAfter", default)]
+    start_after: Option<i64>,
+    // Called when each job finishes instead of the `webhookUrl` setting.
+    #[serde(rename = "webhookUrl", default)]
+    webhook_url: Option<String>,
+    // Replaces the `maxHeight` setting for these jobs; `0` lifts the cap.
+    #[serde(rename = "maxHeight", alias = "max_height", default)]
+    max_height: Option<u32>,
+}
+
+const MAX_SCHEDULE_AHEAD_MS: i64 = 365 * 24 * 60 * 60 * 1000;
+
+// Stable tag for clients to branch on; `reason` is the message for humans.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SkipReason {
+    InQueue,
+    AlreadyDone,
+    InvalidUrl,
+    DomainNotAllowed,
+    // Reserved for when playlists are expanded into jobs; URLs are added as
+    // single jobs for now, so nothing sends it yet.
+    #[allow(dead_code)]
+    PlaylistTooLarge,
+    Error,
+}
+
+#[derive(Serialize)]
+struct SkippedUrl {
+    url: String,
+    code: SkipReason,
+    reason: String,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    done: Option<DoneJobRef>,
+}
+
+#[derive(Serialize)]
+struct DoneJobRef {
+    #[serde(rename = "jobId")]
+    job_id: String,
+    #[serde(rename = "finishedAt")]
+    finished_at: Option<i64>,
+    #[serde(rename = "fileExists")]
+    file_exists: Option<bool>,
+}
+
+impl SkippedUrl {
+    fn new(url: &str, code: SkipReason, reason: impl Into<String>) -> Self {
+        Self { url: url.to_string(), code, reason: reason.into(), done: None }
+    }
+}
+
+// The options every job of an add request gets; shared by the dry run.
+fn add_job_options(payload: AddQueuePayload, scope: Option<Extension<Scope>>) -> Result<JobOptions, ApiError> {
+    if let Some(headers) = &payload.headers {
+        validate_job_headers(headers).map_err(ApiError::bad_request)?;
+    }
+    if let Some(container) = &payload.output_container {
+        validate_output_container(container).map_err(ApiError::bad_request)?;
+    }
+    let subfolder = match (payload.subfolder, payload.tag) {
+        (Some(_), Some(_)) => return Err(ApiError::bad_request("Use either subfolder or tag, not both")),
+        (Some(subfolder), None) => Some(subfolder.trim().trim_end_matches('/').to_string()),
+        (None, Some(tag)) => {
+            let tag = tag.trim().to_string();
+            if tag.contains('/') {
+                return Err(ApiError::bad_request(format!("Invalid tag '{}'", tag)));
+            }
+            Some(tag)
+        }
+        (None, None) => None,
+    };
+    if let Some(subfolder) = &subfolder {
+        validate_subfolder(subfolder).map_err(ApiError::bad_request)?;
+    }
+    if let Some(format) = &payload.format {
+        validate_format(format).map_err(ApiError::bad_request)?;
+    }
+    if let Some(audio_format) = &payload.audio_format {
+        validate_audio_format(audio_format).map_err(ApiError::bad_request)?;
+        if !payload.audio_only {
+            return Err(ApiError::bad_request("audioFormat requires audioOnly"));
+        }
+    }
+    if let Some(at) = payload.start_after {
+        let latest = chrono::Utc::now().timestamp_millis() + MAX_SCHEDULE_AHEAD_MS;
+        if at <= 0 || at > latest {
+            return Err(ApiError::bad_request("startAfter must be a Unix timestamp in milliseconds within the next year"));
+        }
+    }
+    let webhook_url = payload.webhook_url.map(|url| url.trim().to_string());
+    if let Some(url) = &webhook_url {
+        // The server posts job JSON to it, so it is as sensitive as the
+        // `webhookUrl` setting.
+        if scope.is_some_and(|Extension(scope)| scope < Scope::Admin) {
+            return Err(ApiError::forbidden("webhookUrl needs the 'admin' scope"));
+        }
+        validate_webhook_url(url).map_err(ApiError::bad_request)?;
+    }
+    if let Some(height) = payload.max_height {
+        validate_max_height(height).map_err(ApiError::bad_request)?;
+    }
+    Ok(JobOptions {
+        headers: payload.headers,
+        output_container: payload.output_container,
+        subfolder,
+        format: payload.format,
+        audio_only: payload.audio_only,
+        // Audio-only jobs are converted to mp3 unless `original` is asked for.
+        audio_format: payload.audio_format.or_else(|| payload.audio_only.then(|| "mp3".to_string())),
+        priority: payload.priority,
+        start_after: payload.start_after,
+        webhook_url,
+        max_height: payload.max_height.map(i64::from),
+        ..Default::default()
+    })
+}
+
+// What adding a single URL would do.
+enum UrlCheck {
+    // A done job whose files are gone is queued again, linked to it.
+    Add { redownloaded_from: Option<String> },
+    Skip(SkippedUrl),
+}
+
+async fn check_url(state: &AppState, url: &str) -> Result<UrlCheck, ApiError> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Ok(UrlCheck::Skip(SkippedUrl::new(url, SkipReason::InvalidUrl, "Not an http(s) URL")));
+    }
+
+    if let Err(reason) = state.config.domain_filter.check(url) {
+        return Ok(UrlCheck::Skip(SkippedUrl::new(url, SkipReason::DomainNotAllowed, reason)));
+    }
+
+    if state.queue.has_job(url).await? {
+        return Ok(UrlCheck::Skip(SkippedUrl::new(url, SkipReason::InQueue, "Already in queue")));
+    }
+
+    let done = match state.db.find_done_job_by_url(url).await {
+        Ok(None) => match archive::canonical_url_for(url) {
+            Some(canonical) if canonical != url => state.db.find_done_job_by_url(&canonical).await,
+            _ => Ok(None),
+        },
+        found => found,
+    };
+    let Some(done) = done? else {
+        return Ok(UrlCheck::Add { redownloaded_from: None });
+    };
+    // `None` when it can't be told: the job recorded no file name
+    // (e.g. archive imports) or the index isn't built yet.
+    let names = done.all_files();
+    let file_exists = (!names.is_empty() && state.file_index.is_ready())
+        .then(|| names.iter().any(|name| state.file_index.contains_name(name)));
+    if file_exists != Some(false) {
+        return Ok(UrlCheck::Skip(SkippedUrl {
+            done: Some(DoneJobRef { job_id: done.id, finished_at: done.completed_at, file_exists }),
+            ..SkippedUrl::new(url, SkipReason::AlreadyDone, "Already downloaded")
+        }));
+    }
+    Ok(UrlCheck::Add { redownloaded_from: Some(done.id) })
 }
 
+#[utoipa::path(
+    post, path = "/api/queue/add", tag = "queue", request_body = AddQueuePayload,
+    responses((status = 201, description = "Added and skipped URLs"), (status = 400), (status = 403, description = "`webhookUrl` without the admin scope"))
+)]
 async fn add_to_queue(
     State(state): State<AppState>,
-    Json(payload): Json<AddQueuePayload>,
-) -> Response {
-    let lines = payload.urls.lines();
+    scope: Option<Extension<Scope>>,
+    ApiJson(payload): ApiJson<AddQueuePayload>,
+) -> Result<Response, ApiError> {
+    let urls = payload.urls.clone();
+    let options = add_job_options(payload, scope)?;
     let mut added = Vec::new();
     let mut skipped = Vec::new();
-    
-    for url in lines {
+
+    for url in urls.lines() {
         let url = url.trim();
         if url.is_empty() { continue; }
-        
-        if state.queue.has_job(url).await {
-            skipped.push(serde_json::json!({ "url": url, "reason": "Already in queue" }));
-            continue;
+
+        let redownloaded_from = match check_url(&state, url).await? {
+            UrlCheck::Add { redownloaded_from } => redownloaded_from,
+            UrlCheck::Skip(skip) => {
+                skipped.push(skip);
+                continue;
+            }
+        };
+        let job_options = JobOptions { redownloaded_from, ..options.clone() };
+        match state.queue.add_job(url.to_string(), job_options).await {
+            Ok(job) => added.push(job),
+            Err(e) => skipped.push(SkippedUrl::new(url, SkipReason::Error, e.to_string())),
         }
-        
-        if let Ok(Some(done)) = state.db.find_done_job_by_url(url).await {
-            skipped.push(serde_json::json!({ "url": url, "reason": "Already downloaded", "jobId": done.id, "finishedAt": done.completed_at }));
+    }
+    
+    Ok((StatusCode::CREATED, Json(serde_json::json!({ "added": added, "skipped": skipped }))).into_response())
+}
+
+#[derive(Serialize)]
+struct WouldAdd {
+    url: String,
+    redownloaded_from: Option<String>,
+}
+
+#[utoipa::path(
+    post, path = "/api/queue/validate", tag = "queue", request_body = AddQueuePayload,
+    responses((status = 200, description = "URLs that would be added and skipped"), (status = 400), (status = 403, description = "`webhookUrl` without the admin scope"))
+)]
+async fn validate_queue_add(
+    State(state): State<AppState>,
+    scope: Option<Extension<Scope>>,
+    ApiJson(payload): ApiJson<AddQueuePayload>,
+) -> Result<Response, ApiError> {
+    let urls = payload.urls.clone();
+    add_job_options(payload, scope)?;
+    let mut added: Vec<WouldAdd> = Vec::new();
+    let mut skipped = Vec::new();
+
+    for url in urls.lines() {
+        let url = url.trim();
+        if url.is_empty() { continue; }
+
+        // Nothing is inserted, so a repeated line has to be caught here.
+        if added.iter().any(|a| a.url == url) {
+            skipped.push(SkippedUrl::new(url, SkipReason::InQueue, "Already in queue"));
             continue;
         }
-        
-        match state.queue.add_job(url.to_string()).await {
-            Ok(job) => added.push(job),
-            Err(e) => skipped.push(serde_json::json!({ "url": url, "reason": e.to_string() })),
+        match check_url(&state, url).await? {
+            UrlCheck::Add { redownloaded_from } => added.push(WouldAdd { url: url.to_string(), redownloaded_from }),
+            UrlCheck::Skip(skip) => skipped.push(skip),
         }
     }
-    
-    (StatusCode::CREATED, Json(serde_json::json!({ "added": added, "skipped": skipped }))).into_response()
+
+    Ok(Json(serde_json::json!({ "added": added, "skipped": skipped })).into_response())
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct HistoryQuery {
     page: Option<i64>,
     limit: Option<i64>,
+    from: Option<i64>,
+    to: Option<i64>,
+    q: Option<String>,
 }
 
+#[utoipa::path(
+    get, path = "/api/queue/history", tag = "queue", params(HistoryQuery),
+    responses((status = 200, description = "Page of finished jobs"))
+)]
 async fn queue_history(
     State(state): State<AppState>,
-    Query(q): Query<HistoryQuery>,
-) -> Response {
+    ApiQuery(q): ApiQuery<HistoryQuery>,
+) -> Result<Response, ApiError> {
     let page = q.page.unwrap_or(1).max(1);
-    let limit = q.limit.unwrap_or(50).max(1);
-    let offset = (page - 1) * limit;
+    let limit = q.limit.unwrap_or(50).clamp(1, state.config.max_page_size);
+    let offset = (page - 1).saturating_mul(limit);
     
-    if let Ok((items, total)) = state.db.get_job_history(limit, offset).await {
-        Json(serde_json::json!({
-            "items": items,
-            "total": total,
-            "page": page,
-            "limit": limit
-        })).into_response()
-    } else {
-        (StatusCode::INTERNAL_SERVER_ERROR, "Failed").into_response()
-    }
+    let search = q.q.as_deref().map(str::trim).filter(|s| !s.is_empty());
+    
+    let (items, total) = state.db.get_job_history_filtered(limit, offset, q.from, q.to, search).await?;
+    Ok(Json(serde_json::json!({
+        "items": items,
+        "total": total,
+        "page": page,
+        "limit": limit,
+        "maxPageSize": state.config.max_page_size
+    })).into_response())
 }
 
-async fn export_queue(State(state): State<AppState>) -> Response {
-    if let Ok(jobs) = state.db.export_all_jobs().await {
-        let now = chrono::Local::now();
-        let filename = format!("jobs-export-{}", now.format("%Y-%m-%d"));
-        let mut headers = HeaderMap::new();
-        headers.insert(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename).parse().unwrap());
-        (headers, Json(jobs)).into_response()
-    } else {
-        (StatusCode::INTERNAL_SERVER_ERROR, "Failed").into_response()
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct MetricsQuery {
+    /// Look-back window such as `90m`, `24h` or `7d` (default `24h`, max `365d`).
+    window: Option<String>,
+}
+
+const MAX_METRICS_WINDOW_HOURS: i64 = 365 * 24;
+
+fn parse_window(window: &str) -> Option<chrono::Duration> {
+    let (idx, _) = window.char_indices().last()?;
+    let (n, unit) = window.split_at(idx);
+    let n: i64 = n.parse().ok().filter(|n| *n > 0)?;
+    let duration = match unit {
+        "m" => chrono::Duration::try_minutes(n)?,
+        "h" => chrono::Duration::try_hours(n)?,
+        "d" => chrono::Duration::try_days(n)?,
+        _ => return None,
+    };
+    (duration <= chrono::Duration::hours(MAX_METRICS_WINDOW_HOURS)).then_some(duration)
+}
+
+#[utoipa::path(
+    get, path = "/api/queue/metrics", tag = "queue", params(MetricsQuery),
+    responses((status = 200, description = "Throughput over the window"), (status = 400))
+)]
+async fn queue_metrics(
+    State(state): State<AppState>,
+    ApiQuery(q): ApiQuery<MetricsQuery>,
+) -> Result<Response, ApiError> {
+    let window = q.window.as_deref().map(str::trim).filter(|w| !w.is_empty()).unwrap_or("24h").to_string();
+    let duration = parse_window(&window)
+        .ok_or_else(|| ApiError::bad_request(format!("Invalid window '{}', expected e.g. 90m, 24h or 7d", window)))?;
+    let since = chrono::Utc::now().timestamp_millis() - duration.num_milliseconds();
+
+    let m = state.db.get_job_metrics(since).await?;
+    let finished = m.done + m.failed;
+    let hours = duration.num_milliseconds() as f64 / 3_600_000.0;
+    Ok(Json(serde_json::json!({
+        "window": window,
+        "since": since,
+        "done": m.done,
+        "failed": m.failed,
+        "cancelled": m.cancelled,
+        "completedPerHour": m.done as f64 / hours,
+        "avgDownloadMs": m.avg_download_ms.map(|ms| ms.round() as i64),
+        "successRate": (finished > 0).then(|| m.done as f64 / finished as f64),
+    })).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/api/queue/export", tag = "queue",
+    responses((status = 200, body = Vec<Job>))
+)]
+async fn export_queue(
+    State(state): State<AppState>,
+    scope: Option<Extension<Scope>>,
+) -> Result<Response, ApiError> {
+    #[derive(Serialize)]
+    struct ExportedJob<'a> {
+        #[serde(flatten)]
+        job: &'a Job,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        headers: Option<&'a HashMap<String, String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        webhook_url: Option<&'a str>,
     }
+
+    let jobs = state.db.export_all_jobs().await?;
+    // Request headers and webhook URLs are only exported for admins, so the
+    // file can be imported again without losing them.
+    let admin = scope.is_none_or(|Extension(scope)| scope == Scope::Admin);
+    let exported: Vec<ExportedJob> = jobs.iter()
+        .map(|job| ExportedJob {
+            job,
+            headers: job.headers.as_ref().filter(|_| admin).map(|h| &h.0),
+            webhook_url: job.webhook_url.as_deref().filter(|_| admin),
+        })
+        .collect();
+    let now = chrono::Local::now();
+    let filename = format!("jobs-export-{}", now.format("%Y-%m-%d"));
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_DISPOSITION, attachment_disposition(&filename));
+    Ok((headers, Json(exported)).into_response())
 }
 
+#[utoipa::path(
+    post, path = "/api/queue/import", tag = "queue",
+    request_body(content_type = "multipart/form-data", description = "Exported jobs JSON in a `file` field"),
+    responses((status = 200, description = "Imported and skipped counts plus rejected entries"), (status = 400))
+)]
 async fn import_queue(
     State(state): State<AppState>,
-    mut multipart: Multipart,
-) -> impl IntoResponse {
+    multipart: Multipart,
+) -> Result<Response, ApiError> {
+    let bytes = read_upload(multipart).await?;
+    import_jobs(&state, &bytes).await
+}
+
+async fn import_jobs(state: &AppState, bytes: &[u8]) -> Result<Response, ApiError> {
+    let jobs = serde_json::from_slice::<Vec<Job>>(bytes)
+        .map_err(|e| ApiError::bad_request(format!("Invalid export file: {}", e)))?;
+
     let mut imported = 0;
     let mut skipped = 0;
+    let mut rejected = Vec::new();
+    for mut job in jobs {
+        if let Err(reason) = sanitize_imported_job(&mut job) {
+            rejected.push(serde_json::json!({ "id": job.id, "reason": reason }));
+            continue;
+        }
+        if state.db.check_job_exists(&job.id).await? {
+            skipped += 1;
+            continue;
+        }
+        match state.db.import_job(job.clone()).await {
+            Ok(()) => imported += 1,
+            Err(e) => rejected.push(serde_json::json!({ "id": job.id, "reason": e.to_string() })),
+        }
+    }
+    Ok(Json(serde_json::json!({ "imported": imported, "skipped": skipped, "rejected": rejected })).into_response())
+}
 
-    while let Some(field) = multipart.next_field().await.unwrap_or(None) {
-        if field.name() == Some("file") {
-            if let Ok(bytes) = field.bytes().await {
-                 if let Ok(jobs) = serde_json::from_slice::<Vec<Job>>(&bytes) {
-                     for job in jobs {
-                         if let Ok(true) = state.db.check_job_exists(&job.id).await {
-                             skipped += 1;
-                         } else {
-                             let mut new_job = job.clone();
-                             new_job.status = "imported".to_string();
-                             new_job.retries = 0;
-                             
-                             if let Ok(_) = state.db.import_job(new_job).await {
-                                 imported += 1;
-                             }
-                         }
-                     }
-                 }
-            }
+async fn read_upload(mut multipart: Multipart) -> Result<axum::body::Bytes, ApiError> {
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| ApiError::bad_request(format!("Invalid multipart body: {}", e)))?
+    {
+        if field.name() != Some("file") {
+            continue;
+        }
+        return field.bytes().await
+            .map_err(|e| ApiError::bad_request(format!("Failed to read upload: {}", e)));
+    }
+    Err(ApiError::bad_request("Missing 'file' field"))
+}
+
+#[utoipa::path(
+    post, path = "/api/queue/import/archive", tag = "queue",
+    request_body(content_type = "multipart/form-data", description = "yt-dlp --download-archive file in a `file` field"),
+    responses((status = 200, description = "Imported, skipped, unsupported and invalid line counts"), (status = 400))
+)]
+async fn import_archive(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> Result<Response, ApiError> {
+    let bytes = read_upload(multipart).await?;
+
+    let mut imported = 0;
+    let mut skipped = 0;
+    let mut unsupported = 0;
+    let mut invalid = 0;
+    for line in String::from_utf8_lossy(&bytes).lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((extractor, id)) = archive::parse_line(line) else {
+            invalid += 1;
+            continue;
+        };
+        let Some(url) = archive::canonical_url(extractor, id) else {
+            unsupported += 1;
+            continue;
+        };
+        if state.db.find_done_job_by_url(&url).await?.is_some() {
+            skipped += 1;
+            continue;
         }
+        state.db.add_archived_job(&url).await?;
+        imported += 1;
+    }
+
+    Ok(Json(serde_json::json!({ "imported": imported, "skipped": skipped, "unsupported": unsupported, "invalid": invalid })).into_response())
+}
+
+// Imported rows come from an untrusted file, so anything the queue relies on
+// is forced or clamped rather than copied: the status is always `imported`,
+// timestamps must be positive and not in the future and filenames must not
+// point outside their folder.
+fn sanitize_imported_job(job: &mut Job) -> Result<(), &'static str> {
+    let now = chrono::Utc::now().timestamp_millis();
+    let valid_ts = |ts: i64| ts > 0 && ts <= now;
+
+    if job.id.trim().is_empty() || job.id.len() > 64 {
+        return Err("Invalid id");
+    }
+    if !(job.url.starts_with("http://") || job.url.starts_with("https://")) {
+        return Err("Invalid URL");
+    }
+    if !valid_ts(job.created_at) || !job.started_at.is_none_or(valid_ts) || !job.completed_at.is_none_or(valid_ts) {
+        return Err("Invalid timestamp");
+    }
+    let valid_name = |name: &String| !(name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']));
+    if !job.filename.iter().chain(job.files.iter().flat_map(|f| f.iter())).all(valid_name) {
+        return Err("Invalid filename");
+    }
+    if let Some(headers) = &job.headers {
+        validate_job_headers(headers).map_err(|_| "Invalid headers")?;
     }
-    Json(serde_json::json!({ "imported": imported, "skipped": skipped }))
+    if let Some(container) = &job.output_container {
+        validate_output_container(container).map_err(|_| "Invalid output container")?;
+    }
+    if let Some(subfolder) = &job.subfolder {
+        validate_subfolder(subfolder).map_err(|_| "Invalid subfolder")?;
+    }
+    if let Some(format) = &job.format {
+        validate_format(format).map_err(|_| "Invalid format")?;
+    }
+    if let Some(audio_format) = &job.audio_format {
+        validate_audio_format(audio_format).map_err(|_| "Invalid audio format")?;
+    }
+    if let Some(url) = &job.webhook_url {
+        validate_webhook_url(url).map_err(|_| "Invalid webhook URL")?;
+    }
+    if let Some(height) = job.max_height {
+        u32::try_from(height).ok().filter(|h| validate_max_height(*h).is_ok()).ok_or("Invalid max height")?;
+    }
+
+    job.status = "imported".to_string();
+    job.progress = job.progress.clamp(0, 100);
+    job.eta = job.eta.filter(|eta| *eta >= 0);
+    job.retries = 0;
+    job.error_kind = None;
+    job.verified = None;
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RetryQuery {
+    /// Send only this attempt through `PROXY_URL`
+    #[serde(default)]
+    proxy: bool,
 }
 
+#[utoipa::path(
+    post, path = "/api/queue/retry/{id}", tag = "queue", params(("id" = String, Path), RetryQuery),
+    responses((status = 200, body = Job), (status = 400), (status = 404))
+)]
 async fn retry_job(
     State(state): State<AppState>,
-    Path(id): Path<String>,
-) -> Response {
-    if let Some(job) = state.queue.retry_job(&id).await {
-        Json(job).into_response()
-    } else {
-        (StatusCode::NOT_FOUND, "Job not found or cannot retry").into_response()
+    ApiPath(id): ApiPath<String>,
+    ApiQuery(params): ApiQuery<RetryQuery>,
+) -> Result<Response, ApiError> {
+    if !params.proxy {
+        let job = apply_job_action(&state, JobAction::Retry, &id).await?;
+        return Ok(Json(job).into_response());
     }
+    let Some(proxy) = state.config.proxy_url.clone() else {
+        return Err(ApiError::bad_request("No proxy is configured (PROXY_URL)"));
+    };
+    let job = state.queue.retry_job(&id, Some(proxy)).await?
+        .ok_or_else(|| ApiError::not_found("Job not found"))?;
+    Ok(Json(job).into_response())
+}
+
+#[derive(Deserialize, Default, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+enum RedownloadMode {
+    #[default]
+    InPlace,
+    New,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RedownloadQuery {
+    /// `in-place` requeues the job itself, `new` queues a new job linked via `redownloaded_from`
+    #[serde(default)]
+    mode: RedownloadMode,
 }
 
+#[utoipa::path(
+    post, path = "/api/queue/redownload/{id}", tag = "queue", params(("id" = String, Path), RedownloadQuery),
+    responses((status = 200, body = Job), (status = 404))
+)]
 async fn redownload_job(
     State(state): State<AppState>,
-    Path(id): Path<String>,
-) -> Response {
-    if let Some(job) = state.queue.redownload_job(&id).await {
-        Json(job).into_response()
-    } else {
-        (StatusCode::NOT_FOUND, "Job not found").into_response()
+    ApiPath(id): ApiPath<String>,
+    ApiQuery(query): ApiQuery<RedownloadQuery>,
+) -> Result<Response, ApiError> {
+    if let RedownloadMode::New = query.mode {
+        let job = state.queue.redownload_job_as_new(&id).await?.ok_or_else(|| ApiError::not_found("Job not found"))?;
+        return Ok(Json(job).into_response());
+    }
+    let job = apply_job_action(&state, JobAction::Redownload, &id).await?;
+    Ok(Json(job).into_response())
+}
+
+#[utoipa::path(
+    post, path = "/api/queue/{id}/pause", tag = "queue", params(("id" = String, Path)),
+    responses((status = 200, body = Job), (status = 404), (status = 409), (status = 501))
+)]
+async fn pause_job(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+) -> Result<Response, ApiError> {
+    state.queue.pause_job(&id).await.map_err(pause_error)?;
+    job_response(&state, &id).await
+}
+
+#[utoipa::path(
+    post, path = "/api/queue/{id}/resume", tag = "queue", params(("id" = String, Path)),
+    responses((status = 200, body = Job), (status = 404), (status = 409), (status = 501))
+)]
+async fn resume_job(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+) -> Result<Response, ApiError> {
+    state.queue.resume_job(&id).await.map_err(pause_error)?;
+    job_response(&state, &id).await
+}
+
+#[utoipa::path(
+    post, path = "/api/queue/{id}/star", tag = "queue", params(("id" = String, Path)),
+    responses((status = 200, body = Job), (status = 404))
+)]
+async fn star_job(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+) -> Result<Response, ApiError> {
+    if state.db.toggle_starred(&id).await?.is_none() {
+        return Err(ApiError::not_found("Job not found"));
+    }
+    job_response(&state, &id).await
+}
+
+#[derive(Deserialize, ToSchema)]
+struct UpdateUrlPayload {
+    url: String,
+}
+
+#[utoipa::path(
+    patch, path = "/api/queue/{id}/url", tag = "queue", params(("id" = String, Path)), request_body = UpdateUrlPayload,
+    responses(
+        (status = 200, body = Job), (status = 400), (status = 404),
+        (status = 409, description = "Job is downloading or paused, or another queued job has the URL")
+    )
+)]
+async fn update_job_url(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+    ApiJson(payload): ApiJson<UpdateUrlPayload>,
+) -> Result<Response, ApiError> {
+    let job = state.db.get_job(&id).await?.ok_or_else(|| ApiError::not_found("Job not found"))?;
+    if matches!(job.status.as_str(), "downloading" | "paused") {
+        return Err(ApiError::conflict("Job is downloading or paused"));
+    }
+    let mut url = payload.url.trim().to_string();
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err(ApiError::bad_request("Not an http(s) URL"));
+    }
+    state.config.domain_filter.check(&url).map_err(ApiError::bad_request)?;
+    // Short links are followed like `POST /api/files/resolve` does.
+    if state.config.enable_resolve && state.capabilities.curl {
+        if let Ok(resolved) = resolve_url(&url).await {
+            if resolved.starts_with("http://") || resolved.starts_with("https://") {
+                url = resolved;
+            }
+        }
+        state.config.domain_filter.check(&url).map_err(ApiError::bad_request)?;
+    }
+    if url == job.url {
+        return job_response(&state, &id).await;
+    }
+    if state.queue.has_job(&url).await? {
+        return Err(ApiError::conflict("Another job with this URL is already in the queue"));
+    }
+    if !state.db.update_job_url(&id, &url).await? {
+        return Err(ApiError::conflict("Job is downloading or paused"));
+    }
+    job_response(&state, &id).await
+}
+
+#[utoipa::path(
+    post, path = "/api/queue/{id}/archive", tag = "queue", params(("id" = String, Path)),
+    responses((status = 200, body = Job), (status = 404), (status = 409, description = "Job is not finished or its files have not been synced yet"))
+)]
+async fn archive_job(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+) -> Result<Response, ApiError> {
+    let job = state.db.get_job(&id).await?.ok_or_else(|| ApiError::not_found("Job not found"))?;
+    match job.status.as_str() {
+        "done" | "imported" => {
+            // Only offload files the last successful sync could have copied.
+            let finished = job.completed_at.unwrap_or(job.created_at);
+            let synced = state.queue.last_synced_at().is_some_and(|at| finished <= at.timestamp_millis());
+            if !synced {
+                return Err(ApiError::conflict("Job has not been synced since it finished"));
+            }
+        }
+        "missing" => {}
+        "archived" => return Err(ApiError::conflict("Job is already archived")),
+        _ => return Err(ApiError::conflict("Only finished jobs can be archived")),
+    }
+    state.queue.archive_job(&job).await?;
+    job_response(&state, &id).await
+}
+
+fn pause_error(e: PauseError) -> ApiError {
+    match e {
+        PauseError::NotRunning => ApiError::not_found(e.to_string()),
+        PauseError::AlreadyPaused | PauseError::NotPaused => ApiError::conflict(e.to_string()),
+        PauseError::Unsupported => ApiError::not_implemented(e.to_string()),
+        PauseError::Signal(_) => ApiError::internal(e.to_string()),
+    }
+}
+
+async fn job_response(state: &AppState, id: &str) -> Result<Response, ApiError> {
+    match state.db.get_job(id).await? {
+        Some(job) => Ok(Json(job).into_response()),
+        None => Err(ApiError::not_found("Job not found")),
+    }
+}
+
+const UPLOAD_OFFSET_HEADER: &str = "upload-offset";
+
+fn upload_error(e: UploadError) -> ApiError {
+    match e {
+        UploadError::NotFound => ApiError::not_found(e.to_string()),
+        UploadError::OffsetMismatch(_) => ApiError::conflict(e.to_string()),
+        UploadError::TooLarge => ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "too_large", e.to_string()),
+        UploadError::Io(_) => ApiError::internal(e.to_string()),
+    }
+}
+
+fn upload_response(status: StatusCode, id: &uuid::Uuid, offset: u64) -> Response {
+    let mut response = (status, Json(serde_json::json!({ "id": id, "offset": offset }))).into_response();
+    response.headers_mut().insert(UPLOAD_OFFSET_HEADER, HeaderValue::from(offset));
+    response
+}
+
+#[utoipa::path(
+    post, path = "/api/queue/import/uploads", tag = "queue",
+    responses((status = 201, description = "Upload id and offset 0"))
+)]
+async fn create_import_upload() -> Result<Response, ApiError> {
+    let id = upload::create().await.map_err(upload_error)?;
+    Ok(upload_response(StatusCode::CREATED, &id, 0))
+}
+
+#[utoipa::path(
+    get, path = "/api/queue/import/uploads/{id}", tag = "queue", params(("id" = String, Path)),
+    responses((status = 200, description = "Bytes received so far"), (status = 404))
+)]
+async fn import_upload_status(ApiPath(id): ApiPath<uuid::Uuid>) -> Result<Response, ApiError> {
+    let offset = upload::offset(&id).await.map_err(upload_error)?;
+    Ok(upload_response(StatusCode::OK, &id, offset))
+}
+
+#[utoipa::path(
+    patch, path = "/api/queue/import/uploads/{id}", tag = "queue", params(("id" = String, Path)),
+    request_body(content_type = "application/octet-stream", description = "Next chunk, starting at the `Upload-Offset` header"),
+    responses((status = 200, description = "New offset"), (status = 404), (status = 409, description = "Offset mismatch"), (status = 413))
+)]
+async fn append_import_upload(
+    ApiPath(id): ApiPath<uuid::Uuid>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Result<Response, ApiError> {
+    let at = headers.get(UPLOAD_OFFSET_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .ok_or_else(|| ApiError::bad_request("Missing or invalid Upload-Offset header"))?;
+    match upload::append(&id, at, &body).await {
+        Ok(offset) => Ok(upload_response(StatusCode::OK, &id, offset)),
+        Err(UploadError::OffsetMismatch(current)) => {
+            let mut response = upload_error(UploadError::OffsetMismatch(current)).into_response();
+            response.headers_mut().insert(UPLOAD_OFFSET_HEADER, HeaderValue::from(current));
+            Ok(response)
+        }
+        Err(e) => Err(upload_error(e)),
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/api/queue/import/uploads/{id}", tag = "queue", params(("id" = String, Path)),
+    responses((status = 204), (status = 404))
+)]
+async fn delete_import_upload(ApiPath(id): ApiPath<uuid::Uuid>) -> Result<Response, ApiError> {
+    upload::remove(&id).await.map_err(upload_error)?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[utoipa::path(
+    post, path = "/api/queue/import/uploads/{id}/complete", tag = "queue", params(("id" = String, Path)),
+    responses((status = 200, description = "Same result as a single-shot import"), (status = 400), (status = 404))
+)]
+async fn complete_import_upload(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<uuid::Uuid>,
+) -> Result<Response, ApiError> {
+    let bytes = upload::take(&id).await.map_err(upload_error)?;
+    import_jobs(&state, &bytes).await
+}
+
+const MAX_BATCH_IDS: usize = 100;
+
+#[derive(Deserialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum JobAction {
+    Retry,
+    Delete,
+    Redownload,
+    Cancel,
+}
+
+async fn apply_job_action(state: &AppState, action: JobAction, id: &str) -> Result<serde_json::Value, ApiError> {
+    let not_found = || ApiError::not_found("Job not found");
+    match action {
+        JobAction::Retry => state.queue.retry_job(id, None).await?
+            .map(|job| serde_json::json!(job))
+            .ok_or_else(not_found),
+        JobAction::Redownload => state.queue.redownload_job(id).await?
+            .map(|job| serde_json::json!(job))
+            .ok_or_else(not_found),
+        JobAction::Delete => {
+            state.queue.cancel_job(id);
+            if !state.db.check_job_exists(id).await? {
+                return Err(not_found());
+            }
+            state.db.delete_job(id).await?;
+            Ok(serde_json::json!({ "success": true, "id": id }))
+        }
+        JobAction::Cancel => match state.db.get_job(id).await? {
+            Some(job) if matches!(job.status.as_str(), "scheduled" | "queued" | "downloading" | "paused") => {
+                state.queue.cancel_job(id);
+                if matches!(job.status.as_str(), "scheduled" | "queued") {
+                    state.db.mark_failed(id, "Cancelled").await?;
+                }
+                Ok(serde_json::json!({ "success": true, "id": id }))
+            }
+            Some(_) => Err(ApiError::conflict("Job is not scheduled, queued, downloading or paused")),
+            None => Err(not_found()),
+        },
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+struct BatchPayload {
+    action: JobAction,
+    ids: Vec<String>,
+}
+
+#[utoipa::path(
+    post, path = "/api/queue/batch", tag = "queue", request_body = BatchPayload,
+    responses((status = 200, description = "Per-id results"), (status = 400))
+)]
+async fn batch_jobs(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<BatchPayload>,
+) -> Result<Response, ApiError> {
+    if payload.ids.is_empty() {
+        return Err(ApiError::bad_request("No job ids provided"));
+    }
+    if payload.ids.len() > MAX_BATCH_IDS {
+        return Err(ApiError::bad_request(format!("Too many ids (max {})", MAX_BATCH_IDS)));
+    }
+
+    let mut results = Vec::new();
+    for id in payload.ids {
+        match apply_job_action(&state, payload.action, &id).await {
+            Ok(result) => results.push(serde_json::json!({ "id": id, "success": true, "result": result })),
+            Err(e) => results.push(serde_json::json!({ "id": id, "success": false, "error": e.message() })),
+        }
+    }
+
+    Ok(Json(serde_json::json!({ "results": results })).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &std::ffi::OsStr, contents: &[u8]) -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("tiak-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join(name);
+        std::fs::write(&file, contents).unwrap();
+        (root, file)
+    }
+
+    async fn body_bytes(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+    }
+
+    #[tokio::test]
+    async fn streams_a_file_by_its_stored_non_utf8_path() {
+        use std::os::unix::ffi::OsStrExt;
+        let (root, file) = temp_file(std::ffi::OsStr::from_bytes(b"clip \xff.mp4"), b"video");
+
+        let stored = crate::storage::encode_os_str(file.as_os_str());
+        assert!(stored.ends_with("clip %FF.mp4"));
+        let response = serve_file(&crate::storage::stored_to_path(&stored), &HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_bytes(response).await, b"video");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    fn range(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[tokio::test]
+    async fn streams_an_empty_file() {
+        let (root, file) = temp_file("empty.mp4".as_ref(), b"");
+
+        let response = serve_file(&file, &HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "0");
+        assert!(body_bytes(response).await.is_empty());
+
+        for value in ["bytes=0-0", "bytes=0-"] {
+            let response = serve_file(&file, &range(value)).await;
+            assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE, "{}", value);
+            assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */0");
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn streams_a_one_byte_file() {
+        let (root, file) = temp_file("one.mp4".as_ref(), b"x");
+
+        let response = serve_file(&file, &HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "1");
+        assert_eq!(body_bytes(response).await, b"x");
+
+        for value in ["bytes=0-0", "bytes=0-"] {
+            let response = serve_file(&file, &range(value)).await;
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{}", value);
+            assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 0-0/1");
+            assert_eq!(response.headers()[header::CONTENT_LENGTH], "1");
+            assert_eq!(body_bytes(response).await, b"x");
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn attachment_disposition_encodes_unicode_names() {
+        let value = attachment_disposition("Café \"日本\".mp4");
+        assert_eq!(
+            value.to_str().unwrap(),
+            "attachment; filename=\"Caf_ ____.mp4\"; filename*=UTF-8''Caf%C3%A9%20%22%E6%97%A5%E6%9C%AC%22.mp4"
+        );
+    }
+
+    #[test]
+    fn skipped_urls_serialize_their_code() {
+        let skip = SkippedUrl::new("https://example.com/list", SkipReason::PlaylistTooLarge, "Playlist too large");
+        assert_eq!(
+            serde_json::to_value(skip).unwrap(),
+            serde_json::json!({ "url": "https://example.com/list", "code": "playlist_too_large", "reason": "Playlist too large" })
+        );
+    }
+
+    #[test]
+    fn server_folders_are_not_deletable_tags() {
+        for tag in ["", ".", "..", ".redownload", ".zips", ".uploads", "library", "a/b", "a\\b", "jobs.sqlite"] {
+            assert!(!is_deletable_tag(tag), "{}", tag);
+        }
+        assert!(is_deletable_tag("2024-05-01"));
+        assert!(is_deletable_tag("music"));
     }
 }
@@ -0,0 +1,37 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// A token is `<payload>.<signature>`, both base64url: the payload is
+// `<expires at, ms>:<path>` and the signature its HMAC-SHA256 under the
+// server secret. Nothing is stored, so a link can't be revoked before it
+// expires except by changing the secret.
+pub fn sign(secret: &[u8], path: &str, expires_at: i64) -> String {
+    let payload = format!("{}:{}", expires_at, path);
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    format!("{}.{}", URL_SAFE_NO_PAD.encode(payload), URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+}
+
+// The shared path, if the token was signed with `secret` and has not
+// expired by `now` (ms).
+pub fn verify(secret: &[u8], token: &str, now: i64) -> Result<String, &'static str> {
+    let invalid = "Invalid share link";
+    let (payload, signature) = token.split_once('.').ok_or(invalid)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid)?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid)?;
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&payload);
+    mac.verify_slice(&signature).map_err(|_| invalid)?;
+
+    let payload = String::from_utf8(payload).map_err(|_| invalid)?;
+    let (expires_at, path) = payload.split_once(':').ok_or(invalid)?;
+    let expires_at: i64 = expires_at.parse().map_err(|_| invalid)?;
+    if now >= expires_at {
+        return Err("Share link has expired");
+    }
+    Ok(path.to_string())
+}
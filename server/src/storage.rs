@@ -1,14 +1,130 @@
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use walkdir::WalkDir;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use anyhow::Result;
 use std::time::SystemTime;
-use chrono::{DateTime, Utc, Local, Datelike};
+use chrono::{DateTime, Utc, Local};
+use chrono_tz::Tz;
 
 pub const DATA_ROOT: &str = "data";
 
-#[derive(Debug, Clone, Serialize)]
+// Timezone used to name date folders; `None` means the system local time.
+static FOLDER_TIMEZONE: OnceLock<Option<Tz>> = OnceLock::new();
+
+pub fn set_folder_timezone(tz: Option<Tz>) {
+    let _ = FOLDER_TIMEZONE.set(tz);
+}
+
+// Optional second data root with the same layout as `data/`, for a slower
+// and larger disk. Files are moved there by `tier_old_files`.
+static COLD_ROOT: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+pub fn set_cold_root(root: Option<PathBuf>) {
+    let _ = COLD_ROOT.set(root);
+}
+
+pub fn cold_root() -> Option<&'static Path> {
+    COLD_ROOT.get().and_then(|root| root.as_deref())
+}
+
+// Canonicalizes a client supplied `data/...` path, looking on the cold tier
+// when the file is not in the primary root. Callers still check the result
+// with `in_data_roots`.
+pub fn canonicalize_data_path(p: &str) -> Option<PathBuf> {
+    let path = stored_to_path(p);
+    if let Ok(abs) = path.canonicalize() {
+        return Some(abs);
+    }
+    let relative = path.strip_prefix(DATA_ROOT).ok()?;
+    cold_root()?.join(relative).canonicalize().ok()
+}
+
+pub fn in_data_roots(abs: &Path) -> bool {
+    let canonical = |root: &Path| root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    abs.starts_with(canonical(Path::new(DATA_ROOT))) || cold_root().is_some_and(|root| abs.starts_with(canonical(root)))
+}
+
+// Where a file of `folder` is stored, checking the primary root first.
+pub fn locate_data_file(folder: &Path, name: &str) -> Option<PathBuf> {
+    let primary = Path::new(DATA_ROOT).join(folder).join(name);
+    if primary.exists() {
+        return Some(primary);
+    }
+    let cold = cold_root()?.join(folder).join(name);
+    cold.exists().then_some(cold)
+}
+
+// Full walks of the data root (index rebuilds, disk usage) take a permit so
+// they don't compete for a slow disk or NAS.
+static WALK_PERMITS: OnceLock<tokio::sync::Semaphore> = OnceLock::new();
+
+pub fn set_walk_concurrency(permits: usize) {
+    let _ = WALK_PERMITS.set(tokio::sync::Semaphore::new(permits.max(1)));
+}
+
+// Threads that stat the files found by an index walk. The directory
+// traversal itself stays sequential; on a NAS the per-file round trips are
+// what make a large index slow to build.
+static INDEX_THREADS: OnceLock<usize> = OnceLock::new();
+
+pub fn set_index_threads(threads: usize) {
+    let _ = INDEX_THREADS.set(threads.max(1));
+}
+
+fn index_threads() -> usize {
+    INDEX_THREADS.get().copied().unwrap_or(1)
+}
+
+async fn walk_permit() -> tokio::sync::SemaphorePermit<'static> {
+    WALK_PERMITS
+        .get_or_init(|| tokio::sync::Semaphore::new(1))
+        .acquire()
+        .await
+        .expect("walk semaphore is never closed")
+}
+
+pub fn date_folder_name(ts: DateTime<Utc>) -> String {
+    match FOLDER_TIMEZONE.get().copied().flatten() {
+        Some(tz) => ts.with_timezone(&tz).format("%Y-%m-%d").to_string(),
+        None => ts.with_timezone(&Local).format("%Y-%m-%d").to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibraryMode {
+    Date,
+    FlatById,
+}
+
+impl std::str::FromStr for LibraryMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "date" => Ok(LibraryMode::Date),
+            "flat-by-id" => Ok(LibraryMode::FlatById),
+            other => Err(format!("Unknown library mode '{}', expected date or flat-by-id", other)),
+        }
+    }
+}
+
+// Folder under the data root that holds every download in flat-by-id mode.
+pub const LIBRARY_FOLDER: &str = "library";
+
+static LIBRARY_MODE: OnceLock<LibraryMode> = OnceLock::new();
+
+pub fn set_library_mode(mode: LibraryMode) {
+    let _ = LIBRARY_MODE.set(mode);
+}
+
+pub fn library_mode() -> LibraryMode {
+    LIBRARY_MODE.get().copied().unwrap_or(LibraryMode::Date)
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
 pub struct FileItem {
     pub path: String,
     pub name: String,
@@ -17,94 +133,235 @@ pub struct FileItem {
     pub created_at: DateTime<Utc>,
     #[serde(rename = "dateFolder")]
     pub date_folder: String,
+    // Moved to the cold data root; still addressed by its `data/...` path.
+    pub cold: bool,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
 pub struct FileIndexResponse {
     #[serde(rename = "byDate")]
     pub by_date: std::collections::HashMap<String, Vec<FileItem>>,
     #[serde(rename = "lastScan")]
     pub last_scan: i64,
+    pub indexing: bool,
+    pub ready: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FileSort {
+    #[default]
+    Date,
+    Size,
+    Name,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+const MAX_INDEX_ERRORS: usize = 100;
+
+// Outcome of the last index build, for `GET /api/system/info`.
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexStatus {
+    #[serde(rename = "finishedAt")]
+    pub finished_at: i64,
+    #[serde(rename = "durationMs")]
+    pub duration_ms: u64,
+    pub success: bool,
+    // Why the whole build failed; the previous index is kept.
+    pub error: Option<String>,
+    #[serde(rename = "fileCount")]
+    pub file_count: usize,
+    // Entries that could not be read, e.g. permission denied or files
+    // that vanished during the walk. Only the first 100 are listed.
+    #[serde(rename = "entryErrorCount")]
+    pub entry_error_count: usize,
+    #[serde(rename = "entryErrors")]
+    pub entry_errors: Vec<String>,
+}
+
+// Adds and removes that happen while a rebuild walks the tree. The walk may
+// or may not have seen them, so they are replayed onto its result.
+#[derive(Default)]
+struct ScanChanges {
+    added: Vec<FileItem>,
+    removed: Vec<String>,
 }
 
 #[derive(Clone)]
 pub struct FileIndex {
     files: Arc<RwLock<Vec<FileItem>>>,
+    // `Some` while a scan is running. Only touched with `files` write-locked,
+    // so a change lands either in the old list before the swap or here.
+    scan_changes: Arc<Mutex<Option<ScanChanges>>>,
     last_scan: Arc<RwLock<i64>>,
     cached_index: Arc<RwLock<Option<FileIndexResponse>>>,
+    // Bumped whenever `cached_index` is invalidated; feeds the ETag.
+    generation: Arc<AtomicU64>,
+    indexing: Arc<AtomicBool>,
+    ready: Arc<AtomicBool>,
+    // Size and count of the files the last scan walked but did not index
+    // (sidecars, the download archive), for disk usage without another walk.
+    unindexed: Arc<RwLock<(u64, usize)>>,
+    status: Arc<RwLock<Option<IndexStatus>>>,
 }
 
 impl FileIndex {
     pub fn new() -> Self {
         Self {
             files: Arc::new(RwLock::new(Vec::new())),
+            scan_changes: Arc::new(Mutex::new(None)),
             last_scan: Arc::new(RwLock::new(0)),
             cached_index: Arc::new(RwLock::new(None)),
+            generation: Arc::new(AtomicU64::new(0)),
+            indexing: Arc::new(AtomicBool::new(false)),
+            ready: Arc::new(AtomicBool::new(false)),
+            unindexed: Arc::new(RwLock::new((0, 0))),
+            status: Arc::new(RwLock::new(None)),
         }
     }
 
-    pub async fn build_index(&self) -> Result<()> {
-        let root = Path::new(DATA_ROOT);
-        let mut files = Vec::new();
-        let timestamp = Utc::now().timestamp_millis();
-        
-        if root.exists() {
-             let root_path = root.to_path_buf();
-             let entries = tokio::task::spawn_blocking(move || {
-                let mut res = Vec::new();
-                let walker = WalkDir::new(&root_path)
-                    .into_iter()
-                    .filter_map(|e| e.ok())
-                    .filter(|e| e.file_type().is_file());
-                
-                for entry in walker {
-                    let path = entry.path();
-                    let name = entry.file_name().to_string_lossy().to_string();
-                    
-                    if name.contains("jobs.sqlite") {
-                        continue;
-                    }
-
-                    if let Ok(meta) = entry.metadata() {
-                        let size = meta.len();
-                        let created: DateTime<Utc> = meta.created().unwrap_or(SystemTime::now()).into();
-                        
-                        let relative_path = path.strip_prefix(&root_path).unwrap_or(path);
-                        let date_folder = relative_path.components().next()
-                            .map(|c| c.as_os_str().to_string_lossy().to_string())
-                            .unwrap_or_default();
-
-                        res.push(FileItem {
-                            path: path.to_string_lossy().to_string(),
-                            name,
-                            size,
-                            created_at: created,
-                            date_folder,
-                        });
-                    }
-                }
-                res
-            }).await?;
-            files = entries;
+    pub fn is_indexing(&self) -> bool {
+        self.indexing.load(Ordering::SeqCst)
+    }
+
+    /// True once the first index build has completed.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    // `None` until the first build has finished.
+    pub fn status(&self) -> Option<IndexStatus> {
+        self.status.read().unwrap().clone()
+    }
+
+    pub async fn build_index(&self) -> Result<IndexStatus> {
+        self.indexing.store(true, Ordering::SeqCst);
+        let started = std::time::Instant::now();
+        let result = self.scan().await;
+        self.indexing.store(false, Ordering::SeqCst);
+        if result.is_ok() {
+            self.ready.store(true, Ordering::SeqCst);
         }
+        let (file_count, mut entry_errors) = match &result {
+            Ok(entry_errors) => (self.files.read().unwrap().len(), entry_errors.clone()),
+            Err(_) => (0, Vec::new()),
+        };
+        let entry_error_count = entry_errors.len();
+        entry_errors.truncate(MAX_INDEX_ERRORS);
+        let status = IndexStatus {
+            finished_at: Utc::now().timestamp_millis(),
+            duration_ms: started.elapsed().as_millis() as u64,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            file_count,
+            entry_error_count,
+            entry_errors,
+        };
+        *self.status.write().unwrap() = Some(status.clone());
+        result.map(|_| status)
+    }
 
-        {
-            let mut w = self.files.write().unwrap();
-            *w = files;
+    // Returns the entries that could not be read.
+    async fn scan(&self) -> Result<Vec<String>> {
+        if !data_root_available() {
+            return Err(anyhow::anyhow!("Data root '{}' is unavailable, keeping the previous index", DATA_ROOT));
         }
+
+        let root = Path::new(DATA_ROOT);
+        let timestamp = Utc::now().timestamp_millis();
+
+        self.begin_scan();
+
+        let _permit = walk_permit().await;
+        let root_path = root.to_path_buf();
+        let cold_root = cold_root().map(Path::to_path_buf);
+        let walked = tokio::task::spawn_blocking(move || {
+            let mut res = Vec::new();
+            let mut unindexed = (0, 0);
+            let mut errors = Vec::new();
+            walk_files(&root_path, false, &mut res, &mut unindexed, &mut errors);
+            if let Some(cold_root) = cold_root {
+                // A file on both tiers (an interrupted move) is listed once,
+                // from the primary root.
+                let primary: std::collections::HashSet<String> = res.iter().map(|f| f.path.clone()).collect();
+                let mut cold = Vec::new();
+                walk_files(&cold_root, true, &mut cold, &mut unindexed, &mut errors);
+                res.extend(cold.into_iter().filter(|f| !primary.contains(&f.path)));
+            }
+            (res, unindexed, errors)
+        }).await;
+
+        let (files, unindexed, errors) = match walked {
+            Ok(walked) => walked,
+            Err(e) => {
+                *self.scan_changes.lock().unwrap() = None;
+                return Err(e.into());
+            }
+        };
+        self.finish_scan(files, unindexed);
         {
             let mut t = self.last_scan.write().unwrap();
             *t = timestamp;
         }
-        {
-            let mut cache = self.cached_index.write().unwrap();
-            *cache = None;
-        }
+        self.invalidate();
         
-        Ok(())
+        Ok(errors)
+    }
+
+    // From here until `finish_scan`, files added or removed (by finishing
+    // downloads, deletes and moves) are also recorded in `scan_changes`.
+    fn begin_scan(&self) {
+        let _files = self.files.write().unwrap();
+        *self.scan_changes.lock().unwrap() = Some(ScanChanges::default());
+    }
+
+    // Swaps in the walked list with the changes made during the walk
+    // applied on top, so none of them is lost or listed twice.
+    fn finish_scan(&self, mut files: Vec<FileItem>, unindexed: (u64, usize)) {
+        let mut w = self.files.write().unwrap();
+        let changes = self.scan_changes.lock().unwrap().take().unwrap_or_default();
+        *self.unindexed.write().unwrap() = unindexed;
+        for path in &changes.removed {
+            files.retain(|f| &f.path != path);
+        }
+        for item in changes.added {
+            upsert(&mut files, item);
+        }
+        *w = files;
+    }
+
+    fn invalidate(&self) {
+        let mut cache = self.cached_index.write().unwrap();
+        *cache = None;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Changes whenever the `get_index` body would: on every cache
+    // invalidation and when the indexing/ready flags flip.
+    pub fn etag(&self) -> String {
+        format!(
+            "W/\"{}-{}-{}{}\"",
+            *self.last_scan.read().unwrap(),
+            self.generation.load(Ordering::SeqCst),
+            self.is_indexing() as u8,
+            self.is_ready() as u8,
+        )
     }
 
     pub fn get_index(&self) -> FileIndexResponse {
+        let mut response = self.get_cached_index();
+        response.indexing = self.is_indexing();
+        response.ready = self.is_ready();
+        response
+    }
+
+    fn get_cached_index(&self) -> FileIndexResponse {
         {
             let cache = self.cached_index.read().unwrap();
             if let Some(ref cached) = *cache {
@@ -124,12 +381,14 @@ impl FileIndex {
         }
         
         for list in by_date.values_mut() {
-            list.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+            list.sort_by_key(|f| std::cmp::Reverse(f.created_at));
         }
 
         let response = FileIndexResponse {
             by_date,
             last_scan,
+            indexing: false,
+            ready: false,
         };
         
         {
@@ -145,46 +404,105 @@ impl FileIndex {
         
         let root = Path::new(DATA_ROOT);
         if let Ok(meta) = path.metadata() {
-            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let name = encode_os_str(path.file_name().unwrap_or_default());
+            if is_sidecar(&name) {
+                return;
+            }
             let size = meta.len();
             let created: DateTime<Utc> = meta.created().unwrap_or(SystemTime::now()).into();
             let relative_path = path.strip_prefix(root).unwrap_or(path);
             let date_folder = relative_path.components().next()
-                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .map(|c| encode_os_str(c.as_os_str()))
                 .unwrap_or_default();
 
             let item = FileItem {
-                path: path.to_string_lossy().to_string(),
+                path: encode_os_str(path.as_os_str()),
                 name,
                 size,
                 created_at: created,
                 date_folder,
+                cold: false,
             };
 
             {
                 let mut w = self.files.write().unwrap();
-                w.push(item);
+                if let Some(changes) = self.scan_changes.lock().unwrap().as_mut() {
+                    changes.removed.retain(|p| p != &item.path);
+                    changes.added.push(item.clone());
+                }
+                upsert(&mut w, item);
             }
             
-            {
-                let mut cache = self.cached_index.write().unwrap();
-                *cache = None;
-            }
+            self.invalidate();
         }
     }
 
     pub fn remove_file(&self, path_str: &str) {
         {
             let mut w = self.files.write().unwrap();
-            if let Some(pos) = w.iter().position(|x| x.path == path_str) {
-                w.remove(pos);
+            if let Some(changes) = self.scan_changes.lock().unwrap().as_mut() {
+                changes.added.retain(|f| f.path != path_str);
+                changes.removed.push(path_str.to_string());
             }
+            w.retain(|x| x.path != path_str);
         }
         
+        self.invalidate();
+    }
+
+    pub fn mark_cold(&self, path_str: &str) {
         {
-            let mut cache = self.cached_index.write().unwrap();
-            *cache = None;
+            let mut w = self.files.write().unwrap();
+            let Some(item) = w.iter_mut().find(|f| f.path == path_str) else {
+                return;
+            };
+            item.cold = true;
+            if let Some(changes) = self.scan_changes.lock().unwrap().as_mut() {
+                changes.added.retain(|f| f.path != path_str);
+                changes.added.push(item.clone());
+            }
         }
+        self.invalidate();
+    }
+
+    // Total size and file count under the data root, from the index rather
+    // than a fresh walk. `None` until the first build has finished.
+    pub fn disk_usage(&self) -> Option<(u64, usize)> {
+        if !self.is_ready() {
+            return None;
+        }
+        let files = self.files.read().unwrap();
+        let (bytes, count) = *self.unindexed.read().unwrap();
+        Some((files.iter().map(|f| f.size).sum::<u64>() + bytes, files.len() + count))
+    }
+
+    pub fn contains_name(&self, name: &str) -> bool {
+        self.files.read().unwrap().iter().any(|f| f.name == name)
+    }
+
+    pub fn all_files(&self) -> Vec<FileItem> {
+        self.files.read().unwrap().clone()
+    }
+
+    // One page of the index in the given order, and the total file count.
+    // Ties are broken by path so pages don't overlap or skip files.
+    pub fn page(&self, sort: FileSort, order: SortOrder, offset: usize, limit: usize) -> (Vec<FileItem>, usize) {
+        let files = self.files.read().unwrap();
+        let mut sorted: Vec<&FileItem> = files.iter().collect();
+        sorted.sort_by(|a, b| {
+            let ord = match sort {
+                FileSort::Date => a.created_at.cmp(&b.created_at),
+                FileSort::Size => a.size.cmp(&b.size),
+                FileSort::Name => a.name.chars().flat_map(char::to_lowercase).cmp(b.name.chars().flat_map(char::to_lowercase)),
+            }
+            .then_with(|| a.path.cmp(&b.path));
+            match order {
+                SortOrder::Asc => ord,
+                SortOrder::Desc => ord.reverse(),
+            }
+        });
+        let items = sorted.into_iter().skip(offset).take(limit).cloned().collect();
+        (items, files.len())
     }
 
     pub fn count_files_after(&self, timestamp: DateTime<Utc>) -> usize {
@@ -193,9 +511,219 @@ impl FileIndex {
     }
 }
 
+// Indexes every file under `root` by its `data/...` path. Files on the cold
+// tier are dated by modification time, which tiering carries over, since
+// their creation time is when they were moved.
+fn walk_files(root: &Path, cold: bool, res: &mut Vec<FileItem>, unindexed: &mut (u64, usize), errors: &mut Vec<String>) {
+    let paths: Vec<PathBuf> = WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| ![crate::upload::UPLOADS_DIR, crate::queue::REDOWNLOAD_DIR, crate::zip_job::ZIPS_DIR].iter().any(|dir| e.path() == Path::new(dir)))
+        .filter_map(|e| e.map_err(|e| errors.push(e.to_string())).ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+
+    let threads = index_threads().min(paths.len());
+    if threads <= 1 {
+        index_paths(root, cold, &paths, res, unindexed, errors);
+        return;
+    }
+    let parts: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(paths.len().div_ceil(threads))
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let mut part = (Vec::new(), (0, 0), Vec::new());
+                    index_paths(root, cold, chunk, &mut part.0, &mut part.1, &mut part.2);
+                    part
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+    for (items, (bytes, count), part_errors) in parts {
+        res.extend(items);
+        unindexed.0 += bytes;
+        unindexed.1 += count;
+        errors.extend(part_errors);
+    }
+}
+
+fn index_paths(root: &Path, cold: bool, paths: &[PathBuf], res: &mut Vec<FileItem>, unindexed: &mut (u64, usize), errors: &mut Vec<String>) {
+    for path in paths {
+        let name = encode_os_str(path.file_name().unwrap_or_default());
+
+        if name.contains("jobs.sqlite") {
+            continue;
+        }
+        if path.ends_with(".archive") || is_sidecar(&name) {
+            unindexed.0 += path.metadata().map(|m| m.len()).unwrap_or(0);
+            unindexed.1 += 1;
+            continue;
+        }
+
+        let meta = match path.metadata() {
+            Ok(meta) => meta,
+            Err(e) => {
+                errors.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+        };
+        let size = meta.len();
+        let created = if cold { meta.modified() } else { meta.created() };
+        let created: DateTime<Utc> = created.unwrap_or(SystemTime::now()).into();
+
+        let relative_path = path.strip_prefix(root).unwrap_or(path);
+        let date_folder = relative_path.components().next()
+            .map(|c| encode_os_str(c.as_os_str()))
+            .unwrap_or_default();
+
+        res.push(FileItem {
+            path: encode_os_str(Path::new(DATA_ROOT).join(relative_path).as_os_str()),
+            name,
+            size,
+            created_at: created,
+            date_folder,
+            cold,
+        });
+    }
+}
+
+fn upsert(files: &mut Vec<FileItem>, item: FileItem) {
+    match files.iter_mut().find(|f| f.path == item.path) {
+        Some(existing) => *existing = item,
+        None => files.push(item),
+    }
+}
+
+// A missing data root usually means an unmounted volume rather than an empty
+// library, so scans, usage and sync refuse to run instead of reporting (and
+// acting on) zero files.
+pub fn data_root_available() -> bool {
+    Path::new(DATA_ROOT).is_dir()
+}
+
+pub fn check_data_root_writable() -> Result<()> {
+    let root = Path::new(DATA_ROOT);
+    std::fs::create_dir_all(root).map_err(|e| {
+        anyhow::anyhow!("Cannot create data directory '{}': {}. Check that the parent directory is writable by the user running the server.", DATA_ROOT, e)
+    })?;
+
+    let probe = root.join(".write_test");
+    std::fs::write(&probe, b"ok").map_err(|e| {
+        anyhow::anyhow!("Data directory '{}' is not writable: {}. It is probably owned by another user (e.g. root from an earlier run); fix it with `chown -R $(whoami) {}` or start the server as the owning user.", DATA_ROOT, e, DATA_ROOT)
+    })?;
+    let _ = std::fs::remove_file(&probe);
+
+    Ok(())
+}
+
+// Stored paths and names are strings. Bytes of a file or folder name that
+// are not valid UTF-8 (files copied in from other systems; yt-dlp itself
+// always writes UTF-8) are kept as `%XX` so the path survives the round trip,
+// and `stored_to_path` turns them back into the bytes on disk. Files are
+// never renamed.
+pub fn encode_os_str(s: &OsStr) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        encode_bytes(s.as_bytes())
+    }
+    #[cfg(not(unix))]
+    {
+        s.to_string_lossy().to_string()
+    }
+}
+
+#[cfg(unix)]
+fn encode_bytes(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    let mut out = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        out.push_str(chunk.valid());
+        for b in chunk.invalid() {
+            let _ = write!(out, "%{:02X}", b);
+        }
+    }
+    out
+}
+
+// A `%XX` that is literally part of a valid name is only decoded when the
+// path as written doesn't exist. Only bytes from 0x80 up are ever encoded.
+pub fn stored_to_path(s: &str) -> PathBuf {
+    let path = PathBuf::from(s);
+    if !s.contains('%') || path.exists() {
+        return path;
+    }
+    decode_stored(s).unwrap_or(path)
+}
+
+#[cfg(unix)]
+fn decode_stored(s: &str) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut decoded = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .filter(|b| *b >= 0x80);
+        match byte {
+            Some(b) => {
+                out.push(b);
+                decoded = true;
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded.then(|| PathBuf::from(std::ffi::OsString::from_vec(out)))
+}
+
+#[cfg(not(unix))]
+fn decode_stored(_s: &str) -> Option<PathBuf> {
+    None
+}
+
+// Never replaces an existing `to`, which fails with `AlreadyExists`: a hard
+// link claims the new name atomically, and where that can't work (another
+// filesystem, or one without links) the copy is created with `create_new`.
+pub async fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    match tokio::fs::hard_link(from, to).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Err(e.into()),
+        Err(_) => {
+            let mut src = tokio::fs::File::open(from).await?;
+            let mut dst = tokio::fs::OpenOptions::new().write(true).create_new(true).open(to).await?;
+            let copied = async {
+                tokio::io::copy(&mut src, &mut dst).await?;
+                dst.sync_all().await
+            };
+            if let Err(e) = copied.await {
+                let _ = tokio::fs::remove_file(to).await;
+                return Err(e.into());
+            }
+        }
+    }
+    tokio::fs::remove_file(from).await?;
+    Ok(())
+}
+
 pub fn get_today_folder() -> PathBuf {
-    let now = Local::now();
-    let folder_name = now.format("%Y-%m-%d").to_string();
+    let folder_name = date_folder_name(Utc::now());
     let path = Path::new(DATA_ROOT).join(folder_name);
     if !path.exists() {
         let _ = std::fs::create_dir_all(&path);
@@ -203,10 +731,64 @@ pub fn get_today_folder() -> PathBuf {
     path
 }
 
+// Metadata yt-dlp writes next to a video, named like the video with its
+// extension replaced. These are served through `/api/files/description`
+// rather than listed as media. Contact sheets cached by older versions were
+// stored the same way.
+pub const DESCRIPTION_EXT: &str = "description";
+pub const INFO_JSON_EXT: &str = "info.json";
+pub const CONTACT_SHEET_EXT: &str = "contact.jpg";
+
+pub fn is_sidecar(name: &str) -> bool {
+    [DESCRIPTION_EXT, INFO_JSON_EXT, CONTACT_SHEET_EXT].iter().any(|ext| name.ends_with(&format!(".{}", ext)))
+}
+
+pub fn sidecar_paths(media: &Path) -> [PathBuf; 3] {
+    [media.with_extension(DESCRIPTION_EXT), media.with_extension(INFO_JSON_EXT), media.with_extension(CONTACT_SHEET_EXT)]
+}
+
+// The index stores paths as walked from DATA_ROOT (`data/...`), not canonical
+// absolute paths, so convert before touching it. Files on the cold tier map
+// to the same `data/...` path.
+pub fn index_path(abs_path: &Path, data_root: &Path) -> PathBuf {
+    let cold = cold_root().and_then(|root| root.canonicalize().ok());
+    let relative = abs_path.strip_prefix(data_root).ok()
+        .or_else(|| cold.as_deref().and_then(|root| abs_path.strip_prefix(root).ok()))
+        .unwrap_or(abs_path);
+    Path::new(DATA_ROOT).join(relative)
+}
+
+// Removes a file, its index entry and sidecars, and its folder once empty.
+pub async fn remove_data_file(file_index: &FileIndex, abs_path: &Path, data_root: &Path) -> std::io::Result<()> {
+    tokio::fs::remove_file(abs_path).await?;
+    file_index.remove_file(&encode_os_str(index_path(abs_path, data_root).as_os_str()));
+    for sidecar in sidecar_paths(abs_path) {
+        let _ = tokio::fs::remove_file(sidecar).await;
+    }
+
+    if let Some(parent) = abs_path.parent() {
+        if parent.starts_with(data_root) && parent != data_root {
+            let _ = tokio::fs::remove_dir(parent).await;
+        }
+    }
+    Ok(())
+}
+
+pub fn available_space() -> Result<u64> {
+    Ok(fs4::available_space(DATA_ROOT)?)
+}
+
+pub fn total_space() -> Result<u64> {
+    Ok(fs4::total_space(DATA_ROOT)?)
+}
+
 pub async fn get_disk_usage() -> Result<(u64, usize)> {
+    if !data_root_available() {
+        return Err(anyhow::anyhow!("Data root '{}' is unavailable", DATA_ROOT));
+    }
     let root = Path::new(DATA_ROOT);
-    if !root.exists() { return Ok((0, 0)); }
     
+    let _permit = walk_permit().await;
     let root_path = root.to_path_buf();
     let result = tokio::task::spawn_blocking(move || {
         let mut total_size = 0;
@@ -225,4 +807,119 @@ pub async fn get_disk_usage() -> Result<(u64, usize)> {
     }).await?;
     
     Ok(result)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn temp_root() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("tiak-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn non_utf8_names_round_trip_through_the_index() {
+        let root = temp_root();
+        let folder = root.join(OsStr::from_bytes(b"caf\xe9"));
+        std::fs::create_dir_all(&folder).unwrap();
+        let file = folder.join(OsStr::from_bytes(b"clip \xff\xfe.mp4"));
+        std::fs::write(&file, b"video").unwrap();
+
+        let mut items = Vec::new();
+        index_paths(&root, false, std::slice::from_ref(&file), &mut items, &mut (0, 0), &mut Vec::new());
+
+        assert_eq!(items.len(), 1);
+        let item = &items[0];
+        assert_eq!(item.path, "data/caf%E9/clip %FF%FE.mp4");
+        assert_eq!(item.name, "clip %FF%FE.mp4");
+        assert_eq!(item.date_folder, "caf%E9");
+        // The file is left as it is on disk.
+        assert!(file.exists());
+
+        let stored = item.path.replacen(DATA_ROOT, &root.to_string_lossy(), 1);
+        assert_eq!(stored_to_path(&stored), file);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn non_utf8_names_are_deleted_with_their_index_entry() {
+        let root = temp_root();
+        let folder = root.join(OsStr::from_bytes(b"caf\xe9"));
+        std::fs::create_dir_all(&folder).unwrap();
+        let file = folder.join(OsStr::from_bytes(b"clip \xff.mp4"));
+        std::fs::write(&file, b"video").unwrap();
+        let index = FileIndex::new();
+        let mut items = Vec::new();
+        index.begin_scan();
+        index_paths(&root, false, std::slice::from_ref(&file), &mut items, &mut (0, 0), &mut Vec::new());
+        index.finish_scan(items, (0, 0));
+        assert_eq!(index.all_files()[0].path, "data/caf%E9/clip %FF.mp4");
+
+        remove_data_file(&index, &file, &root).await.unwrap();
+
+        assert!(!file.exists());
+        assert!(index.all_files().is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn literal_percent_names_are_left_alone() {
+        let root = temp_root();
+        let file = root.join("100%FF.mp4");
+        std::fs::write(&file, b"x").unwrap();
+
+        let stored = encode_os_str(file.as_os_str());
+        assert_eq!(stored_to_path(&stored), file);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn a_download_finishing_during_a_rebuild_is_kept_after_the_swap() {
+        let root = temp_root();
+        let seen = root.join("seen.mp4");
+        let missed = root.join("missed.mp4");
+        std::fs::write(&seen, b"a").unwrap();
+        std::fs::write(&missed, b"b").unwrap();
+
+        let index = FileIndex::new();
+        index.begin_scan();
+        // Both downloads finish while the walk runs; the walk only reaches
+        // `seen`, `missed` lands in a folder it has already passed.
+        index.add_file(&seen);
+        index.add_file(&missed);
+        let walk = FileIndex::new();
+        walk.add_file(&seen);
+        index.finish_scan(walk.all_files(), (0, 0));
+
+        let files = index.all_files();
+        for file in [&seen, &missed] {
+            let name = file.file_name().unwrap().to_string_lossy();
+            assert_eq!(files.iter().filter(|f| f.name == name).count(), 1, "{}", name);
+        }
+        assert!(index.scan_changes.lock().unwrap().is_none());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn move_file_never_overwrites() {
+        let root = temp_root();
+        let from = root.join("a.mp4");
+        let to = root.join("sub/b.mp4");
+        std::fs::write(&from, b"a").unwrap();
+        std::fs::create_dir_all(to.parent().unwrap()).unwrap();
+        std::fs::write(&to, b"b").unwrap();
+
+        let e = move_file(&from, &to).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<std::io::Error>().unwrap().kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(&from).unwrap(), b"a");
+        assert_eq!(std::fs::read(&to).unwrap(), b"b");
+
+        std::fs::remove_file(&to).unwrap();
+        move_file(&from, &to).await.unwrap();
+        assert!(!from.exists());
+        assert_eq!(std::fs::read(&to).unwrap(), b"a");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+use serde::Serialize;
+use std::env;
+use std::path::Path;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, utoipa::ToSchema)]
+pub struct Capabilities {
+    #[serde(rename = "ytDlp")]
+    pub yt_dlp: bool,
+    pub ffmpeg: bool,
+    pub ffprobe: bool,
+    pub rclone: bool,
+    pub curl: bool,
+}
+
+impl Capabilities {
+    pub fn probe() -> Self {
+        let caps = Self {
+            yt_dlp: Path::new("venv_python/bin/python").is_file() && Path::new("bin/yt-dlp").is_file(),
+            ffmpeg: find_in_path("ffmpeg"),
+            ffprobe: find_in_path("ffprobe"),
+            rclone: find_in_path("rclone"),
+            curl: find_in_path("curl"),
+        };
+
+        for (name, present) in [("yt-dlp", caps.yt_dlp), ("ffmpeg", caps.ffmpeg), ("ffprobe", caps.ffprobe), ("rclone", caps.rclone), ("curl", caps.curl)] {
+            if present {
+                info!("Found external tool: {}", name);
+            } else {
+                warn!("External tool not found: {}", name);
+            }
+        }
+
+        caps
+    }
+}
+
+pub fn find_in_path(name: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(name).is_file()))
+        .unwrap_or(false)
+}
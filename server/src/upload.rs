@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+// Partial uploads live under the data root so they survive restarts; the
+// index, sync and WebDAV all skip this hidden folder.
+pub const UPLOADS_DIR: &str = "data/.uploads";
+pub const MAX_UPLOAD_BYTES: u64 = 1024 * 1024 * 1024;
+const UPLOAD_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Appends are check-then-write on the current size, so they are serialized.
+static APPEND_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[derive(Debug)]
+pub enum UploadError {
+    NotFound,
+    OffsetMismatch(u64),
+    TooLarge,
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadError::NotFound => write!(f, "Upload not found"),
+            UploadError::OffsetMismatch(offset) => write!(f, "Upload-Offset does not match, upload is at {}", offset),
+            UploadError::TooLarge => write!(f, "Upload exceeds {} bytes", MAX_UPLOAD_BYTES),
+            UploadError::Io(e) => write!(f, "Upload failed: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for UploadError {
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            UploadError::NotFound
+        } else {
+            UploadError::Io(e)
+        }
+    }
+}
+
+fn upload_path(id: &Uuid) -> PathBuf {
+    Path::new(UPLOADS_DIR).join(format!("{}.part", id))
+}
+
+pub async fn create() -> Result<Uuid, UploadError> {
+    tokio::fs::create_dir_all(UPLOADS_DIR).await?;
+    let id = Uuid::new_v4();
+    tokio::fs::File::create(upload_path(&id)).await?;
+    Ok(id)
+}
+
+pub async fn offset(id: &Uuid) -> Result<u64, UploadError> {
+    Ok(tokio::fs::metadata(upload_path(id)).await?.len())
+}
+
+// Returns the new offset. A chunk for any other offset than the current size
+// is refused, so a client that lost a response can ask for the offset and
+// resend from there.
+pub async fn append(id: &Uuid, at: u64, chunk: &[u8]) -> Result<u64, UploadError> {
+    let _guard = APPEND_LOCK.lock().await;
+    let current = offset(id).await?;
+    if at != current {
+        return Err(UploadError::OffsetMismatch(current));
+    }
+    if current + chunk.len() as u64 > MAX_UPLOAD_BYTES {
+        return Err(UploadError::TooLarge);
+    }
+    let mut file = tokio::fs::OpenOptions::new().append(true).open(upload_path(id)).await?;
+    file.write_all(chunk).await?;
+    file.flush().await?;
+    Ok(current + chunk.len() as u64)
+}
+
+pub async fn take(id: &Uuid) -> Result<Vec<u8>, UploadError> {
+    let path = upload_path(id);
+    let bytes = tokio::fs::read(&path).await?;
+    tokio::fs::remove_file(&path).await?;
+    Ok(bytes)
+}
+
+pub async fn remove(id: &Uuid) -> Result<(), UploadError> {
+    tokio::fs::remove_file(upload_path(id)).await?;
+    Ok(())
+}
+
+pub async fn remove_stale() -> usize {
+    let mut removed = 0;
+    let Ok(mut entries) = tokio::fs::read_dir(UPLOADS_DIR).await else {
+        return 0;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let stale = entry.metadata().await
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > UPLOAD_TTL);
+        if stale && tokio::fs::remove_file(entry.path()).await.is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
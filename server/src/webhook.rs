@@ -0,0 +1,56 @@
+use crate::db::Job;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::warn;
+
+const MAX_WEBHOOK_URL_LEN: usize = 2048;
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+pub fn validate_webhook_url(url: &str) -> Result<(), String> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err(format!("Invalid webhook URL '{}', expected an http(s) URL", url));
+    }
+    if url.len() > MAX_WEBHOOK_URL_LEN || url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err("Invalid webhook URL".to_string());
+    }
+    Ok(())
+}
+
+// POSTs `{ "event": "job.finished", "job": {...} }`. The body goes through
+// stdin so job data never shows up in the process list.
+pub async fn send(url: &str, job: Job) {
+    let body = serde_json::json!({ "event": "job.finished", "job": &job }).to_string();
+    let child = Command::new("curl")
+        .arg("-sS")
+        .arg("-f")
+        .arg("-o")
+        .arg("/dev/null")
+        .arg("-m")
+        .arg(WEBHOOK_TIMEOUT_SECS.to_string())
+        .arg("-H")
+        .arg("Content-Type: application/json")
+        .arg("--data-binary")
+        .arg("@-")
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to start curl for the webhook of job {}: {}", job.id, e);
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(body.as_bytes()).await;
+    }
+    match child.wait_with_output().await {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => warn!("Webhook for job {} failed: {}", job.id, String::from_utf8_lossy(&output.stderr).trim()),
+        Err(e) => warn!("Webhook for job {} failed: {}", job.id, e),
+    }
+}
@@ -0,0 +1,301 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+
+// Each job writes `<id>/<name>` here, as `<name>.part` until it is complete,
+// and the finished archive is kept for ZIP_TTL so it can be downloaded (and
+// resumed) with range requests. The index, sync and WebDAV all skip this
+// hidden folder.
+pub const ZIPS_DIR: &str = "data/.zips";
+const ZIP_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Archives are built one at a time; each reads every file it contains.
+static ZIP_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+static JOBS: LazyLock<DashMap<Uuid, Arc<ZipJob>>> = LazyLock::new(DashMap::new);
+// Held while a new job is checked against the limits and added, so two
+// requests can't both take the last slot or the same free space.
+static START_LOCK: Mutex<()> = Mutex::new(());
+// Queued and running archives at once; more are refused until one ends.
+pub const MAX_LIVE_JOBS: usize = 4;
+
+#[derive(Debug)]
+pub enum StartError {
+    TooMany,
+    NoSpace { needed: u64, free: u64 },
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartError::TooMany => write!(f, "{} zip jobs are already queued or running", MAX_LIVE_JOBS),
+            StartError::NoSpace { needed, free } => write!(f, "The archive needs about {} bytes but only {} are free", needed, free),
+            StartError::Io(e) => write!(f, "Failed to check free space: {}", e),
+        }
+    }
+}
+
+struct ZipJob {
+    name: String,
+    files_total: usize,
+    // Summed when the job is added; also the estimated archive size, as
+    // media barely compresses.
+    bytes_total: AtomicU64,
+    files_done: AtomicUsize,
+    bytes_done: AtomicU64,
+    created_at: i64,
+    state: Mutex<ZipState>,
+}
+
+#[derive(Clone)]
+enum ZipState {
+    Queued,
+    Running,
+    Done { size: u64, finished_at: i64 },
+    Failed(String),
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ZipJobStatus {
+    pub id: String,
+    // `queued`, `running`, `done` or `failed`.
+    pub status: &'static str,
+    pub name: String,
+    // Counts are unknown for archives finished before a restart.
+    #[serde(rename = "filesTotal")]
+    pub files_total: Option<usize>,
+    #[serde(rename = "filesDone")]
+    pub files_done: Option<usize>,
+    #[serde(rename = "bytesTotal")]
+    pub bytes_total: Option<u64>,
+    #[serde(rename = "bytesDone")]
+    pub bytes_done: Option<u64>,
+    pub progress: i64,
+    // Size of the finished archive.
+    pub size: Option<u64>,
+    pub error: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<i64>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<i64>,
+}
+
+fn job_dir(id: &Uuid) -> PathBuf {
+    Path::new(ZIPS_DIR).join(id.to_string())
+}
+
+fn part_name(name: &str) -> String {
+    format!("{}.part", name)
+}
+
+fn millis(at: SystemTime) -> i64 {
+    at.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as i64
+}
+
+// Writes `entries` (file, name in the archive) to `writer`, calling
+// `on_progress` with the number of finished files and the bytes just read.
+pub fn write_entries<W: Write + Seek>(
+    writer: W,
+    entries: &[(PathBuf, String)],
+    mut on_progress: impl FnMut(usize, u64),
+) -> Result<W, anyhow::Error> {
+    let mut zip = zip::ZipWriter::new(writer);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated).large_file(true);
+    let mut buf = vec![0; 64 * 1024];
+    for (i, (path, name)) in entries.iter().enumerate() {
+        let mut src = std::fs::File::open(path)?;
+        zip.start_file(name.as_str(), options)?;
+        loop {
+            let n = src.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            zip.write_all(&buf[..n])?;
+            on_progress(i, n as u64);
+        }
+        on_progress(i + 1, 0);
+    }
+    Ok(zip.finish()?)
+}
+
+fn is_live(job: &ZipJob) -> bool {
+    matches!(*job.state.lock().unwrap(), ZipState::Queued | ZipState::Running)
+}
+
+// Queues an archive of `entries` to be written as `name` and returns its id.
+// Refused when too many archives are pending or when the free space, less
+// what pending archives still have to write, can't hold it.
+pub async fn start(entries: Vec<(PathBuf, String)>, name: String) -> Result<Uuid, StartError> {
+    let (entries, total) = tokio::task::spawn_blocking(move || {
+        let total: u64 = entries.iter().filter_map(|(path, _)| std::fs::metadata(path).ok()).map(|m| m.len()).sum();
+        (entries, total)
+    })
+    .await
+    .map_err(|e| StartError::Io(std::io::Error::other(e)))?;
+
+    let id = Uuid::new_v4();
+    let job = Arc::new(ZipJob {
+        name,
+        files_total: entries.len(),
+        bytes_total: AtomicU64::new(total),
+        files_done: AtomicUsize::new(0),
+        bytes_done: AtomicU64::new(0),
+        created_at: millis(SystemTime::now()),
+        state: Mutex::new(ZipState::Queued),
+    });
+    {
+        let _start = START_LOCK.lock().unwrap();
+        let live: Vec<Arc<ZipJob>> = JOBS.iter().map(|j| j.value().clone()).filter(|j| is_live(j)).collect();
+        if live.len() >= MAX_LIVE_JOBS {
+            return Err(StartError::TooMany);
+        }
+        let pending: u64 = live.iter()
+            .map(|j| j.bytes_total.load(Ordering::Relaxed).saturating_sub(j.bytes_done.load(Ordering::Relaxed)))
+            .sum();
+        let free = crate::storage::available_space().map_err(|e| StartError::Io(std::io::Error::other(e)))?.saturating_sub(pending);
+        if free < total {
+            return Err(StartError::NoSpace { needed: total, free });
+        }
+        JOBS.insert(id, job.clone());
+    }
+    tokio::spawn(async move {
+        let _guard = ZIP_LOCK.lock().await;
+        *job.state.lock().unwrap() = ZipState::Running;
+        let dir = job_dir(&id);
+        let worker = job.clone();
+        let result = tokio::task::spawn_blocking(move || -> Result<u64, anyhow::Error> {
+            std::fs::create_dir_all(&dir)?;
+            let part = dir.join(part_name(&worker.name));
+            let file = std::fs::File::create(&part)?;
+            let file = write_entries(file, &entries, |files, bytes| {
+                worker.files_done.store(files, Ordering::Relaxed);
+                worker.bytes_done.fetch_add(bytes, Ordering::Relaxed);
+            })?;
+            file.sync_all()?;
+            let size = file.metadata()?.len();
+            std::fs::rename(&part, dir.join(&worker.name))?;
+            Ok(size)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|r| r);
+        let state = match result {
+            Ok(size) => {
+                info!("Zip job {}: wrote {} ({} bytes)", id, job.name, size);
+                ZipState::Done { size, finished_at: millis(SystemTime::now()) }
+            }
+            Err(e) => {
+                warn!("Zip job {} failed: {}", id, e);
+                let _ = tokio::fs::remove_dir_all(job_dir(&id)).await;
+                ZipState::Failed(e.to_string())
+            }
+        };
+        *job.state.lock().unwrap() = state;
+        drop(_guard);
+        // Deleted while it was being written.
+        if !JOBS.contains_key(&id) {
+            let _ = tokio::fs::remove_dir_all(job_dir(&id)).await;
+            return;
+        }
+
+        tokio::time::sleep(ZIP_TTL).await;
+        if JOBS.remove(&id).is_some() {
+            let _ = tokio::fs::remove_dir_all(job_dir(&id)).await;
+        }
+    });
+    Ok(id)
+}
+
+// The finished archive of a job that has one.
+async fn finished_file(id: &Uuid) -> Option<(PathBuf, std::fs::Metadata)> {
+    let mut entries = tokio::fs::read_dir(job_dir(id)).await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let meta = entry.metadata().await.ok()?;
+        if meta.is_file() && !entry.file_name().to_string_lossy().ends_with(".part") {
+            return Some((entry.path(), meta));
+        }
+    }
+    None
+}
+
+pub async fn status(id: &Uuid) -> Option<ZipJobStatus> {
+    if let Some(job) = JOBS.get(id).map(|j| j.clone()) {
+        let state = job.state.lock().unwrap().clone();
+        let bytes_done = job.bytes_done.load(Ordering::Relaxed);
+        let bytes_total = job.bytes_total.load(Ordering::Relaxed);
+        let (status, progress, size, error, finished_at) = match state {
+            ZipState::Queued => ("queued", 0, None, None, None),
+            ZipState::Running => ("running", (bytes_done * 100).checked_div(bytes_total).unwrap_or(0).min(99) as i64, None, None, None),
+            ZipState::Done { size, finished_at } => ("done", 100, Some(size), None, Some(finished_at)),
+            ZipState::Failed(e) => ("failed", 0, None, Some(e), None),
+        };
+        return Some(ZipJobStatus {
+            id: id.to_string(),
+            status,
+            name: job.name.clone(),
+            files_total: Some(job.files_total),
+            files_done: Some(job.files_done.load(Ordering::Relaxed)),
+            bytes_total: Some(bytes_total),
+            bytes_done: Some(bytes_done),
+            progress,
+            size,
+            error,
+            created_at: Some(job.created_at),
+            expires_at: finished_at.map(|at| at + ZIP_TTL.as_millis() as i64),
+        });
+    }
+    let (path, meta) = finished_file(id).await?;
+    Some(ZipJobStatus {
+        id: id.to_string(),
+        status: "done",
+        name: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        files_total: None,
+        files_done: None,
+        bytes_total: None,
+        bytes_done: None,
+        progress: 100,
+        size: Some(meta.len()),
+        error: None,
+        created_at: None,
+        expires_at: meta.modified().ok().map(|at| millis(at) + ZIP_TTL.as_millis() as i64),
+    })
+}
+
+pub async fn archive_path(id: &Uuid) -> Option<PathBuf> {
+    finished_file(id).await.map(|(path, _)| path)
+}
+
+// `false` when the job is unknown. A job still being written is forgotten
+// and its files are removed once it finishes.
+pub async fn remove(id: &Uuid) -> bool {
+    let known = JOBS.remove(id).is_some();
+    let removed = tokio::fs::remove_dir_all(job_dir(id)).await.is_ok();
+    known || removed
+}
+
+// Removes archives left over from before a restart once they expire.
+pub async fn remove_stale() -> usize {
+    let mut removed = 0;
+    let Ok(mut entries) = tokio::fs::read_dir(ZIPS_DIR).await else {
+        return 0;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let tracked = Uuid::parse_str(&entry.file_name().to_string_lossy()).is_ok_and(|id| JOBS.contains_key(&id));
+        let stale = entry.metadata().await
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > ZIP_TTL);
+        if !tracked && stale && tokio::fs::remove_dir_all(entry.path()).await.is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}